@@ -1,11 +1,32 @@
+use bevy::{color::Color, math::IVec3, reflect::Reflect};
+
+pub use rotation::{Axis, BlockRotation};
+pub use shape::{BlockShape, ShapeBox};
+
+mod rotation;
+pub(crate) mod shape;
+
 /// BlockMeta holds info about a block that is used when generating meshes & coliders;
+/// blocks that are not solid, opaque or shaped are not meshed, see `BlockMeta::is_meshed`
 /// bit 0: solid
 /// bit 1: opaque
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub struct BlockMeta(pub(crate) u8);
+/// bit 2..=7: the face in `BlockFace` order is a full square
+/// bit 8: the block is not a full cube and is meshed from its `BlockShape`
+/// bit 9: the blocks state is sent to the shader to pick its texture
+/// bit 10: the block has a `BlockEntity` spawned for it
+/// bit 11: the block is tinted by the biome tint of its column
+/// bit 12: the block is tinted by its state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub struct BlockMeta(pub(crate) u16);
 
 impl BlockMeta {
     pub const EMPTY: BlockMeta = BlockMeta(0);
+    const FACES_OFFSET: u16 = 2;
+    const SHAPED: u16 = 0b01_0000_0000;
+    const TEXTURED_BY_STATE: u16 = 0b10_0000_0000;
+    const HAS_BLOCK_ENTITY: u16 = 0b100_0000_0000;
+    const TINT_BY_BIOME: u16 = 0b1000_0000_0000;
+    const TINT_BY_STATE: u16 = 0b1_0000_0000_0000;
 }
 
 impl BlockMeta {
@@ -16,6 +37,48 @@ impl BlockMeta {
     pub fn is_transparent(&self) -> bool {
         self.0 & 0b0000_0010 == 0
     }
+
+    /// Is the block meshed at all, blocks that are not solid, opaque or shaped are skipped
+    /// even if they have a `BlockEntity`
+    pub fn is_meshed(&self) -> bool {
+        self.0 & (0b0000_0011 | Self::SHAPED) != 0
+    }
+
+    /// Is the block meshed from its `BlockShape` rather then as a cube
+    pub fn is_shaped(&self) -> bool {
+        self.0 & Self::SHAPED != 0
+    }
+
+    /// Does the state of the block change its texture
+    pub fn textured_by_state(&self) -> bool {
+        self.0 & Self::TEXTURED_BY_STATE != 0
+    }
+
+    /// Does the block get a `BlockEntity` spawned for it
+    pub fn has_block_entity(&self) -> bool {
+        self.0 & Self::HAS_BLOCK_ENTITY != 0
+    }
+
+    /// Is the block tinted by the biome tint of its column
+    pub fn tinted_by_biome(&self) -> bool {
+        self.0 & Self::TINT_BY_BIOME != 0
+    }
+
+    /// Is the block tinted by its state
+    pub fn tinted_by_state(&self) -> bool {
+        self.0 & Self::TINT_BY_STATE != 0
+    }
+
+    /// Does the block fill the whole of the given face
+    pub fn covers(&self, face: BlockFace) -> bool {
+        self.0 & (1 << (Self::FACES_OFFSET + face as u16)) != 0
+    }
+
+    /// Does the block hide the face of a neighbour that is touching the given face
+    #[inline(always)]
+    pub fn occludes(&self, face: BlockFace) -> bool {
+        !self.is_transparent() && self.covers(face)
+    }
 }
 
 impl<T: Block> From<T> for BlockMeta {
@@ -27,6 +90,33 @@ impl<T: Block> From<T> for BlockMeta {
         if !block.is_transparent() {
             meta.0 |= 0b0000_0010; // Set opaque bit
         }
+        let shape = block.shape();
+        if meta == BlockMeta::EMPTY && shape == BlockShape::Cube {
+            // cubes that are neither solid or opaque are never meshed
+            if block.has_block_entity() {
+                meta.0 |= BlockMeta::HAS_BLOCK_ENTITY;
+            }
+            return meta;
+        }
+        for face in BlockFace::ALL {
+            if shape.covers(face) {
+                meta.0 |= 1 << (BlockMeta::FACES_OFFSET + face as u16);
+            }
+        }
+        if shape != BlockShape::Cube {
+            meta.0 |= BlockMeta::SHAPED;
+        }
+        if block.textured_by_state() {
+            meta.0 |= BlockMeta::TEXTURED_BY_STATE;
+        }
+        if block.has_block_entity() {
+            meta.0 |= BlockMeta::HAS_BLOCK_ENTITY;
+        }
+        match block.tint() {
+            BlockTint::Biome => meta.0 |= BlockMeta::TINT_BY_BIOME,
+            BlockTint::State => meta.0 |= BlockMeta::TINT_BY_STATE,
+            BlockTint::None | BlockTint::Constant(_) => {}
+        }
         meta
     }
 }
@@ -35,10 +125,88 @@ pub trait Block: Copy {
     fn is_solid(&self) -> bool;
     fn is_transparent(&self) -> bool;
     fn id(&self) -> u8;
+    /// The shape used when meshing this block, defaults to a full cube
+    fn shape(&self) -> BlockShape {
+        BlockShape::Cube
+    }
+    /// Should the per voxel state of this block be used to pick its texture,
+    /// leave this false for state that is only used by gameplay so chunks are meshed as if it is not there
+    fn textured_by_state(&self) -> bool {
+        false
+    }
+    /// Should an entity be spawned for each of this block in a chunk,
+    /// for chests, signs and machines that need their own components. See `BlockEntity`
+    fn has_block_entity(&self) -> bool {
+        false
+    }
+    /// How the texture of this block is coloured, set the same tint on the `VoxelMaterial`
+    /// with `VoxelMaterial::set_tint` so the shader knows about it
+    fn tint(&self) -> BlockTint {
+        BlockTint::None
+    }
+}
+
+/// How the texture of a block is coloured, for greyscale textures like grass and leaves.
+/// `Biome` and `State` tints pick a colour from `VoxelMaterial::tint_colormap`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BlockTint {
+    #[default]
+    None,
+    /// The same colour everywhere
+    Constant(Color),
+    /// The colour at the biome tint of the column the block is in, see `ChunkData::set_biome_tint`
+    Biome,
+    /// The colour at the state of the block, see `ChunkData::set_state`
+    State,
+}
+
+/// The six faces of a block.
+/// North is -Z to match bevy's forward direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockFace {
+    Up = 0,
+    Down = 1,
+    North = 2,
+    South = 3,
+    East = 4,
+    West = 5,
+}
+
+impl BlockFace {
+    pub const ALL: [BlockFace; 6] = [
+        BlockFace::Up,
+        BlockFace::Down,
+        BlockFace::North,
+        BlockFace::South,
+        BlockFace::East,
+        BlockFace::West,
+    ];
+
+    pub const fn opposite(self) -> BlockFace {
+        match self {
+            BlockFace::Up => BlockFace::Down,
+            BlockFace::Down => BlockFace::Up,
+            BlockFace::North => BlockFace::South,
+            BlockFace::South => BlockFace::North,
+            BlockFace::East => BlockFace::West,
+            BlockFace::West => BlockFace::East,
+        }
+    }
+
+    pub const fn normal(self) -> IVec3 {
+        match self {
+            BlockFace::Up => IVec3::Y,
+            BlockFace::Down => IVec3::NEG_Y,
+            BlockFace::North => IVec3::NEG_Z,
+            BlockFace::South => IVec3::Z,
+            BlockFace::East => IVec3::X,
+            BlockFace::West => IVec3::NEG_X,
+        }
+    }
 }
 
 /// A BlockId is a simple wrapper around a u8 that represents the ID of a block.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
 pub struct BlockId(pub u8);
 
 impl PartialEq<u8> for BlockId {
@@ -53,3 +221,53 @@ fn empty_meta() {
     assert!(air.is_transparent());
     assert!(!air.is_solid());
 }
+
+#[test]
+fn slab_meta() {
+    #[derive(Clone, Copy)]
+    struct Slab;
+    impl Block for Slab {
+        fn id(&self) -> u8 {
+            1
+        }
+        fn is_solid(&self) -> bool {
+            true
+        }
+        fn is_transparent(&self) -> bool {
+            false
+        }
+        fn shape(&self) -> BlockShape {
+            BlockShape::Slab
+        }
+    }
+    let slab = BlockMeta::from(Slab);
+    assert!(slab.is_shaped());
+    assert!(slab.occludes(BlockFace::Down));
+    assert!(!slab.occludes(BlockFace::Up));
+    assert!(!slab.occludes(BlockFace::North));
+}
+
+#[test]
+fn cross_meta() {
+    #[derive(Clone, Copy)]
+    struct Flower;
+    impl Block for Flower {
+        fn id(&self) -> u8 {
+            1
+        }
+        fn is_solid(&self) -> bool {
+            false
+        }
+        fn is_transparent(&self) -> bool {
+            true
+        }
+        fn shape(&self) -> BlockShape {
+            BlockShape::Cross
+        }
+    }
+    let flower = BlockMeta::from(Flower);
+    assert_ne!(flower, BlockMeta::EMPTY);
+    assert!(flower.is_shaped());
+    assert!(!flower.is_solid());
+    assert!(!flower.occludes(BlockFace::Down));
+}
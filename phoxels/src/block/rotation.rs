@@ -0,0 +1,140 @@
+use bevy::{math::IVec3, reflect::Reflect};
+
+use super::{BlockFace, ShapeBox, shape::SHAPE_RESOLUTION};
+
+/// An axis a block can be aligned to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// How a block is rotated in the world.
+/// A block is modeled facing North with its top Up,
+/// `facing` rotates it so its front (North face) points in the given direction,
+/// `axis` rotates it so its top (Up face) points along the given axis; for logs and pillars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+pub struct BlockRotation(pub(crate) u8);
+
+impl BlockRotation {
+    /// The block is not rotated
+    pub const IDENTITY: BlockRotation = BlockRotation(0);
+
+    /// Rotate the block so its front points towards `face`
+    pub const fn facing(face: BlockFace) -> BlockRotation {
+        BlockRotation(match face {
+            BlockFace::North => 0,
+            BlockFace::South => 1,
+            BlockFace::East => 2,
+            BlockFace::West => 3,
+            BlockFace::Up => 4,
+            BlockFace::Down => 5,
+        })
+    }
+
+    /// Rotate the block so its top points along `axis`
+    pub const fn axis(axis: Axis) -> BlockRotation {
+        BlockRotation(match axis {
+            Axis::Y => 0,
+            Axis::Z => 4,
+            Axis::X => 6,
+        })
+    }
+
+    /// Get the face of the world a face of the unrotated block ends up pointing at
+    pub const fn rotate(self, face: BlockFace) -> BlockFace {
+        use BlockFace::*;
+        match (self.0, face) {
+            // facing South; half turn around Y
+            (1, North) => South,
+            (1, South) => North,
+            (1, East) => West,
+            (1, West) => East,
+            // facing East; quarter turn around Y
+            (2, North) => East,
+            (2, East) => South,
+            (2, South) => West,
+            (2, West) => North,
+            // facing West; quarter turn the other way around Y
+            (3, North) => West,
+            (3, West) => South,
+            (3, South) => East,
+            (3, East) => North,
+            // facing Up; quarter turn around X, this is also the Z axis
+            (4, North) => Up,
+            (4, Up) => South,
+            (4, South) => Down,
+            (4, Down) => North,
+            // facing Down; quarter turn the other way around X
+            (5, North) => Down,
+            (5, Down) => South,
+            (5, South) => Up,
+            (5, Up) => North,
+            // X axis; quarter turn around Z
+            (6, Up) => East,
+            (6, East) => Down,
+            (6, Down) => West,
+            (6, West) => Up,
+            (_, face) => face,
+        }
+    }
+
+    /// Get the face of the unrotated block that ends up pointing at a face of the world
+    pub fn unrotate(self, face: BlockFace) -> BlockFace {
+        BlockFace::ALL
+            .into_iter()
+            .find(|f| self.rotate(*f) == face)
+            .expect("rotations are one to one")
+    }
+
+    /// Rotate a box around the center of the block
+    pub fn rotate_box(self, shape_box: ShapeBox) -> ShapeBox {
+        if self == BlockRotation::IDENTITY {
+            return shape_box;
+        }
+        let x = self.rotate(BlockFace::East).normal();
+        let y = self.rotate(BlockFace::Up).normal();
+        let z = self.rotate(BlockFace::South).normal();
+        let half = SHAPE_RESOLUTION as i32 / 2;
+        let rotate = |p: [u8; 3]| {
+            let p = IVec3::from_array(p.map(i32::from)) - half;
+            x * p.x + y * p.y + z * p.z + half
+        };
+        let a = rotate(shape_box.min);
+        let b = rotate(shape_box.max);
+        ShapeBox::new(
+            a.min(b).to_array().map(|v| v as u8),
+            a.max(b).to_array().map(|v| v as u8),
+        )
+    }
+}
+
+#[test]
+fn rotations_are_one_to_one() {
+    let rotations = [
+        BlockFace::North,
+        BlockFace::South,
+        BlockFace::East,
+        BlockFace::West,
+        BlockFace::Up,
+        BlockFace::Down,
+    ]
+    .map(BlockRotation::facing)
+    .into_iter()
+    .chain([Axis::X, Axis::Y, Axis::Z].map(BlockRotation::axis));
+    for rotation in rotations {
+        for face in BlockFace::ALL {
+            let world = rotation.rotate(face);
+            assert_eq!(rotation.unrotate(world), face);
+            assert_eq!(
+                rotation.rotate(face.opposite()),
+                world.opposite(),
+                "{rotation:?} does not keep {face:?} opposite"
+            );
+        }
+    }
+    let slab = ShapeBox::new([0, 0, 0], [16, 8, 16]);
+    let sideways = BlockRotation::axis(Axis::X).rotate_box(slab);
+    assert!(sideways.covers(BlockFace::West));
+}
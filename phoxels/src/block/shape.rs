@@ -0,0 +1,153 @@
+use std::borrow::Cow;
+
+use bevy::reflect::Reflect;
+
+use super::BlockFace;
+
+/// The number of steps a block is divided into along each axis when describing a `ShapeBox`
+pub const SHAPE_RESOLUTION: u8 = 16;
+
+/// An axis aligned box inside a block, measured in 1/16ths of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub struct ShapeBox {
+    pub min: [u8; 3],
+    pub max: [u8; 3],
+}
+
+impl ShapeBox {
+    pub const FULL: ShapeBox = ShapeBox::new([0, 0, 0], [16, 16, 16]);
+
+    pub const fn new(min: [u8; 3], max: [u8; 3]) -> ShapeBox {
+        debug_assert!(min[0] < max[0] && min[1] < max[1] && min[2] < max[2]);
+        debug_assert!(
+            max[0] <= SHAPE_RESOLUTION && max[1] <= SHAPE_RESOLUTION && max[2] <= SHAPE_RESOLUTION
+        );
+        ShapeBox { min, max }
+    }
+
+    /// Does this box fill the whole of the given face of the block
+    pub fn covers(&self, face: BlockFace) -> bool {
+        let (axis, on_edge) = match face {
+            BlockFace::Up => (1, self.max[1] == SHAPE_RESOLUTION),
+            BlockFace::Down => (1, self.min[1] == 0),
+            BlockFace::North => (2, self.min[2] == 0),
+            BlockFace::South => (2, self.max[2] == SHAPE_RESOLUTION),
+            BlockFace::East => (0, self.max[0] == SHAPE_RESOLUTION),
+            BlockFace::West => (0, self.min[0] == 0),
+        };
+        on_edge
+            && (0..3)
+                .filter(|a| *a != axis)
+                .all(|a| self.min[a] == 0 && self.max[a] == SHAPE_RESOLUTION)
+    }
+
+    /// Does the given face of this box lie on the outside of the block
+    pub fn on_edge(&self, face: BlockFace) -> bool {
+        match face {
+            BlockFace::Up => self.max[1] == SHAPE_RESOLUTION,
+            BlockFace::Down => self.min[1] == 0,
+            BlockFace::North => self.min[2] == 0,
+            BlockFace::South => self.max[2] == SHAPE_RESOLUTION,
+            BlockFace::East => self.max[0] == SHAPE_RESOLUTION,
+            BlockFace::West => self.min[0] == 0,
+        }
+    }
+}
+
+const SLAB: [ShapeBox; 1] = [ShapeBox::new([0, 0, 0], [16, 8, 16])];
+const TOP_SLAB: [ShapeBox; 1] = [ShapeBox::new([0, 8, 0], [16, 16, 16])];
+// the step is on the South half, so the stair climbs towards South and its South face is full
+const STAIRS: [ShapeBox; 2] = [
+    ShapeBox::new([0, 0, 0], [16, 8, 16]),
+    ShapeBox::new([0, 8, 8], [16, 16, 16]),
+];
+const FENCE_POST: [ShapeBox; 1] = [ShapeBox::new([6, 0, 6], [10, 16, 10])];
+
+/// The shape of a block, used by the mesher to build its geometry
+/// and to work out which faces of its neighbours it hides.
+#[derive(Debug, Clone, PartialEq, Default, Reflect)]
+pub enum BlockShape {
+    /// A full block, these are greedy meshed
+    #[default]
+    Cube,
+    /// The bottom half of a block
+    Slab,
+    /// The top half of a block
+    TopSlab,
+    /// A bottom slab with a step on its South half
+    Stairs,
+    /// Two diagonal quads, used for plants
+    Cross,
+    /// A centre post with rails that connect to solid neighbours
+    Fence,
+    /// Any number of boxes inside the block
+    Custom(Cow<'static, [ShapeBox]>),
+}
+
+impl BlockShape {
+    /// The boxes that make up this shape,
+    /// `Cross` has no boxes and `Fence` only returns its post
+    pub fn boxes(&self) -> &[ShapeBox] {
+        match self {
+            BlockShape::Cube => &[ShapeBox::FULL],
+            BlockShape::Slab => &SLAB,
+            BlockShape::TopSlab => &TOP_SLAB,
+            BlockShape::Stairs => &STAIRS,
+            BlockShape::Cross => &[],
+            BlockShape::Fence => &FENCE_POST,
+            BlockShape::Custom(boxes) => boxes,
+        }
+    }
+
+    /// Does the shape fill the whole of the given face,
+    /// the boxes on the face may fill it together like the back of `Stairs`
+    pub fn covers(&self, face: BlockFace) -> bool {
+        let axis = match face {
+            BlockFace::Up | BlockFace::Down => 1,
+            BlockFace::North | BlockFace::South => 2,
+            BlockFace::East | BlockFace::West => 0,
+        };
+        let (u, v) = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        // one bit per 1/16th of the face, a row for each step along v
+        let mut rows = [0u16; SHAPE_RESOLUTION as usize];
+        for b in self.boxes().iter().filter(|b| b.on_edge(face)) {
+            let row = ((1u32 << b.max[u]) - (1u32 << b.min[u])) as u16;
+            for r in &mut rows[b.min[v] as usize..b.max[v] as usize] {
+                *r |= row;
+            }
+        }
+        rows.iter().all(|r| *r == u16::MAX)
+    }
+
+    /// The rails of a fence going out from the post towards the given face
+    pub fn fence_rails(face: BlockFace) -> [ShapeBox; 2] {
+        let (min_x, max_x, min_z, max_z) = match face {
+            BlockFace::North => (7, 9, 0, 6),
+            BlockFace::South => (7, 9, 10, 16),
+            BlockFace::East => (10, 16, 7, 9),
+            BlockFace::West => (0, 6, 7, 9),
+            BlockFace::Up | BlockFace::Down => unreachable!("fences only connect horizontally"),
+        };
+        [
+            ShapeBox::new([min_x, 6, min_z], [max_x, 9, max_z]),
+            ShapeBox::new([min_x, 12, min_z], [max_x, 15, max_z]),
+        ]
+    }
+}
+
+#[test]
+fn stairs_cover_their_back() {
+    let stairs = BlockShape::Stairs;
+    // the slab and the step fill the South face together
+    assert!(stairs.covers(BlockFace::South));
+    assert!(stairs.covers(BlockFace::Down));
+    assert!(!stairs.covers(BlockFace::North));
+    assert!(!stairs.covers(BlockFace::Up));
+    assert!(!stairs.covers(BlockFace::East));
+    assert!(BlockShape::Cube.covers(BlockFace::East));
+    assert!(!BlockShape::Fence.covers(BlockFace::Down));
+}
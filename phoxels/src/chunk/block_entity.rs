@@ -0,0 +1,172 @@
+use bevy::{
+    ecs::{
+        component::{Component, Immutable, StorageType},
+        entity::{EntityMapper, MapEntities},
+    },
+    math::UVec3,
+    platform::collections::HashMap,
+    prelude::{
+        Changed, ChildOf, Commands, Entity, Query, ReflectComponent, Transform, Vec3, Visibility,
+    },
+    reflect::Reflect,
+};
+
+use crate::core::*;
+
+/// Marks an entity as belonging to a voxel in a chunk.
+/// They are spawned as children of the chunk for blocks where `Block::has_block_entity` is true
+/// so they move and despawn with the chunk;
+/// add an observer for `OnAdd` `BlockEntity` to insert your own components
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+#[reflect(Component)]
+#[component(immutable)]
+#[require(Transform, Visibility)]
+pub struct BlockEntity {
+    /// The position of the block in the chunk
+    pub position: UVec3,
+    pub block: BlockId,
+}
+
+/// The block entities of a chunk by their position in the chunk.
+/// This is kept in sync with the chunks `ChunkData` automatically
+#[derive(Reflect, Debug, Clone, Default)]
+#[reflect(Component)]
+pub struct BlockEntities(HashMap<UVec3, Entity>);
+
+impl BlockEntities {
+    /// Get the block entity at the given position in the chunk
+    pub fn get(&self, x: u32, y: u32, z: u32) -> Option<Entity> {
+        self.0.get(&UVec3::new(x, y, z)).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (UVec3, Entity)> + '_ {
+        self.0.iter().map(|(p, e)| (*p, *e))
+    }
+}
+
+impl MapEntities for BlockEntities {
+    fn map_entities<E: EntityMapper>(&mut self, entity_mapper: &mut E) {
+        for entity in self.0.values_mut() {
+            *entity = entity_mapper.get_mapped(*entity);
+        }
+    }
+}
+
+// implemented by hand so the entities get mapped when loaded from a scene
+impl Component for BlockEntities {
+    const STORAGE_TYPE: StorageType = StorageType::Table;
+
+    type Mutability = Immutable;
+
+    fn map_entities<E: EntityMapper>(this: &mut Self, mapper: &mut E) {
+        MapEntities::map_entities(this, mapper);
+    }
+}
+
+/// Spawns and despawns block entities to match the blocks in chunks that have changed
+pub(super) fn sync_block_entities(
+    mut commands: Commands,
+    chunks: Query<(Entity, &ChunkData, Option<&BlockEntities>), Changed<ChunkData>>,
+    block_entities: Query<&BlockEntity>,
+) {
+    for (chunk, data, current) in chunks.iter() {
+        if !data.has_block_entities() && current.is_none_or(|c| c.0.is_empty()) {
+            continue;
+        }
+        let mut current = current.cloned().unwrap_or_default();
+        let mut synced = BlockEntities::default();
+        for (x, y, z) in crate::utils::DynBlockIter::new(data.size) {
+            if !data.block_meta(x, y, z).has_block_entity() {
+                continue;
+            }
+            let position = UVec3::new(x, y, z);
+            let block = data.get_block_id(x, y, z).expect("in bounds");
+            let existing = match current.0.remove(&position) {
+                Some(entity)
+                    if block_entities
+                        .get(entity)
+                        .is_ok_and(|block_entity| block_entity.block == block) =>
+                {
+                    Some(entity)
+                }
+                Some(entity) => {
+                    // the block was swapped for another that has its own block entity
+                    if let Ok(mut entity) = commands.get_entity(entity) {
+                        entity.despawn();
+                    }
+                    None
+                }
+                None => None,
+            };
+            let entity = existing.unwrap_or_else(|| {
+                #[cfg(feature = "log")]
+                bevy::log::trace!(
+                    "Spawning block entity in Chunk({:?}) at {}",
+                    chunk,
+                    position
+                );
+                commands
+                    .spawn((
+                        BlockEntity { position, block },
+                        Transform::from_translation(position.as_vec3() + Vec3::splat(0.5)),
+                        ChildOf(chunk),
+                    ))
+                    .id()
+            });
+            synced.0.insert(position, entity);
+        }
+        // anything left no longer has a block that needs it
+        for (_, entity) in current.0.drain() {
+            if let Ok(mut entity) = commands.get_entity(entity) {
+                entity.despawn();
+            }
+        }
+        commands.entity(chunk).insert(synced);
+    }
+}
+
+#[test]
+fn block_entities_follow_blocks() {
+    use crate::test_utils::{Air, Chest, Furnace, test_world};
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world();
+    let mut data = ChunkData::empty();
+    data.set_block(1, 2, 3, Chest);
+    let chunk = world.spawn(data).id();
+    world.run_system_once(sync_block_entities).unwrap();
+
+    let entity = world
+        .get::<BlockEntities>(chunk)
+        .and_then(|b| b.get(1, 2, 3))
+        .expect("chest has a block entity");
+    assert_eq!(
+        world.get::<ChildOf>(entity).map(|c| c.parent()),
+        Some(chunk)
+    );
+
+    // a different block gets its own block entity in place of the old one
+    world
+        .get_mut::<ChunkData>(chunk)
+        .unwrap()
+        .set_block(1, 2, 3, Furnace);
+    world.run_system_once(sync_block_entities).unwrap();
+    assert!(world.get_entity(entity).is_err());
+    let entity = world
+        .get::<BlockEntities>(chunk)
+        .and_then(|b| b.get(1, 2, 3))
+        .expect("furnace has a block entity");
+    assert_eq!(
+        world.get::<BlockEntity>(entity).map(|b| b.block),
+        Some(BlockId(4))
+    );
+    assert_eq!(world.query::<&BlockEntity>().iter(&world).count(), 1);
+
+    world
+        .get_mut::<ChunkData>(chunk)
+        .unwrap()
+        .set_block(1, 2, 3, Air);
+    world.run_system_once(sync_block_entities).unwrap();
+    assert!(world.get_entity(entity).is_err());
+    assert_eq!(world.get::<BlockEntities>(chunk).unwrap().iter().count(), 0);
+}
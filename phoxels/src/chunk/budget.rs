@@ -0,0 +1,173 @@
+use core::time::Duration;
+
+use bevy::{
+    platform::time::Instant,
+    prelude::{Res, ResMut, Resource},
+    render::mesh::Mesh,
+    time::Time,
+};
+
+use super::{failure::ChunkTask, manager::GeneratorLimits};
+
+/// How much work extracting finished chunk tasks may do each frame
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExtractLimit {
+    /// Extract every finished task as soon as it is done
+    #[default]
+    Unlimited,
+    /// Stop extracting once either budget is spent, the rest wait for the next frame.
+    /// `bytes` only counts the vertex and index data of meshes
+    Budget {
+        time: Option<Duration>,
+        bytes: Option<usize>,
+    },
+    /// A time budget that shrinks when frames take longer then `target_frame_time`
+    /// and grows back when they are quicker
+    Adaptive {
+        target_frame_time: Duration,
+        bytes: Option<usize>,
+    },
+}
+
+/// What has been spent of the extraction budget this frame.
+/// At least one task of each kind, data, pass and mesh, is always extracted each frame
+/// so chunks keep loading and finished data can't starve the meshes
+#[derive(Resource, Debug, Default)]
+pub struct FrameBudget {
+    time: Option<Duration>,
+    bytes: Option<usize>,
+    spent: Duration,
+    spent_bytes: usize,
+    /// by the kind of task, see `FrameBudget::kind`
+    extracted: [usize; 3],
+}
+
+impl FrameBudget {
+    /// The smallest time budget adaptive mode will shrink to
+    pub const MIN_ADAPTIVE_TIME: Duration = Duration::from_micros(500);
+
+    /// The time budget for this frame, `None` when there is no time limit
+    pub fn time(&self) -> Option<Duration> {
+        self.time
+    }
+
+    /// How long has been spent extracting this frame
+    pub fn spent(&self) -> Duration {
+        self.spent
+    }
+
+    /// How many mesh bytes have been uploaded this frame
+    pub fn spent_bytes(&self) -> usize {
+        self.spent_bytes
+    }
+
+    /// How many tasks have been extracted this frame
+    pub fn extracted(&self) -> usize {
+        self.extracted.iter().sum()
+    }
+
+    fn kind(task: ChunkTask) -> usize {
+        match task {
+            ChunkTask::Generate => 0,
+            ChunkTask::Pass(_) => 1,
+            ChunkTask::Mesh => 2,
+        }
+    }
+
+    /// Is there budget left to extract another task,
+    /// `started` is when the calling system started extracting
+    pub(super) fn has_room(&self, started: Instant, task: ChunkTask) -> bool {
+        self.extracted[Self::kind(task)] == 0
+            || (self
+                .time
+                .is_none_or(|time| self.spent + started.elapsed() < time)
+                && self.bytes.is_none_or(|bytes| self.spent_bytes < bytes))
+    }
+
+    pub(super) fn record(&mut self, task: ChunkTask) {
+        self.extracted[Self::kind(task)] += 1;
+    }
+
+    pub(super) fn spend_bytes(&mut self, bytes: usize) {
+        self.spent_bytes += bytes;
+    }
+
+    pub(super) fn finish(&mut self, started: Instant) {
+        self.spent += started.elapsed();
+    }
+}
+
+/// The bytes of vertex and index data uploaded for a mesh
+pub(super) fn mesh_bytes(mesh: &Mesh) -> usize {
+    mesh.count_vertices() * mesh.get_vertex_size() as usize
+        + mesh.indices().map_or(0, |indices| match indices {
+            bevy::render::mesh::Indices::U16(indices) => indices.len() * 2,
+            bevy::render::mesh::Indices::U32(indices) => indices.len() * 4,
+        })
+}
+
+/// Start a new frame of the budget, adapting it to the last frame time
+pub(super) fn reset_frame_budget(
+    limits: Res<GeneratorLimits>,
+    time: Option<Res<Time>>,
+    mut budget: ResMut<FrameBudget>,
+) {
+    let (time_budget, bytes) = match limits.extract {
+        ExtractLimit::Unlimited => (None, None),
+        ExtractLimit::Budget { time, bytes } => (time, bytes),
+        ExtractLimit::Adaptive {
+            target_frame_time,
+            bytes,
+        } => {
+            let most = target_frame_time / 2;
+            let current = budget.time.unwrap_or(target_frame_time / 4);
+            let delta = time.map_or(Duration::ZERO, |time| time.delta());
+            let next = if delta > target_frame_time {
+                current.mul_f32(0.75)
+            } else {
+                current + Duration::from_micros(250)
+            };
+            (
+                Some(next.clamp(
+                    FrameBudget::MIN_ADAPTIVE_TIME,
+                    most.max(FrameBudget::MIN_ADAPTIVE_TIME),
+                )),
+                bytes,
+            )
+        }
+    };
+    *budget = FrameBudget {
+        time: time_budget,
+        bytes,
+        ..Default::default()
+    };
+}
+
+#[test]
+fn budget_always_allows_one() {
+    let mut budget = FrameBudget {
+        time: Some(Duration::ZERO),
+        bytes: Some(0),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    assert!(budget.has_room(started, ChunkTask::Generate));
+    budget.record(ChunkTask::Generate);
+    assert!(!budget.has_room(started, ChunkTask::Generate));
+    // the data used up the budget, but each kind of task still gets one
+    assert!(budget.has_room(started, ChunkTask::Pass(0)));
+    assert!(budget.has_room(started, ChunkTask::Mesh));
+    budget.record(ChunkTask::Mesh);
+    assert!(!budget.has_room(started, ChunkTask::Mesh));
+    assert_eq!(budget.extracted(), 2);
+
+    let mut budget = FrameBudget {
+        bytes: Some(150),
+        ..Default::default()
+    };
+    budget.record(ChunkTask::Mesh);
+    budget.spend_bytes(100);
+    assert!(budget.has_room(started, ChunkTask::Mesh));
+    budget.spend_bytes(100);
+    assert!(!budget.has_room(started, ChunkTask::Mesh));
+}
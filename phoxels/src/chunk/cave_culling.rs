@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    ecs::change_detection::DetectChangesMut,
+    platform::collections::HashSet,
+    prelude::{Commands, Component, Entity, GlobalTransform, Query, Res, Visibility, With},
+};
+
+use super::connectivity::ChunkConnectivity;
+use super::spatial::{ChunkId, ChunkMap};
+use crate::block::BlockFace;
+
+/// Add to a camera to hide the chunks it can't see into, like caves under the ground.
+/// Each frame the chunks are walked outwards from the one the camera is in, only crossing
+/// a chunk between sides its `ChunkConnectivity` says can see each other and never
+/// turning back on a direction already taken.
+/// Meshed chunks that no camera reaches get `Visibility::Hidden`, and get back the visibility
+/// they had once they are reached again. Chunks that were already hidden are left alone.
+/// Cameras outside the loaded chunks have nowhere to walk from and are skipped,
+/// when every camera is skipped nothing is culled
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct CaveCulling;
+
+/// A chunk hidden by `CaveCulling`, with the visibility it had before
+#[derive(Component, Debug, Clone, Copy)]
+pub(crate) struct CaveCulled(Visibility);
+
+/// Walk out from `start` to every chunk that could be seen from inside it.
+/// `connectivity` returns None for chunks that are not loaded, they are never entered
+pub fn reachable_chunks(
+    start: ChunkId,
+    connectivity: impl Fn(ChunkId) -> Option<ChunkConnectivity>,
+) -> HashSet<ChunkId> {
+    let mut reached = HashSet::new();
+    if connectivity(start).is_none() {
+        return reached;
+    }
+    reached.insert(start);
+    // the chunk, the side it was entered through and the directions taken to get there
+    let mut queue = VecDeque::from([(start, None::<BlockFace>, 0u8)]);
+    while let Some((chunk, entered, directions)) = queue.pop_front() {
+        let Some(inside) = connectivity(chunk) else {
+            continue;
+        };
+        for face in BlockFace::ALL {
+            // going back the way we came can't show anything new
+            if directions & (1 << face.opposite() as u8) != 0 {
+                continue;
+            }
+            if entered.is_some_and(|entered| !inside.connected(entered, face)) {
+                continue;
+            }
+            let next = ChunkId::from_ivec3(*chunk + face.normal());
+            if reached.contains(&next) || connectivity(next).is_none() {
+                continue;
+            }
+            reached.insert(next);
+            queue.push_back((next, Some(face.opposite()), directions | 1 << face as u8));
+        }
+    }
+    reached
+}
+
+pub(crate) fn cave_cull_chunks(
+    mut commands: Commands,
+    cameras: Query<&GlobalTransform, With<CaveCulling>>,
+    map: Res<ChunkMap>,
+    connectivity: Query<Option<&ChunkConnectivity>, With<ChunkId>>,
+    mut chunks: Query<
+        (Entity, &ChunkId, &mut Visibility, Option<&CaveCulled>),
+        With<ChunkConnectivity>,
+    >,
+) {
+    let lookup = |id: ChunkId| {
+        let chunk = connectivity.get(map.get(id)?).ok()?;
+        // a chunk that has not been meshed yet can't hide anything
+        Some(chunk.copied().unwrap_or(ChunkConnectivity::ALL))
+    };
+    let mut reached = HashSet::new();
+    // with no camera to walk from every chunk gets its visibility back
+    let mut culling = false;
+    for camera in &cameras {
+        let start = ChunkId::containing(camera.translation().floor().as_ivec3());
+        // there is no graph to walk from outside the loaded chunks
+        if lookup(start).is_none() {
+            continue;
+        }
+        culling = true;
+        reached.extend(reachable_chunks(start, lookup));
+    }
+
+    for (entity, id, mut visibility, culled) in &mut chunks {
+        let seen = !culling || reached.contains(id);
+        match culled {
+            Some(CaveCulled(before)) if seen => {
+                visibility.set_if_neq(*before);
+                commands.entity(entity).remove::<CaveCulled>();
+            }
+            None if !seen && *visibility != Visibility::Hidden => {
+                commands.entity(entity).insert(CaveCulled(*visibility));
+                *visibility = Visibility::Hidden;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn enclosed_chunks_are_hidden() {
+    use crate::prelude::ChunkData;
+    use crate::test_utils::{Stone, test_world};
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::prelude::{Transform, World};
+
+    let mut world = test_world();
+    // a row of chunks with a wall of stone between the camera and the far end,
+    // the last chunk was hidden before any culling
+    let chunks = [
+        (ChunkData::empty(), Visibility::Inherited),
+        (ChunkData::empty(), Visibility::Inherited),
+        (ChunkData::solid(Stone), Visibility::Inherited),
+        (ChunkData::empty(), Visibility::Visible),
+        (ChunkData::empty(), Visibility::Hidden),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(x, (data, visibility))| {
+        world
+            .spawn((
+                ChunkId::new(x as i32 - 1, 0, 0),
+                ChunkConnectivity::from_chunk(data),
+                *visibility,
+            ))
+            .id()
+    })
+    .collect::<Vec<_>>();
+    let camera = world
+        .spawn((
+            CaveCulling,
+            GlobalTransform::from(Transform::from_xyz(8., 8., 8.)),
+        ))
+        .id();
+
+    world.run_system_once(cave_cull_chunks).unwrap();
+    let visibility = |world: &World| {
+        chunks
+            .iter()
+            .map(|chunk| *world.get::<Visibility>(*chunk).unwrap())
+            .collect::<Vec<_>>()
+    };
+    // the wall itself can be seen, but not past it
+    assert_eq!(
+        visibility(&world),
+        [
+            Visibility::Inherited,
+            Visibility::Inherited,
+            Visibility::Inherited,
+            Visibility::Hidden,
+            Visibility::Hidden
+        ]
+    );
+    assert!(world.get::<CaveCulled>(chunks[3]).is_some());
+    assert!(world.get::<CaveCulled>(chunks[4]).is_none());
+
+    // a camera outside of the loaded chunks is skipped, the first camera still culls
+    world.spawn((
+        CaveCulling,
+        GlobalTransform::from(Transform::from_xyz(0., 100., 0.)),
+    ));
+    world.run_system_once(cave_cull_chunks).unwrap();
+    assert_eq!(
+        visibility(&world),
+        [
+            Visibility::Inherited,
+            Visibility::Inherited,
+            Visibility::Inherited,
+            Visibility::Hidden,
+            Visibility::Hidden
+        ]
+    );
+
+    // with only that camera left nothing is culled,
+    // only the chunk hidden by culling is shown again
+    world.despawn(camera);
+    world.run_system_once(cave_cull_chunks).unwrap();
+    assert_eq!(
+        visibility(&world),
+        [
+            Visibility::Inherited,
+            Visibility::Inherited,
+            Visibility::Inherited,
+            Visibility::Visible,
+            Visibility::Hidden
+        ]
+    );
+    assert!(world.get::<CaveCulled>(chunks[3]).is_none());
+}
@@ -0,0 +1,168 @@
+use bevy::{
+    math::IVec3,
+    prelude::{Component, ReflectComponent},
+    reflect::Reflect,
+};
+
+use super::ChunkData;
+use crate::block::{BlockFace, BlockMeta};
+
+/// Which sides of a chunk can see each other through the blocks inside it.
+/// Made along with the mesh of the chunk, one bit for each of the 15 pairs of sides
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+#[reflect(Component)]
+pub struct ChunkConnectivity(u16);
+
+impl ChunkConnectivity {
+    /// No side can see another; a chunk of stone
+    pub const NONE: ChunkConnectivity = ChunkConnectivity(0);
+    /// Every side can see every other; a chunk of air
+    pub const ALL: ChunkConnectivity = ChunkConnectivity((1 << 15) - 1);
+
+    /// The bit of a pair of sides, the order of the sides does not matter
+    const fn bit(a: BlockFace, b: BlockFace) -> u16 {
+        let (a, b) = if (a as u16) < (b as u16) {
+            (a as u16, b as u16)
+        } else {
+            (b as u16, a as u16)
+        };
+        // the pairs starting before `a`, then how far `b` is past `a`
+        1 << (a * (11 - a) / 2 + b - a - 1)
+    }
+
+    /// Can something looking in through side `a` see out of side `b`,
+    /// a side can always see itself
+    pub const fn connected(&self, a: BlockFace, b: BlockFace) -> bool {
+        a as u16 == b as u16 || self.0 & Self::bit(a, b) != 0
+    }
+
+    pub fn connect(&mut self, a: BlockFace, b: BlockFace) {
+        if a != b {
+            self.0 |= Self::bit(a, b);
+        }
+    }
+
+    /// Flood fill the blocks that can be seen through,
+    /// the sides touched by the same pocket can all see each other
+    pub fn from_chunk(data: &ChunkData) -> ChunkConnectivity {
+        if !data.block_meta.iter().any(blocks_sight) {
+            return ChunkConnectivity::ALL;
+        }
+        let mut connectivity = ChunkConnectivity::NONE;
+        let mut visited = vec![false; data.blocks.len()];
+        let mut stack = Vec::new();
+        // a pocket that touches a side has a block on that side to start from
+        for start in 0..data.blocks.len() {
+            let position = data.position(start);
+            let on_side =
+                position.cmpeq(IVec3::ZERO).any() || position.cmpeq(data.size.as_ivec3() - 1).any();
+            if !on_side || visited[start] || data.blocks_sight(start) {
+                continue;
+            }
+            visited[start] = true;
+            stack.push(start);
+            let mut sides = Vec::with_capacity(6);
+            while let Some(index) = stack.pop() {
+                let position = data.position(index);
+                for face in BlockFace::ALL {
+                    let next = position + face.normal();
+                    if next.cmplt(IVec3::ZERO).any() || next.cmpge(data.size.as_ivec3()).any() {
+                        if !sides.contains(&face) {
+                            sides.push(face);
+                        }
+                        continue;
+                    }
+                    let next = data.get_index(next.x as u32, next.y as u32, next.z as u32);
+                    if visited[next] || data.blocks_sight(next) {
+                        continue;
+                    }
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+            for a in sides.iter() {
+                for b in sides.iter() {
+                    connectivity.connect(*a, *b);
+                }
+            }
+            if connectivity == ChunkConnectivity::ALL {
+                break;
+            }
+        }
+        connectivity
+    }
+}
+
+/// Blocks that hide everything behind them whichever side they are seen from
+fn blocks_sight(meta: &BlockMeta) -> bool {
+    BlockFace::ALL.into_iter().all(|face| meta.occludes(face))
+}
+
+impl ChunkData {
+    /// The inverse of `get_index`
+    fn position(&self, index: usize) -> IVec3 {
+        let index = index as u32;
+        IVec3::new(
+            (index % self.size.x) as i32,
+            (index / (self.size.x * self.size.z)) as i32,
+            ((index / self.size.x) % self.size.z) as i32,
+        )
+    }
+
+    fn blocks_sight(&self, index: usize) -> bool {
+        blocks_sight(&self.block_meta[self.blocks[index].0 as usize])
+    }
+}
+
+#[test]
+fn connectivity_follows_tunnels() {
+    use crate::test_utils::{Air, Glass, Stone};
+
+    let size = super::CHUNK_SIZE.size();
+
+    assert_eq!(
+        ChunkConnectivity::from_chunk(&ChunkData::empty()),
+        ChunkConnectivity::ALL
+    );
+    let mut chunk = ChunkData::solid(Stone);
+    assert_eq!(
+        ChunkConnectivity::from_chunk(&chunk),
+        ChunkConnectivity::NONE
+    );
+
+    // a cave that doesn't reach a side connects nothing
+    chunk.set_block(8, 8, 8, Air);
+    assert_eq!(
+        ChunkConnectivity::from_chunk(&chunk),
+        ChunkConnectivity::NONE
+    );
+
+    // a tunnel from west to east, with a window of glass in it
+    for x in 0..size {
+        chunk.set_block(x, 4, 4, Air);
+    }
+    chunk.set_block(3, 4, 4, Glass);
+    let tunnel = ChunkConnectivity::from_chunk(&chunk);
+    assert!(tunnel.connected(BlockFace::West, BlockFace::East));
+    assert!(tunnel.connected(BlockFace::East, BlockFace::West));
+    assert!(!tunnel.connected(BlockFace::West, BlockFace::Up));
+    assert!(!tunnel.connected(BlockFace::North, BlockFace::South));
+
+    // a shaft up from the tunnel joins the top to both ends
+    for y in 4..size {
+        chunk.set_block(8, y, 4, Air);
+    }
+    let shaft = ChunkConnectivity::from_chunk(&chunk);
+    assert!(shaft.connected(BlockFace::Up, BlockFace::West));
+    assert!(shaft.connected(BlockFace::Up, BlockFace::East));
+    assert!(!shaft.connected(BlockFace::Up, BlockFace::Down));
+
+    // every pair of sides has its own bit
+    let mut all = ChunkConnectivity::NONE;
+    for a in BlockFace::ALL {
+        for b in BlockFace::ALL {
+            all.connect(a, b);
+        }
+    }
+    assert_eq!(all, ChunkConnectivity::ALL);
+}
@@ -0,0 +1,124 @@
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+use bevy::{
+    ecs::event::EventWriter,
+    platform::collections::HashMap,
+    prelude::{Component, Entity, Event, ReflectComponent, ReflectResource, Resource},
+    reflect::Reflect,
+    tasks::futures_lite::FutureExt,
+};
+
+/// The task of a chunk that panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+pub enum ChunkTask {
+    /// The `PhoxelGenerator` of the chunk
+    Generate,
+    /// A pass of the `GenerationPipeline` centered on the chunk
+    Pass(u8),
+    Mesh,
+}
+
+/// Sent when a task of a chunk panics, the panic is caught so the game keeps running.
+/// Sent for every attempt, including the ones that are retried
+#[derive(Event, Debug, Clone)]
+pub struct ChunkGenerationFailed {
+    pub chunk: Entity,
+    pub task: ChunkTask,
+    /// The message the task panicked with
+    pub message: String,
+    /// How many times the task has failed for this chunk, starting at 1
+    pub attempt: u32,
+}
+
+/// Added to chunks that ran out of retries and were given up on.
+/// Queuing the chunk to generate again removes it
+#[derive(Component, Debug, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
+pub struct ChunkErrored;
+
+/// What happens to a chunk whose generation failed more then `FailurePolicy::retries` times.
+/// Chunks that fail to mesh or run a pass are always marked `ChunkErrored`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Reflect)]
+pub enum ChunkFallback {
+    /// Give the chunk `ChunkData::empty()` as if generation had finished
+    Empty,
+    /// Leave the chunk without data and add `ChunkErrored`
+    #[default]
+    Errored,
+}
+
+/// How panicking chunk tasks are handled
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+#[reflect(Resource)]
+pub struct FailurePolicy {
+    /// How many times a failed task is run again before falling back
+    pub retries: u32,
+    pub fallback: ChunkFallback,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        Self {
+            retries: 1,
+            fallback: ChunkFallback::Errored,
+        }
+    }
+}
+
+pub(super) enum Recovery {
+    Retry,
+    GiveUp(ChunkFallback),
+}
+
+impl FailurePolicy {
+    /// Record a failed task and decide what to do with its chunk
+    pub(super) fn fail(
+        &self,
+        failures: &mut HashMap<Entity, u32>,
+        chunk: Entity,
+        task: ChunkTask,
+        message: String,
+        events: &mut EventWriter<ChunkGenerationFailed>,
+    ) -> Recovery {
+        let attempt = failures.entry(chunk).or_default();
+        *attempt += 1;
+        let attempt = *attempt;
+        #[cfg(feature = "log")]
+        bevy::log::error!(
+            "{:?} task for chunk {:?} panicked (attempt {}): {}",
+            task,
+            chunk,
+            attempt,
+            message
+        );
+        events.write(ChunkGenerationFailed {
+            chunk,
+            task,
+            message,
+            attempt,
+        });
+        if attempt <= self.retries {
+            return Recovery::Retry;
+        }
+        failures.remove(&chunk);
+        Recovery::GiveUp(self.fallback)
+    }
+}
+
+pub(super) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Run a task catching any panic as an error
+pub(super) async fn catch<T>(task: impl Future<Output = T>) -> Result<T, String> {
+    AssertUnwindSafe(task)
+        .catch_unwind()
+        .await
+        .map_err(panic_message)
+}
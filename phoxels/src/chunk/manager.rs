@@ -6,73 +6,108 @@ use bevy::{
     ecs::{
         bundle::{Bundle, DynamicBundle},
         component::ComponentId,
+        event::EventWriter,
         query::{QueryData, ReadOnlyQueryData},
         reflect::AppTypeRegistry,
     },
-    platform::collections::HashMap,
-    prelude::{Component, Entity, Query, Res, ResMut, Resource},
+    math::IVec3,
+    prelude::{
+        Changed, Component, Deref, DerefMut, DetectChanges, Entity, Query, Ref, Res, ResMut,
+        Resource,
+    },
     reflect::{
         DynamicTuple, FromReflect, GetTypeRegistration, PartialReflect, Reflect, ReflectFromPtr,
         Tuple, TupleInfo, TypeInfo,
     },
     render::mesh::{Mesh, Mesh3d},
-    tasks::Task,
 };
-use indexmap::IndexSet;
 
-#[derive(Resource, Default)]
+use super::budget::{ExtractLimit, FrameBudget};
+use super::failure::{
+    ChunkErrored, ChunkFallback, ChunkGenerationFailed, ChunkTask, FailurePolicy,
+};
+use super::queue::JobQueue;
+
+/// Queues chunks to have their data generated by a `PhoxelGenerator`.
+/// Derefs to its `JobQueue`
+#[derive(Resource, Default, Deref, DerefMut)]
 pub struct ChunkGenerator {
-    to_generate: IndexSet<Entity>,
-    generating: HashMap<Entity, Task<ChunkData>>,
-    old_generating: HashMap<Entity, Task<ChunkData>>,
+    #[deref]
+    queue: JobQueue<ChunkData>,
+    /// chunks that still have passes of the `GenerationPipeline` to run
+    #[cfg(feature = "spatial")]
+    pub(super) waiting: indexmap::IndexSet<Entity>,
+    #[cfg(feature = "spatial")]
+    pub(super) passing: bevy::platform::collections::HashMap<Entity, super::pipeline::PassTask>,
+    /// chunks that are part of a running pass
+    #[cfg(feature = "spatial")]
+    pub(super) locked: bevy::platform::collections::HashSet<Entity>,
 }
 
 impl ChunkGenerator {
-    /// Adds a chunk to the queue to have its data generated.
-    pub fn add_to_queue(&mut self, chunk_id: Entity) {
-        self.to_generate.insert(chunk_id);
+    /// Stop generating a chunk, dropping its task and removing it from the queue.
+    /// A running generation pass is left to finish so the chunks around it are unlocked.
+    /// Returns true if the chunk was queued or generating
+    pub fn cancel(&mut self, chunk_id: Entity) -> bool {
+        #[allow(unused_mut)]
+        let mut cancelled = self.queue.cancel(chunk_id);
+        #[cfg(feature = "spatial")]
+        {
+            cancelled |= self.waiting.shift_remove(&chunk_id);
+        }
+        cancelled
     }
 
-    fn generating(&self) -> usize {
-        self.generating.len()
+    /// Generation tasks and passes that are running
+    pub(super) fn generating(&self) -> usize {
+        #[cfg(feature = "spatial")]
+        return self.queue.running() + self.passing.len();
+        #[cfg(not(feature = "spatial"))]
+        self.queue.running()
     }
 
-    // Sets the priority of the chunks to generate based on a custom function.
-    // The chunk ordered greater than the other will be generated first
-    // pub fn set_priority<F: FnMut(&ChunkId, &ChunkId) -> std::cmp::Ordering>(&mut self, func: F) {
-    //     self.to_generate.sort_by(func);
-    // }
-}
+    /// Is the chunk having its data generated or is it part of a running pass
+    #[cfg(feature = "spatial")]
+    pub fn is_busy(&self, chunk: Entity) -> bool {
+        self.queue.is_running(chunk) || self.locked.contains(&chunk)
+    }
 
-#[derive(Resource, Default)]
-pub struct ChunkMesher {
-    to_generate: IndexSet<Entity>,
-    generating: HashMap<Entity, Task<Mesh>>,
-    old_generating: HashMap<Entity, Task<Mesh>>,
+    /// Is the chunk waiting for or running a generation pass
+    #[cfg(feature = "spatial")]
+    pub fn is_passing(&self, chunk: Entity) -> bool {
+        self.waiting.contains(&chunk) || self.passing.contains_key(&chunk)
+    }
 }
 
-impl ChunkMesher {
-    /// Adds a chunk to the queue to have its mesh generated.
-    pub fn add_to_queue(&mut self, chunk_id: Entity) {
-        self.to_generate.insert(chunk_id);
-    }
+/// Queues chunks to have their mesh made from their `ChunkData`.
+/// Derefs to its `JobQueue`
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ChunkMesher(JobQueue<(Mesh, super::connectivity::ChunkConnectivity)>);
 
-    fn generating(&self) -> usize {
-        self.generating.len()
-    }
+type GeneratorFn<T> = dyn Fn(T, &mut ChunkRng) -> ChunkData + Send + Sync;
+type AsyncGeneratorFn<T> =
+    dyn Fn(T, ChunkRng) -> bevy::tasks::BoxedFuture<'static, ChunkData> + Send + Sync;
 
-    // Sets the priority of the chunks to generate based on a custom function.
-    // The chunk ordered greater than the other will be generated first?
-    // pub fn set_priority<F: FnMut(&ChunkId, &ChunkId) -> std::cmp::Ordering>(&mut self, func: F) {
-    //     self.to_generate.sort_by(func);
-    // }
+enum GeneratorKind<T: PhoxelGeneratorData> {
+    Sync(Arc<GeneratorFn<T>>),
+    Async(Arc<AsyncGeneratorFn<T>>),
+}
+
+impl<T: PhoxelGeneratorData> Clone for GeneratorKind<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Sync(f) => Self::Sync(f.clone()),
+            Self::Async(f) => Self::Async(f.clone()),
+        }
+    }
 }
 
 /// `PhoxelGenerator` is a resource that holds a closure for generating `ChunkData` based on a `ChunkId`.
 /// If used as a resource in Bevy, it allows you to define how chunks are generated by default.
 /// If used as a component, it can be inserted to override the default generation.
+/// Seeded generators are handed a `ChunkRng` made from the `WorldSeed` and the chunks position.
 #[derive(Resource)]
-pub struct PhoxelGenerator<T: PhoxelGeneratorData>(Arc<dyn Fn(T) -> ChunkData + Send + Sync>);
+pub struct PhoxelGenerator<T: PhoxelGeneratorData>(GeneratorKind<T>);
 
 impl<T: PhoxelGeneratorData> Clone for PhoxelGenerator<T> {
     fn clone(&self) -> Self {
@@ -411,11 +446,46 @@ impl<T: PhoxelGeneratorData> Component for PhoxelGenerator<T> {
 
 impl<T: PhoxelGeneratorData> PhoxelGenerator<T> {
     pub fn new<F: Fn(T) -> ChunkData + Send + Sync + 'static>(f: F) -> Self {
-        Self(Arc::new(f))
+        Self(GeneratorKind::Sync(Arc::new(move |data, _| f(data))))
+    }
+
+    /// A generator that uses the random number generator of the chunk,
+    /// the same `WorldSeed` always generates the same chunks
+    pub fn seeded<F: Fn(T, &mut ChunkRng) -> ChunkData + Send + Sync + 'static>(f: F) -> Self {
+        Self(GeneratorKind::Sync(Arc::new(f)))
+    }
+
+    /// A generator that returns a future, so it can await IO like loading a saved chunk
+    /// or yield between layers without holding a compute thread.
+    /// On wasm the future is blocked on, so it cannot wait on the browser
+    pub fn new_async<F, Fut>(f: F) -> Self
+    where
+        F: Fn(T, ChunkRng) -> Fut + Send + Sync + 'static,
+        Fut: bevy::tasks::ConditionalSendFuture<Output = ChunkData> + 'static,
+    {
+        Self(GeneratorKind::Async(Arc::new(move |data, rng| {
+            Box::pin(f(data, rng))
+        })))
+    }
+
+    /// Is this generator made with `new_async`
+    pub fn is_async(&self) -> bool {
+        matches!(self.0, GeneratorKind::Async(_))
     }
 
-    async fn generate(self, data: T) -> ChunkData {
-        (self.0)(data)
+    /// Generate a chunk on this thread, blocking on async generators
+    pub fn generate_now(&self, data: T, mut rng: ChunkRng) -> ChunkData {
+        match &self.0 {
+            GeneratorKind::Sync(f) => f(data, &mut rng),
+            GeneratorKind::Async(f) => bevy::tasks::block_on(f(data, rng)),
+        }
+    }
+
+    async fn generate(self, data: T, mut rng: ChunkRng) -> ChunkData {
+        match self.0 {
+            GeneratorKind::Sync(f) => f(data, &mut rng),
+            GeneratorKind::Async(f) => f(data, rng).await,
+        }
     }
 
     fn on_insert(
@@ -429,10 +499,12 @@ impl<T: PhoxelGeneratorData> PhoxelGenerator<T> {
 /// `GeneratorLimits` is a resource that defines the max number of chunks that can be generated or meshed concurrently.
 /// It is used to control the load on the system and prevent overwhelming the task pool.
 /// It defaults to the number of threads in the Bevy async compute task pool.
+/// `extract` limits how many finished tasks are taken each frame, so mesh uploads don't cause hitches.
 #[derive(Resource, Debug, Clone, Copy)]
 pub struct GeneratorLimits {
     pub max_generating_chunks: usize,
     pub max_meshing_chunks: usize,
+    pub extract: ExtractLimit,
 }
 
 impl Default for GeneratorLimits {
@@ -440,6 +512,7 @@ impl Default for GeneratorLimits {
         Self {
             max_generating_chunks: bevy::tasks::AsyncComputeTaskPool::get().thread_num(),
             max_meshing_chunks: bevy::tasks::AsyncComputeTaskPool::get().thread_num(),
+            extract: ExtractLimit::Unlimited,
         }
     }
 }
@@ -450,21 +523,24 @@ pub(super) fn start_generating_chunk_data<T: PhoxelGeneratorData>(
     limits: Res<GeneratorLimits>,
     chunk_specific_generators: Query<&PhoxelGenerator<T>>,
     chunk_data: Query<PhoxelGeneratorDataFetch<T>>,
+    seed: Option<Res<WorldSeed>>,
     #[cfg(target_arch = "wasm32")] mut commands: bevy::prelude::Commands,
+    #[cfg(all(target_arch = "wasm32", feature = "spatial"))] pipeline: Option<
+        Res<super::pipeline::GenerationPipeline>,
+    >,
+    #[cfg(all(target_arch = "wasm32", feature = "spatial"))] pending: Res<
+        super::pending::PendingWrites,
+    >,
+    #[cfg(feature = "spatial")] ids: Query<&super::spatial::ChunkId>,
 ) {
-    if generator.generating() >= limits.max_generating_chunks {
-        return;
-    }
-    if generator.to_generate.is_empty() {
-        return;
-    }
-    let can_generate = limits.max_generating_chunks - generator.generating();
+    let seed = seed.map(|seed| *seed).unwrap_or_default();
+    let busy = generator.generating() - generator.running();
+    let can_generate = generator.free_slots(limits.max_generating_chunks, busy);
     let task_pool = bevy::tasks::AsyncComputeTaskPool::get();
-    for _ in 0..generator.to_generate.len().min(can_generate) {
+    for _ in 0..can_generate {
         let chunk_id = generator
-            .to_generate
             .pop()
-            .expect("to_generate should not be empty");
+            .expect("there are as many queued chunks as slots");
         #[cfg(feature = "log")]
         bevy::log::trace!("Generating data for chunk: {:?}", chunk_id);
         let voxel_generator = if let Ok(voxel_generator) = chunk_specific_generators.get(chunk_id) {
@@ -487,15 +563,35 @@ pub(super) fn start_generating_chunk_data<T: PhoxelGeneratorData>(
         };
         let chunk_generator = voxel_generator.clone();
         let data = d.to_owned();
+        // without a position every chunk gets the same stream
+        #[cfg(feature = "spatial")]
+        let position = ids.get(chunk_id).map_or(IVec3::ZERO, |id| **id);
+        #[cfg(not(feature = "spatial"))]
+        let position = IVec3::ZERO;
+        let rng = ChunkRng::new(seed, position);
         #[cfg(target_arch = "wasm32")]
         {
-            let data = chunk_generator.0(data);
+            #[allow(unused_mut)]
+            let mut data = chunk_generator.generate_now(data, rng);
+            #[cfg(feature = "spatial")]
+            if let Ok(id) = ids.get(chunk_id) {
+                pending.apply(*id, &mut data);
+            }
+            #[cfg(feature = "spatial")]
+            if pipeline.as_ref().is_some_and(|p| p.passes() > 0) {
+                generator.waiting.insert(chunk_id);
+                commands
+                    .entity(chunk_id)
+                    .insert((data, super::pipeline::ChunkStage::default()));
+                continue;
+            }
             commands.entity(chunk_id).insert(data);
         }
         #[cfg(not(target_arch = "wasm32"))]
-        generator
-            .generating
-            .insert(chunk_id, task_pool.spawn(chunk_generator.generate(data)));
+        generator.start(
+            chunk_id,
+            task_pool.spawn(super::failure::catch(chunk_generator.generate(data, rng))),
+        );
     }
 }
 
@@ -506,19 +602,12 @@ pub(super) fn start_generating_chunk_mesh(
     #[cfg(target_arch = "wasm32")] mut commands: bevy::prelude::Commands,
     #[cfg(target_arch = "wasm32")] mut assets: bevy::prelude::ResMut<bevy::prelude::Assets<Mesh>>,
 ) {
-    if generator.generating() >= limits.max_meshing_chunks {
-        return;
-    }
-    if generator.to_generate.is_empty() {
-        return;
-    }
-    let can_generate = limits.max_generating_chunks - generator.generating();
+    let can_mesh = generator.free_slots(limits.max_meshing_chunks, 0);
     let task_pool = bevy::tasks::AsyncComputeTaskPool::get();
-    for _ in 0..generator.to_generate.len().min(can_generate) {
+    for _ in 0..can_mesh {
         let chunk_id = generator
-            .to_generate
             .pop()
-            .expect("to_generate should not be empty");
+            .expect("there are as many queued chunks as slots");
         #[cfg(feature = "log")]
         bevy::log::trace!("Generating mesh for chunk: {:?}", chunk_id);
         let Ok(chunk_data) = chunk_data.get(chunk_id) else {
@@ -529,40 +618,100 @@ pub(super) fn start_generating_chunk_mesh(
         #[cfg(target_arch = "wasm32")]
         {
             let mesh = crate::chunk::mesh_gen::make_mesh(chunk_data.clone());
-            commands.entity(chunk_id).insert(Mesh3d(assets.add(mesh)));
+            let connectivity = super::connectivity::ChunkConnectivity::from_chunk(chunk_data);
+            commands
+                .entity(chunk_id)
+                .insert((Mesh3d(assets.add(mesh)), connectivity));
+            generator.ran_now();
         }
         #[cfg(not(target_arch = "wasm32"))]
-        generator.generating.insert(
+        generator.start(
             chunk_id,
-            task_pool.spawn(chunk_data.clone().generate_mesh()),
+            task_pool.spawn(super::failure::catch(chunk_data.clone().generate_mesh())),
         );
     }
 }
 
+/// Queue chunks whose data was edited in place to be meshed again,
+/// which drops any mesh task that was started from the data before the edit.
+/// Chunks that have new data inserted are queued by the insert hook of `ChunkData`
+pub(super) fn queue_edited_chunks(
+    mut mesher: ResMut<ChunkMesher>,
+    chunks: Query<(Entity, Ref<ChunkData>), Changed<ChunkData>>,
+    #[cfg(feature = "spatial")] pipeline: Option<Res<super::pipeline::GenerationPipeline>>,
+    #[cfg(feature = "spatial")] stages: Query<&super::pipeline::ChunkStage>,
+) {
+    for (chunk, data) in &chunks {
+        if data.is_added() {
+            continue;
+        }
+        // chunks are not meshed until every pass has run
+        #[cfg(feature = "spatial")]
+        if let Ok(stage) = stages.get(chunk)
+            && pipeline
+                .as_ref()
+                .is_some_and(|pipeline| !pipeline.is_complete(*stage))
+        {
+            continue;
+        }
+        mesher.add_to_queue(chunk);
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
+#[allow(clippy::too_many_arguments)]
 pub(super) fn extract_finished_chunk_data(
     mut generator: ResMut<ChunkGenerator>,
     mut commands: bevy::prelude::Commands,
+    policy: Res<FailurePolicy>,
+    mut failed: EventWriter<ChunkGenerationFailed>,
+    mut budget: ResMut<FrameBudget>,
+    #[cfg(feature = "spatial")] pipeline: Option<Res<super::pipeline::GenerationPipeline>>,
+    #[cfg(feature = "spatial")] pending: Res<super::pending::PendingWrites>,
+    #[cfg(feature = "spatial")] ids: Query<&super::spatial::ChunkId>,
 ) {
     let ChunkGenerator {
-        generating,
-        old_generating,
+        queue,
+        #[cfg(feature = "spatial")]
+        waiting,
         ..
     } = generator.as_mut();
-    if generating.is_empty() {
-        return;
-    }
-    std::mem::swap(generating, old_generating);
-    for (entity, task) in old_generating.drain() {
-        if !task.is_finished() {
-            generating.insert(entity, task);
-            continue;
-        }
-        #[cfg(feature = "log")]
-        bevy::log::trace!("Extracting finished data for chunk: {:?}", entity);
-        let data = bevy::tasks::block_on(task);
-        commands.entity(entity).insert(data);
-    }
+    let policy = *policy;
+    queue.extract(
+        &mut budget,
+        ChunkTask::Generate,
+        &policy,
+        &mut failed,
+        |entity, data| {
+            #[cfg(feature = "log")]
+            bevy::log::trace!("Extracting finished data for chunk: {:?}", entity);
+            #[allow(unused_mut)]
+            let mut data = match data {
+                Ok(data) => data,
+                Err(ChunkFallback::Empty) => ChunkData::empty(),
+                Err(ChunkFallback::Errored) => {
+                    commands.entity(entity).try_insert(ChunkErrored);
+                    return 0;
+                }
+            };
+            // blocks spilled into this chunk by its neighbours
+            #[cfg(feature = "spatial")]
+            if let Ok(id) = ids.get(entity) {
+                pending.apply(*id, &mut data);
+            }
+            #[cfg(feature = "spatial")]
+            if pipeline.as_ref().is_some_and(|p| p.passes() > 0) {
+                // meshing waits until every pass has run
+                waiting.insert(entity);
+                commands
+                    .entity(entity)
+                    .try_insert((data, super::pipeline::ChunkStage::default()));
+                return 0;
+            }
+            commands.entity(entity).try_insert(data);
+            0
+        },
+    );
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -570,29 +719,64 @@ pub(super) fn extract_finished_chunk_mesh(
     mut generator: ResMut<ChunkMesher>,
     mut commands: bevy::prelude::Commands,
     mut mesh_assets: ResMut<Assets<Mesh>>,
+    policy: Res<FailurePolicy>,
+    mut failed: EventWriter<ChunkGenerationFailed>,
+    mut budget: ResMut<FrameBudget>,
+    #[cfg(feature = "indirect")] indirect: Option<Res<crate::indirect::IndirectChunks>>,
 ) {
-    let ChunkMesher {
-        generating,
-        old_generating,
-        ..
-    } = generator.as_mut();
-    if generating.is_empty() {
-        return;
-    }
-    std::mem::swap(generating, old_generating);
-    for (entity, task) in old_generating.drain() {
-        if !task.is_finished() {
-            generating.insert(entity, task);
-            continue;
-        }
+    let policy = *policy;
+    generator.extract(
+        &mut budget,
+        ChunkTask::Mesh,
+        &policy,
+        &mut failed,
+        |entity, mesh| {
+            let Ok((mesh, connectivity)) = mesh else {
+                // there is nothing to fall back to without a mesh
+                commands.entity(entity).try_insert(ChunkErrored);
+                return 0;
+            };
+            commands.entity(entity).try_insert(connectivity);
+            #[cfg(feature = "log")]
+            bevy::log::trace!("Chunk {:?} has finished meshing inserting mesh", entity);
+            let bytes = super::budget::mesh_bytes(&mesh);
+            #[cfg(feature = "indirect")]
+            if indirect.is_some()
+                && let Some(chunk_mesh) = crate::indirect::ChunkMesh::from_mesh(&mesh)
+            {
+                // the mesh lives in the shared buffers instead of being an asset
+                commands
+                    .entity(entity)
+                    .try_insert(chunk_mesh)
+                    .try_remove::<Mesh3d>();
+                return bytes;
+            }
+            commands
+                .entity(entity)
+                .try_insert(Mesh3d(mesh_assets.add(mesh)));
+            bytes
+        },
+    );
+}
 
+/// Drop the tasks and queue entries of chunks that have been despawned
+pub(super) fn cancel_despawned_chunks(
+    mut generator: ResMut<ChunkGenerator>,
+    mut mesher: ResMut<ChunkMesher>,
+    chunks: Query<()>,
+) {
+    let despawned = generator
+        .iter_queued()
+        .chain(generator.iter_running())
+        .chain(mesher.iter_queued())
+        .chain(mesher.iter_running())
+        .filter(|chunk| !chunks.contains(*chunk))
+        .collect::<Vec<_>>();
+    for chunk in despawned {
         #[cfg(feature = "log")]
-        bevy::log::trace!("Chunk {:?} has finished meshing inserting mesh", entity);
-
-        let data = bevy::tasks::block_on(task);
-        commands
-            .entity(entity)
-            .insert(Mesh3d(mesh_assets.add(data)));
+        bevy::log::trace!("Cancelling tasks of despawned chunk: {:?}", chunk);
+        generator.cancel(chunk);
+        mesher.cancel(chunk);
     }
 }
 
@@ -610,5 +794,157 @@ impl PhoxelGenerate {
         world
             .resource_mut::<ChunkGenerator>()
             .add_to_queue(ctx.entity);
+        // a chunk queued again gets another chance
+        world.commands().entity(ctx.entity).remove::<ChunkErrored>();
+    }
+}
+
+#[test]
+fn async_generators_yield() {
+    use crate::test_utils::Stone;
+
+    let generator = PhoxelGenerator::new_async(|_: (), mut rng: ChunkRng| async move {
+        let mut data = ChunkData::empty();
+        for y in 0..CHUNK_SIZE.size() {
+            for (x, _, z) in crate::utils::DynBlockIter::new(bevy::math::UVec3::new(
+                CHUNK_SIZE.size(),
+                1,
+                CHUNK_SIZE.size(),
+            )) {
+                if rng.chance(0.5) {
+                    data.set_block(x, y, z, Stone);
+                }
+            }
+            // let other tasks run between layers
+            bevy::tasks::futures_lite::future::yield_now().await;
+        }
+        data
+    });
+    assert!(generator.is_async());
+    let chunks = (0..8)
+        .map(|x| ((), IVec3::new(x, 0, 0)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        crate::dev::check_deterministic(&generator, WorldSeed(1), &chunks),
+        Ok(())
+    );
+    let task = bevy::tasks::AsyncComputeTaskPool::get_or_init(bevy::tasks::TaskPool::default)
+        .spawn(
+            generator
+                .clone()
+                .generate((), ChunkRng::new(WorldSeed(1), IVec3::ZERO)),
+        );
+    let data = bevy::tasks::block_on(task);
+    assert_eq!(
+        crate::dev::chunk_bytes(&data),
+        crate::dev::chunk_bytes(
+            &generator.generate_now((), ChunkRng::new(WorldSeed(1), IVec3::ZERO))
+        )
+    );
+}
+
+#[test]
+fn panics_are_caught() {
+    use bevy::ecs::{event::Events, system::RunSystemOnce};
+
+    let mut world = crate::test_utils::test_world();
+    let pool = bevy::tasks::AsyncComputeTaskPool::get();
+    world.insert_resource(FailurePolicy {
+        retries: 1,
+        fallback: ChunkFallback::Errored,
+    });
+    let chunk = world.spawn_empty().id();
+
+    for attempt in 1..=2 {
+        let task = pool.spawn(super::failure::catch(async {
+            panic!("broken generator");
+        }));
+        while !task.is_finished() {
+            std::thread::yield_now();
+        }
+        world.resource_mut::<ChunkGenerator>().start(chunk, task);
+        world.run_system_once(extract_finished_chunk_data).unwrap();
+        let failed = world
+            .resource_mut::<Events<ChunkGenerationFailed>>()
+            .drain()
+            .collect::<Vec<_>>();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].message, "broken generator");
+        assert_eq!(failed[0].attempt, attempt);
+        // the first failure is retried, the second gives up
+        let queued = world.resource_mut::<ChunkGenerator>().pop();
+        assert_eq!(queued == Some(chunk), attempt == 1);
+        assert_eq!(world.get::<ChunkErrored>(chunk).is_some(), attempt == 2);
+    }
+}
+
+#[test]
+fn queues_respect_their_limits() {
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = crate::test_utils::test_world();
+    world.insert_resource(GeneratorLimits {
+        max_generating_chunks: 10,
+        max_meshing_chunks: 2,
+        extract: ExtractLimit::Unlimited,
+    });
+    let chunks = (0..5)
+        .map(|_| world.spawn(ChunkData::empty()).id())
+        .collect::<Vec<_>>();
+    assert_eq!(world.resource::<ChunkMesher>().queued(), 5);
+
+    world.run_system_once(start_generating_chunk_mesh).unwrap();
+    let mesher = world.resource::<ChunkMesher>();
+    assert_eq!(mesher.running(), 2);
+    assert_eq!(mesher.queued(), 3);
+    assert_eq!(mesher.stats().started, 2);
+
+    // despawned chunks are cancelled
+    for chunk in chunks {
+        world.despawn(chunk);
+    }
+    world.run_system_once(cancel_despawned_chunks).unwrap();
+    let mesher = world.resource::<ChunkMesher>();
+    assert_eq!(mesher.running() + mesher.queued(), 0);
+    assert_eq!(mesher.stats().cancelled, 2);
+}
+
+#[test]
+fn edited_chunks_are_remeshed() {
+    use crate::test_utils::{Stone, test_world};
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world();
+    world.init_resource::<Assets<Mesh>>();
+    let queue_edited = world.register_system(queue_edited_chunks);
+    let chunk = world.spawn(ChunkData::empty()).id();
+    // inserting the data queued the chunk, so it is not queued again
+    world.run_system(queue_edited).unwrap();
+    world.run_system_once(start_generating_chunk_mesh).unwrap();
+    assert!(world.resource::<ChunkMesher>().is_running(chunk));
+
+    // gameplay edits the chunk in place while its mesh is being made
+    world
+        .get_mut::<ChunkData>(chunk)
+        .unwrap()
+        .set_block(0, 0, 0, Stone);
+    world.run_system(queue_edited).unwrap();
+    let mesher = world.resource::<ChunkMesher>();
+    assert!(!mesher.is_running(chunk));
+    assert!(mesher.is_queued(chunk));
+    assert_eq!(mesher.stats().cancelled, 1);
+
+    // the mesh that is used is made from the edited data
+    world.run_system_once(start_generating_chunk_mesh).unwrap();
+    for _ in 0..1000 {
+        world.run_system_once(extract_finished_chunk_mesh).unwrap();
+        if world.get::<Mesh3d>(chunk).is_some() {
+            break;
+        }
+        std::thread::yield_now();
     }
+    let mesh = world.get::<Mesh3d>(chunk).expect("chunk is meshed");
+    let mesh = world.resource::<Assets<Mesh>>().get(&mesh.0).unwrap();
+    assert!(mesh.indices().is_some_and(|indices| !indices.is_empty()));
+    assert_eq!(world.resource::<ChunkMesher>().stats().finished, 1);
 }
@@ -2,15 +2,19 @@ use bevy::math::UVec3;
 use bevy::{asset::RenderAssetUsages, render::mesh::Mesh};
 
 use super::{CHUNK_SIZE, ChunkData};
-use crate::core::BlockMeta;
+use crate::block::shape::SHAPE_RESOLUTION;
+use crate::core::{BlockFace, BlockRotation, BlockShape, ShapeBox};
 use crate::utils::DynBlockIter;
 
+// Each face starts with the corner that carries its face in `BLOCK_DATA`,
+// that is the provoking vertex of both its triangles, see `Vertex::face`
+
 // Back face
 const BACK_FACE: [Vertex; 4] = [
-    Vertex::LeftBottomBack,  // left bottom back
-    Vertex::RightBottomBack, // right bottom back
     Vertex::RightTopBack,    // right top back
     Vertex::LeftTopBack,     // left top back
+    Vertex::LeftBottomBack,  // left bottom back
+    Vertex::RightBottomBack, // right bottom back
 ];
 
 // Front face
@@ -23,10 +27,10 @@ const FRONT_FACE: [Vertex; 4] = [
 
 // Left face
 const LEFT_FACE: [Vertex; 4] = [
-    Vertex::LeftBottomBack,  // left bottom back
-    Vertex::LeftTopBack,     // left top back
     Vertex::LeftTopFront,    // left top front
     Vertex::LeftBottomFront, // left bottom front
+    Vertex::LeftBottomBack,  // left bottom back
+    Vertex::LeftTopBack,     // left top back
 ];
 
 // Right face
@@ -39,10 +43,10 @@ const RIGHT_FACE: [Vertex; 4] = [
 
 // Bottom face
 const BOTTOM_FACE: [Vertex; 4] = [
-    Vertex::LeftBottomBack,   // left bottom back
-    Vertex::LeftBottomFront,  // left bottom front
     Vertex::RightBottomFront, // right bottom front
     Vertex::RightBottomBack,  // right bottom back
+    Vertex::LeftBottomBack,   // left bottom back
+    Vertex::LeftBottomFront,  // left bottom front
 ];
 
 // Top face
@@ -63,11 +67,29 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
     #[cfg(feature = "standerd_position")]
     let mut positions_old = Vec::new();
     let mut indices = Vec::new();
+    // only added to the mesh if there are shaped, rotated or textured by state blocks in the chunk
+    let mut extras = Vec::new();
+    let mut needs_extra = false;
     let mut checked = bevy::platform::collections::HashMap::new();
     // let UVec3 { x, y, z } = data.size;
     for (x, y, z) in DynBlockIter::new(data.size) {
         let block = data.block_meta(x, y, z);
-        if block == BlockMeta::EMPTY {
+        if !block.is_meshed() {
+            continue;
+        }
+        if block.is_shaped() || data.rotation(x, y, z) != BlockRotation::IDENTITY {
+            needs_extra = true;
+            add_shaped_block(
+                &data,
+                x,
+                y,
+                z,
+                &mut positions,
+                &mut extras,
+                &mut indices,
+                #[cfg(feature = "standerd_position")]
+                &mut positions_old,
+            );
             continue;
         }
         let mut current: Face = checked.remove(&UVec3::new(x, y, z)).unwrap_or_default();
@@ -75,15 +97,15 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             continue; // All block already added
         }
         let mut m_block = VertexSet::default();
-        let block = data.texture(x, y, z);
+        let block = data.mesh_key(x, y, z);
         if !current.top() {
-            if data.block_meta(x, y + 1, z).is_transparent() {
+            if !data.occludes(x, y + 1, z, BlockFace::Down) {
                 let mut x_run = 1;
                 for x in (x + 1)..data.size.x {
-                    if data.texture(x, y, z) != block {
+                    if data.mesh_key(x, y, z) != block {
                         break;
                     }
-                    if !data.block_meta(x, y + 1, z).is_transparent() {
+                    if data.occludes(x, y + 1, z, BlockFace::Down) {
                         break;
                     }
                     let other = checked.entry(UVec3::new(x, y, z)).or_default();
@@ -96,10 +118,10 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
                 let mut z_run = 1;
                 'z_loop: for z in (z + 1)..data.size.z {
                     for x in x..(x + x_run) {
-                        if data.texture(x, y, z) != block {
+                        if data.mesh_key(x, y, z) != block {
                             break 'z_loop;
                         }
-                        if !data.block_meta(x, y + 1, z).is_transparent() {
+                        if data.occludes(x, y + 1, z, BlockFace::Down) {
                             break 'z_loop;
                         }
                         if checked.get(&UVec3::new(x, y, z)).is_some_and(|f| f.top()) {
@@ -123,13 +145,13 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             current.set_top();
         }
         if !current.bottom() {
-            if y == 0 || data.block_meta(x, y - 1, z).is_transparent() {
+            if y == 0 || !data.occludes(x, y - 1, z, BlockFace::Up) {
                 let mut x_run = 1;
                 for x in (x + 1)..data.size.x {
-                    if data.texture(x, y, z) != block {
+                    if data.mesh_key(x, y, z) != block {
                         break;
                     }
-                    if y != 0 && !data.block_meta(x, y - 1, z).is_transparent() {
+                    if y != 0 && data.occludes(x, y - 1, z, BlockFace::Up) {
                         break;
                     }
                     let other = checked.entry(UVec3::new(x, y, z)).or_default();
@@ -142,10 +164,10 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
                 let mut z_run = 1;
                 'z_look: for z in (z + 1)..data.size.z {
                     for x in x..(x + x_run) {
-                        if data.texture(x, y, z) != block {
+                        if data.mesh_key(x, y, z) != block {
                             break 'z_look;
                         }
-                        if y != 0 && !data.block_meta(x, y - 1, z).is_transparent() {
+                        if y != 0 && data.occludes(x, y - 1, z, BlockFace::Up) {
                             break 'z_look;
                         }
                         if checked
@@ -172,13 +194,13 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             current.set_bottom();
         }
         if !current.left() {
-            if x == 0 || data.block_meta(x - 1, y, z).is_transparent() {
+            if x == 0 || !data.occludes(x - 1, y, z, BlockFace::East) {
                 let mut z_run = 1;
                 for nz in (z + 1)..data.size.z {
-                    if data.texture(x, y, nz) != block {
+                    if data.mesh_key(x, y, nz) != block {
                         break;
                     }
-                    if x != 0 && !data.block_meta(x - 1, y, nz).is_transparent() {
+                    if x != 0 && data.occludes(x - 1, y, nz, BlockFace::East) {
                         break;
                     }
                     let other = checked.entry(UVec3::new(x, y, nz)).or_default();
@@ -191,10 +213,10 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
                 let mut y_run = 1;
                 'y_look: for ny in (y + 1)..data.size.y {
                     for nz in z..(z + z_run) {
-                        if data.texture(x, ny, nz) != block {
+                        if data.mesh_key(x, ny, nz) != block {
                             break 'y_look;
                         }
-                        if x != 0 && !data.block_meta(x - 1, ny, nz).is_transparent() {
+                        if x != 0 && data.occludes(x - 1, ny, nz, BlockFace::East) {
                             break 'y_look;
                         }
                         if checked
@@ -221,13 +243,13 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             current.set_left();
         }
         if !current.right() {
-            if data.block_meta(x + 1, y, z).is_transparent() {
+            if !data.occludes(x + 1, y, z, BlockFace::West) {
                 let mut z_run = 1;
                 for nz in (z + 1)..data.size.z {
-                    if data.texture(x, y, nz) != block {
+                    if data.mesh_key(x, y, nz) != block {
                         break;
                     }
-                    if !data.block_meta(x + 1, y, nz).is_transparent() {
+                    if data.occludes(x + 1, y, nz, BlockFace::West) {
                         break;
                     }
                     let other = checked.entry(UVec3::new(x, y, nz)).or_default();
@@ -240,10 +262,10 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
                 let mut y_run = 1;
                 'y_look: for ny in (y + 1)..data.size.y {
                     for nz in z..(z + z_run) {
-                        if data.texture(x, ny, nz) != block {
+                        if data.mesh_key(x, ny, nz) != block {
                             break 'y_look;
                         }
-                        if !data.block_meta(x + 1, ny, nz).is_transparent() {
+                        if data.occludes(x + 1, ny, nz, BlockFace::West) {
                             break 'y_look;
                         }
                         if checked
@@ -270,13 +292,13 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             current.set_right();
         }
         if !current.front() {
-            if z == 0 || data.block_meta(x, y, z - 1).is_transparent() {
+            if z == 0 || !data.occludes(x, y, z - 1, BlockFace::South) {
                 let mut x_run = 1;
                 for nx in (x + 1)..data.size.x {
-                    if data.texture(nx, y, z) != block {
+                    if data.mesh_key(nx, y, z) != block {
                         break;
                     }
-                    if z != 0 && !data.block_meta(nx, y, z - 1).is_transparent() {
+                    if z != 0 && data.occludes(nx, y, z - 1, BlockFace::South) {
                         break;
                     }
                     let other = checked.entry(UVec3::new(nx, y, z)).or_default();
@@ -289,10 +311,10 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
                 let mut y_run = 1;
                 'y_look: for ny in (y + 1)..data.size.y {
                     for nx in x..(x + x_run) {
-                        if data.texture(nx, ny, z) != block {
+                        if data.mesh_key(nx, ny, z) != block {
                             break 'y_look;
                         }
-                        if z != 0 && !data.block_meta(nx, ny, z - 1).is_transparent() {
+                        if z != 0 && data.occludes(nx, ny, z - 1, BlockFace::South) {
                             break 'y_look;
                         }
                         if checked
@@ -319,13 +341,13 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             current.set_front();
         }
         if !current.back() {
-            if data.block_meta(x, y, z + 1).is_transparent() {
+            if !data.occludes(x, y, z + 1, BlockFace::North) {
                 let mut x_run = 1;
                 for nx in (x + 1)..data.size.x {
-                    if data.texture(nx, y, z) != block {
+                    if data.mesh_key(nx, y, z) != block {
                         break;
                     }
-                    if !data.block_meta(nx, y, z + 1).is_transparent() {
+                    if data.occludes(nx, y, z + 1, BlockFace::North) {
                         break;
                     }
                     let other = checked.entry(UVec3::new(nx, y, z)).or_default();
@@ -338,10 +360,10 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
                 let mut y_run = 1;
                 'y_look: for ny in (y + 1)..data.size.y {
                     for nx in x..(x + x_run) {
-                        if data.texture(nx, ny, z) != block {
+                        if data.mesh_key(nx, ny, z) != block {
                             break 'y_look;
                         }
-                        if !data.block_meta(nx, ny, z + 1).is_transparent() {
+                        if data.occludes(nx, ny, z + 1, BlockFace::North) {
                             break 'y_look;
                         }
                         if checked
@@ -368,28 +390,186 @@ pub fn make_mesh(data: ChunkData) -> Mesh {
             current.set_back();
         }
         let id = data.texture(x, y, z);
+        let state = data.texture_state(x, y, z) as u32;
+        let tint = data.tint(x, y, z) as u32;
+        needs_extra |= state != 0 || tint != 0;
         checked.insert(UVec3::new(x, y, z), current);
         indices.extend(m_block.indices.iter().map(|i| positions.len() as u32 + i));
         positions.extend(m_block.vertexs.iter().map(|p| {
-            let p = p.0.to_pos(p.1, p.2, p.3);
+            let corner = p.0;
+            let p = corner.to_pos(p.1, p.2, p.3);
             let x = p[0] + x;
             let y = p[1] + y;
             let z = p[2] + z;
             #[cfg(feature = "standerd_position")]
             positions_old.push([x as f32, y as f32, z as f32]);
-            x | y << CHUNK_SIZE.bits_per_axis()
-                | z << (CHUNK_SIZE.bits_per_axis() * 2)
-                | id << (8 + (CHUNK_SIZE.bits_per_axis() * 2))
-            // 9 bits left
+            pack_position(x, y, z, id, corner.face() as u32 + 1)
         }));
+        extras.resize(positions.len(), state << 16 | tint << 24);
     }
     mesh.insert_attribute(crate::simple_shader::BLOCK_DATA, positions);
+    if needs_extra {
+        mesh.insert_attribute(crate::simple_shader::BLOCK_EXTRA, extras);
+    }
     #[cfg(feature = "standerd_position")]
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions_old);
     mesh.insert_indices(bevy::render::mesh::Indices::U32(indices));
     mesh
 }
 
+/// `face` is the `BlockFace` + 1 the vertex is on, or 0 for faces that are not on the block grid
+#[inline(always)]
+fn pack_position(x: u32, y: u32, z: u32, id: u32, face: u32) -> u32 {
+    x | y << CHUNK_SIZE.bits_per_axis()
+        | z << (CHUNK_SIZE.bits_per_axis() * 2)
+        | id << (8 + (CHUNK_SIZE.bits_per_axis() * 2))
+        | face << 29
+}
+
+/// Get the position of the block touching the given face,
+/// returns None if it would be below 0
+fn neighbour(x: u32, y: u32, z: u32, face: BlockFace) -> Option<UVec3> {
+    let n = UVec3::new(x, y, z).as_ivec3() + face.normal();
+    if n.min_element() < 0 {
+        return None;
+    }
+    Some(n.as_uvec3())
+}
+
+/// Is the given face of the block hidden by the block it is touching
+fn face_hidden(data: &ChunkData, x: u32, y: u32, z: u32, face: BlockFace) -> bool {
+    neighbour(x, y, z, face).is_some_and(|n| data.occludes(n.x, n.y, n.z, face.opposite()))
+}
+
+/// The corners of a face of a box, wound counter clockwise when looking at the face
+fn face_quad(face: BlockFace, shape: &ShapeBox) -> [[u32; 3]; 4] {
+    let [min_x, min_y, min_z] = shape.min.map(u32::from);
+    let [max_x, max_y, max_z] = shape.max.map(u32::from);
+    match face {
+        BlockFace::Up => [
+            [min_x, max_y, max_z],
+            [max_x, max_y, max_z],
+            [max_x, max_y, min_z],
+            [min_x, max_y, min_z],
+        ],
+        BlockFace::Down => [
+            [min_x, min_y, max_z],
+            [min_x, min_y, min_z],
+            [max_x, min_y, min_z],
+            [max_x, min_y, max_z],
+        ],
+        BlockFace::West => [
+            [min_x, min_y, max_z],
+            [min_x, max_y, max_z],
+            [min_x, max_y, min_z],
+            [min_x, min_y, min_z],
+        ],
+        BlockFace::East => [
+            [max_x, min_y, max_z],
+            [max_x, min_y, min_z],
+            [max_x, max_y, min_z],
+            [max_x, max_y, max_z],
+        ],
+        BlockFace::North => [
+            [min_x, min_y, min_z],
+            [min_x, max_y, min_z],
+            [max_x, max_y, min_z],
+            [max_x, min_y, min_z],
+        ],
+        BlockFace::South => [
+            [min_x, min_y, max_z],
+            [max_x, min_y, max_z],
+            [max_x, max_y, max_z],
+            [min_x, max_y, max_z],
+        ],
+    }
+}
+
+const R: u32 = SHAPE_RESOLUTION as u32;
+
+// both sides of both diagonals so they can be seen from any direction
+const CROSS_QUADS: [[[u32; 3]; 4]; 4] = [
+    [[0, 0, 0], [R, 0, R], [R, R, R], [0, R, 0]],
+    [[0, 0, 0], [0, R, 0], [R, R, R], [R, 0, R]],
+    [[0, 0, R], [R, 0, 0], [R, R, 0], [0, R, R]],
+    [[0, 0, R], [0, R, R], [R, R, 0], [R, 0, 0]],
+];
+
+/// Adds the faces of a block that is not a full cube or is rotated.
+/// Shapes are measured in 1/16ths of a block so the whole part of each position goes in `BLOCK_DATA`
+/// and the remainder goes in the bottom 12 bits of `BLOCK_EXTRA`.
+/// Bits 12..15 of `BLOCK_EXTRA` hold the face of the unrotated block + 1 so the shader can pick its texture,
+/// the state and tint go in bits 16..24 and 24..32 like for cubes
+#[allow(clippy::too_many_arguments)]
+fn add_shaped_block(
+    data: &ChunkData,
+    x: u32,
+    y: u32,
+    z: u32,
+    positions: &mut Vec<u32>,
+    extras: &mut Vec<u32>,
+    indices: &mut Vec<u32>,
+    #[cfg(feature = "standerd_position")] positions_old: &mut Vec<[f32; 3]>,
+) {
+    let shape = data.block_shape(x, y, z);
+    let rotation = data.rotation(x, y, z);
+    let mut quads = Vec::new();
+    let mut boxes = shape
+        .boxes()
+        .iter()
+        .map(|b| rotation.rotate_box(*b))
+        .collect::<Vec<_>>();
+    match shape {
+        BlockShape::Cross => quads.extend(CROSS_QUADS.map(|q| (q, 0, 0))),
+        BlockShape::Fence => {
+            for face in [
+                BlockFace::North,
+                BlockFace::South,
+                BlockFace::East,
+                BlockFace::West,
+            ] {
+                if neighbour(x, y, z, face)
+                    .is_some_and(|n| data.block_meta(n.x, n.y, n.z).is_solid())
+                {
+                    boxes.extend(BlockShape::fence_rails(face));
+                }
+            }
+        }
+        _ => {}
+    }
+    for shape_box in boxes.iter() {
+        for face in BlockFace::ALL {
+            if shape_box.on_edge(face) && face_hidden(data, x, y, z, face) {
+                continue;
+            }
+            let logical_face = rotation.unrotate(face) as u32 + 1;
+            quads.push((
+                face_quad(face, shape_box),
+                logical_face << 12,
+                face as u32 + 1,
+            ));
+        }
+    }
+
+    let id = data.texture(x, y, z);
+    let state = (data.texture_state(x, y, z) as u32) << 16 | (data.tint(x, y, z) as u32) << 24;
+    for (quad, extra, face) in quads {
+        let start = positions.len() as u32;
+        for [px, py, pz] in quad {
+            let (px, py, pz) = (x * R + px, y * R + py, z * R + pz);
+            #[cfg(feature = "standerd_position")]
+            positions_old.push([
+                px as f32 / R as f32,
+                py as f32 / R as f32,
+                pz as f32 / R as f32,
+            ]);
+            positions.push(pack_position(px / R, py / R, pz / R, id, face));
+            extras.push(state | extra | (px % R) | ((py % R) << 4) | ((pz % R) << 8));
+        }
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| start + i));
+    }
+}
+
 #[derive(Default)]
 struct Face(u8);
 
@@ -544,6 +724,21 @@ enum Vertex {
 }
 
 impl Vertex {
+    /// The face this corner carries in `BLOCK_DATA`, every face starts with a different corner
+    /// so its triangles know which face they are on while still sharing corners between faces.
+    /// `LeftBottomBack` and `RightTopFront` never start a face
+    fn face(self) -> BlockFace {
+        match self {
+            Vertex::LeftTopBack => BlockFace::Up,
+            Vertex::RightBottomFront => BlockFace::Down,
+            Vertex::LeftBottomFront => BlockFace::North,
+            Vertex::RightTopBack => BlockFace::South,
+            Vertex::RightBottomBack => BlockFace::East,
+            Vertex::LeftTopFront => BlockFace::West,
+            Vertex::LeftBottomBack | Vertex::RightTopFront => BlockFace::Up,
+        }
+    }
+
     fn to_pos(self, x_run: u8, y_run: u8, z_run: u8) -> [u32; 3] {
         match self {
             Vertex::LeftBottomFront => [0, 0, 0],
@@ -558,3 +753,54 @@ impl Vertex {
         }
     }
 }
+
+#[test]
+fn non_solid_cross_is_meshed() {
+    use crate::core::Block;
+    #[derive(Clone, Copy)]
+    struct Flower;
+    impl Block for Flower {
+        fn id(&self) -> u8 {
+            1
+        }
+        fn is_solid(&self) -> bool {
+            false
+        }
+        fn is_transparent(&self) -> bool {
+            true
+        }
+        fn shape(&self) -> BlockShape {
+            BlockShape::Cross
+        }
+    }
+    let mut chunk = ChunkData::empty();
+    chunk.set_block(0, 0, 0, Flower);
+    let mesh = make_mesh(chunk);
+    assert_eq!(mesh.indices().map(|i| i.len()), Some(CROSS_QUADS.len() * 6));
+}
+
+#[test]
+fn block_entity_trigger_is_not_meshed() {
+    use crate::core::Block;
+    #[derive(Clone, Copy)]
+    struct Trigger;
+    impl Block for Trigger {
+        fn id(&self) -> u8 {
+            1
+        }
+        fn is_solid(&self) -> bool {
+            false
+        }
+        fn is_transparent(&self) -> bool {
+            true
+        }
+        fn has_block_entity(&self) -> bool {
+            true
+        }
+    }
+    let mut chunk = ChunkData::empty();
+    chunk.set_block(0, 0, 0, Trigger);
+    assert!(chunk.block_meta(0, 0, 0).has_block_entity());
+    let mesh = make_mesh(chunk);
+    assert_eq!(mesh.indices().map(|i| i.len()), Some(0));
+}
@@ -6,14 +6,29 @@ use bevy::{
     color::palettes::css::BLACK,
     ecs::schedule::IntoScheduleConfigs,
     math::UVec3,
-    prelude::Vec3,
+    platform::collections::HashMap,
+    prelude::{ReflectComponent, Vec3},
+    reflect::Reflect,
     render::{mesh::Mesh, primitives::Aabb},
 };
 
 pub use manager::GeneratorLimits;
 use manager::{ChunkGenerator, ChunkMesher};
 
+pub(crate) mod budget;
+#[cfg(feature = "spatial")]
+pub(crate) mod cave_culling;
+pub(crate) mod connectivity;
+pub(crate) mod failure;
 pub(crate) mod manager;
+#[cfg(feature = "spatial")]
+pub(crate) mod pending;
+#[cfg(feature = "spatial")]
+pub(crate) mod pipeline;
+pub(crate) mod queue;
+pub(crate) mod seed;
+#[cfg(feature = "spatial")]
+pub(crate) mod spatial;
 
 pub const CHUNK_SIZE: ChunkSize = ChunkSize::Medium;
 
@@ -52,8 +67,15 @@ impl ChunkSize {
     }
 }
 
-#[derive(bevy::prelude::Component, Clone, Debug)]
-#[component(on_insert = ChunkData::on_insert, on_remove = ChunkData::on_remove)]
+/// The blocks in a chunk.
+/// Reflect is used to serialize chunks, so it can be saved with bevy's reflection serializers or in a scene.
+#[derive(bevy::prelude::Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+#[component(
+    on_insert = ChunkData::on_insert,
+    on_replace = ChunkData::on_replace,
+    on_remove = ChunkData::on_remove
+)]
 #[require(Aabb = Aabb::from_min_max(
     Vec3::ZERO,
     Vec3::ONE * CHUNK_SIZE.size() as f32,
@@ -61,10 +83,22 @@ impl ChunkSize {
 pub struct ChunkData {
     blocks: Vec<BlockId>,
     block_meta: [BlockMeta; 256],
+    /// only blocks that are not cubes are stored
+    block_shapes: HashMap<u8, BlockShape>,
+    /// empty until a block is rotated
+    rotations: Vec<BlockRotation>,
+    /// the state of blocks that have one, keyed by index
+    states: HashMap<u32, u8>,
+    /// empty until a column is given a biome tint, keyed by `x + z * size.x`
+    biome_tints: Vec<u8>,
     meta_fills: (u128, u128),
     size: UVec3,
     #[cfg(feature = "diagnostics")]
     count: usize,
+    /// the count added to `VoxelCount::loaded` when the data was inserted,
+    /// edits in place change `count` but not what was added
+    #[cfg(feature = "diagnostics")]
+    loaded: usize,
 }
 
 impl ChunkData {
@@ -74,14 +108,24 @@ impl ChunkData {
             meta_fills: (0, 0),
             size: UVec3::splat(CHUNK_SIZE.size()),
             block_meta: [BlockMeta::EMPTY; 256],
+            block_shapes: HashMap::new(),
+            rotations: Vec::new(),
+            states: HashMap::new(),
+            biome_tints: Vec::new(),
             #[cfg(feature = "diagnostics")]
             count: 0,
+            #[cfg(feature = "diagnostics")]
+            loaded: 0,
         }
     }
 
     pub fn solid(block: impl Block) -> Self {
         let mut block_meta = [BlockMeta::EMPTY; 256];
         block_meta[block.id() as usize] = BlockMeta::from(block);
+        let mut block_shapes = HashMap::new();
+        if block_meta[block.id() as usize].is_shaped() {
+            block_shapes.insert(block.id(), block.shape());
+        }
         let meta_fills = if block.id() < 128 {
             (1 << block.id() as usize, 0)
         } else {
@@ -91,9 +135,15 @@ impl ChunkData {
             blocks: vec![BlockId(block.id()); CHUNK_SIZE.volume() as usize],
             size: UVec3::splat(CHUNK_SIZE.size()),
             block_meta,
+            block_shapes,
+            rotations: Vec::new(),
+            states: HashMap::new(),
+            biome_tints: Vec::new(),
             meta_fills,
             #[cfg(feature = "diagnostics")]
             count: CHUNK_SIZE.volume() as usize,
+            #[cfg(feature = "diagnostics")]
+            loaded: 0,
         }
     }
 
@@ -101,6 +151,130 @@ impl ChunkData {
     fn set_block_unchecked(&mut self, x: u32, y: u32, z: u32, block: u8) {
         let index = self.get_index(x, y, z);
         self.blocks[index] = BlockId(block);
+        if !self.rotations.is_empty() {
+            self.rotations[index] = BlockRotation::IDENTITY;
+        }
+        if !self.states.is_empty() {
+            self.states.remove(&(index as u32));
+        }
+    }
+
+    /// Set the block at the given coordinates with a state
+    /// Panics if the coordinates are out of bounds
+    pub fn set_block_with_state(&mut self, x: u32, y: u32, z: u32, block: impl Block, state: u8) {
+        self.set_block(x, y, z, block);
+        self.set_state(x, y, z, state);
+    }
+
+    /// Set the state of the block at the given coordinates, a state of 0 is the same as no state.
+    /// The state is cleared when the block is replaced
+    /// Panics if the coordinates are out of bounds
+    pub fn set_state(&mut self, x: u32, y: u32, z: u32, state: u8) {
+        // an index out of bounds would alias another voxel's state instead of panicking
+        assert!(
+            self.in_bounds(x, y, z),
+            "block index out of bounds: ({}, {}, {})",
+            x,
+            y,
+            z
+        );
+        let index = self.get_index(x, y, z) as u32;
+        if state == 0 {
+            self.states.remove(&index);
+        } else {
+            self.states.insert(index, state);
+        }
+    }
+
+    /// Get the state of the block at the given coordinates
+    /// returns None if the block has no state or is out of bounds
+    pub fn get_state(&self, x: u32, y: u32, z: u32) -> Option<u8> {
+        if !self.in_bounds(x, y, z) {
+            return None;
+        }
+        self.states.get(&(self.get_index(x, y, z) as u32)).copied()
+    }
+
+    /// Get the state of the block at the given coordinates
+    /// returns 0 if the block has no state or is out of bounds
+    #[inline(always)]
+    pub fn state(&self, x: u32, y: u32, z: u32) -> u8 {
+        self.get_state(x, y, z).unwrap_or(0)
+    }
+
+    /// Set where on `VoxelMaterial::tint_colormap` the blocks tinted by `BlockTint::Biome`
+    /// in a column get their colour from
+    /// Panics if the coordinates are out of bounds
+    pub fn set_biome_tint(&mut self, x: u32, z: u32, tint: u8) {
+        debug_assert!(
+            x < self.size.x && z < self.size.z,
+            "column index out of bounds: ({}, {})",
+            x,
+            z
+        );
+        if self.biome_tints.is_empty() {
+            if tint == 0 {
+                return;
+            }
+            self.biome_tints = vec![0; (self.size.x * self.size.z) as usize];
+        }
+        self.biome_tints[(x + z * self.size.x) as usize] = tint;
+    }
+
+    /// Get the biome tint of a column
+    /// returns 0 if the column has none or is out of bounds
+    #[inline(always)]
+    pub fn biome_tint(&self, x: u32, z: u32) -> u8 {
+        if self.biome_tints.is_empty() || x >= self.size.x || z >= self.size.z {
+            return 0;
+        }
+        self.biome_tints[(x + z * self.size.x) as usize]
+    }
+
+    /// Set the block at the given coordinates and rotate it
+    /// Panics if the coordinates are out of bounds
+    pub fn set_block_rotated(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        block: impl Block,
+        rotation: BlockRotation,
+    ) {
+        self.set_block(x, y, z, block);
+        self.set_rotation(x, y, z, rotation);
+    }
+
+    /// Set the rotation of the block at the given coordinates
+    /// the rotation is reset when the block is replaced
+    /// Panics if the coordinates are out of bounds
+    pub fn set_rotation(&mut self, x: u32, y: u32, z: u32, rotation: BlockRotation) {
+        // an index out of bounds would alias another voxel's rotation instead of panicking
+        assert!(
+            self.in_bounds(x, y, z),
+            "block index out of bounds: ({}, {}, {})",
+            x,
+            y,
+            z
+        );
+        let index = self.get_index(x, y, z);
+        if self.rotations.is_empty() {
+            if rotation == BlockRotation::IDENTITY {
+                return;
+            }
+            self.rotations = vec![BlockRotation::IDENTITY; self.blocks.len()];
+        }
+        self.rotations[index] = rotation;
+    }
+
+    /// Get the rotation of the block at the given coordinates
+    /// returns BlockRotation::IDENTITY if out of bounds
+    #[inline(always)]
+    pub fn rotation(&self, x: u32, y: u32, z: u32) -> BlockRotation {
+        if self.rotations.is_empty() || !self.in_bounds(x, y, z) {
+            return BlockRotation::IDENTITY;
+        }
+        self.rotations[self.get_index(x, y, z)]
     }
 
     /// Set the block at the given coordinates
@@ -119,15 +293,14 @@ impl ChunkData {
         let block_id = block.id();
         #[cfg(feature = "diagnostics")]
         let meta = BlockMeta::from(block);
+        // the count only changes with the id, the rest of the voxel is reset either way
         #[cfg(feature = "diagnostics")]
         if self.blocks[self.get_index(x, y, z)] != block_id {
-            if meta == BlockMeta::EMPTY {
-                self.count -= 1;
-            } else {
-                self.count += 1;
+            match (self.block_meta(x, y, z).is_meshed(), meta.is_meshed()) {
+                (true, false) => self.count -= 1,
+                (false, true) => self.count += 1,
+                _ => {}
             }
-        } else {
-            return;
         }
 
         self.set_block_unchecked(x, y, z, block_id);
@@ -141,7 +314,11 @@ impl ChunkData {
             self.meta_fills.1 & (1 << (block.id() - 128))
         } == 0;
         if add {
-            self.block_meta[block.id() as usize] = BlockMeta::from(block);
+            let meta = BlockMeta::from(block);
+            if meta.is_shaped() {
+                self.block_shapes.insert(block.id(), block.shape());
+            }
+            self.block_meta[block.id() as usize] = meta;
             if block.id() < 128 {
                 self.meta_fills.0 |= 1 << block.id()
             } else {
@@ -166,6 +343,31 @@ impl ChunkData {
         self.get_block_meta(x, y, z).unwrap_or(BlockMeta::EMPTY)
     }
 
+    /// Does the block at the given coordinates hide the face of a neighbour touching its `face`
+    /// returns false if out of bounds
+    #[inline(always)]
+    pub fn occludes(&self, x: u32, y: u32, z: u32, face: BlockFace) -> bool {
+        let meta = self.block_meta(x, y, z);
+        if !meta.is_shaped() {
+            return meta.occludes(face);
+        }
+        meta.occludes(self.rotation(x, y, z).unrotate(face))
+    }
+
+    /// Are there any blocks in this chunk that have a `BlockEntity`
+    pub fn has_block_entities(&self) -> bool {
+        self.block_meta.iter().any(|meta| meta.has_block_entity())
+    }
+
+    /// Get the shape of the block at the given coordinates
+    /// returns BlockShape::Cube if out of bounds
+    pub fn block_shape(&self, x: u32, y: u32, z: u32) -> &BlockShape {
+        static CUBE: BlockShape = BlockShape::Cube;
+        self.get_block_id(x, y, z)
+            .and_then(|id| self.block_shapes.get(&id.0))
+            .unwrap_or(&CUBE)
+    }
+
     pub fn get_block_id(&self, x: u32, y: u32, z: u32) -> Option<BlockId> {
         if self.in_bounds(x, y, z) {
             Some(self.blocks[self.get_index(x, y, z)])
@@ -182,6 +384,37 @@ impl ChunkData {
         }
     }
 
+    /// A value that is only equal for blocks that look the same when meshed
+    #[inline(always)]
+    pub(crate) fn mesh_key(&self, x: u32, y: u32, z: u32) -> u32 {
+        self.texture(x, y, z)
+            | (self.rotation(x, y, z).0 as u32) << 8
+            | (self.texture_state(x, y, z) as u32) << 16
+            | (self.tint(x, y, z) as u32) << 24
+    }
+
+    /// Where on the tint colormap the block gets its colour, 0 for blocks not tinted by biome or state
+    #[inline(always)]
+    pub(crate) fn tint(&self, x: u32, y: u32, z: u32) -> u8 {
+        let meta = self.block_meta(x, y, z);
+        if meta.tinted_by_biome() {
+            self.biome_tint(x, z)
+        } else if meta.tinted_by_state() {
+            self.state(x, y, z)
+        } else {
+            0
+        }
+    }
+
+    /// The state of the block if it is used to pick its texture
+    #[inline(always)]
+    pub(crate) fn texture_state(&self, x: u32, y: u32, z: u32) -> u8 {
+        if self.states.is_empty() || !self.block_meta(x, y, z).textured_by_state() {
+            return 0;
+        }
+        self.state(x, y, z)
+    }
+
     #[inline(always)]
     pub fn in_bounds(&self, x: u32, y: u32, z: u32) -> bool {
         x < self.size.x && y < self.size.y && z < self.size.z
@@ -193,8 +426,9 @@ impl ChunkData {
     }
 
     #[inline(always)]
-    pub(crate) async fn generate_mesh(self) -> Mesh {
-        mesh_gen::make_mesh(self)
+    pub(crate) async fn generate_mesh(self) -> (Mesh, connectivity::ChunkConnectivity) {
+        let connectivity = connectivity::ChunkConnectivity::from_chunk(&self);
+        (mesh_gen::make_mesh(self), connectivity)
     }
 
     fn on_insert(
@@ -209,35 +443,53 @@ impl ChunkData {
                 .expect("ChunkData requires ChunkId");
             chunk_data.update_count();
             let c = chunk_data.voxel_count();
+            chunk_data.loaded = c;
             let mut diagnostics = world.resource_mut::<crate::diagnostics::VoxelCount>();
             diagnostics.loaded += c;
         }
+        #[cfg(feature = "spatial")]
+        if let Some(stage) = world.get::<pipeline::ChunkStage>(ctx.entity).copied()
+            && world
+                .get_resource::<pipeline::GenerationPipeline>()
+                .is_some_and(|pipeline| !pipeline.is_complete(stage))
+        {
+            return;
+        }
         #[cfg(feature = "log")]
         bevy::log::trace!("Chunk({:?}) added to meshing que", ctx.entity);
         world.resource_mut::<ChunkMesher>().add_to_queue(ctx.entity);
     }
 
-    fn on_remove(
+    /// Runs before the data is removed or has new data inserted over it,
+    /// so the old data is no longer counted as loaded
+    #[cfg_attr(not(feature = "diagnostics"), allow(unused_variables, unused_mut))]
+    fn on_replace(
         mut world: bevy::ecs::world::DeferredWorld,
         ctx: bevy::ecs::component::HookContext,
     ) {
         #[cfg(feature = "diagnostics")]
         {
-            let mut chunk_data = world.entity_mut(ctx.entity);
-            let mut chunk_data = chunk_data
-                .get_mut::<ChunkData>()
-                .expect("ChunkData requires ChunkId");
-            chunk_data.update_count();
-            let c = chunk_data.voxel_count();
+            let c = world
+                .get::<ChunkData>(ctx.entity)
+                .expect("hook runs before the data is replaced")
+                .loaded;
             let mut diagnostics = world.resource_mut::<crate::diagnostics::VoxelCount>();
             diagnostics.loaded -= c;
         }
     }
+
+    fn on_remove(
+        mut world: bevy::ecs::world::DeferredWorld,
+        ctx: bevy::ecs::component::HookContext,
+    ) {
+        // there is no data left to mesh
+        world.resource_mut::<ChunkMesher>().cancel(ctx.entity);
+    }
     #[cfg(feature = "diagnostics")]
     fn update_count(&mut self) {
         let mut filled = 0;
         for block in self.blocks.iter() {
-            if self.block_meta[block.0 as usize] != BlockMeta::EMPTY {
+            if self.block_meta[block.0 as usize].is_meshed() {
                 filled += 1;
             }
         }
@@ -250,48 +502,9 @@ impl ChunkData {
     }
 }
 
+pub(crate) mod block_entity;
 pub(crate) mod mesh_gen;
 
-// #[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Deref, DerefMut)]
-// #[component(immutable, on_insert = ChunkId::on_insert)]
-// #[require(Transform, Visibility)]
-// //Aabb=Aabb::from_min_max(Vec3::NEG_ONE * CHUNK_SIZE as f32 / 2., Vec3::ONE * CHUNK_SIZE as f32 / 2.)
-// pub struct ChunkId(IVec3);
-
-// impl ChunkId {
-//     pub fn new(x: i32, y: i32, z: i32) -> Self {
-//         ChunkId(IVec3::new(x, y, z))
-//     }
-
-//     pub fn origin(&self) -> Vec3 {
-//         (self.0 * CHUNK_SIZE.size() as i32).as_vec3()
-//     }
-
-//     fn on_insert(
-//         mut world: bevy::ecs::world::DeferredWorld,
-//         ctx: bevy::ecs::component::HookContext,
-//     ) {
-//         let id = *world
-//             .entity(ctx.entity)
-//             .get::<ChunkId>()
-//             .expect("onadd of ChunkId");
-//         world
-//             .entity_mut(ctx.entity)
-//             .get_mut::<Transform>()
-//             .expect("ChunkId Requires Transform")
-//             .translation = id.origin();
-//         world
-//             .resource_mut::<ChunkManager>()
-//             .add_chunk(id, ctx.entity);
-
-//         if world.entity(ctx.entity).get::<ChunkData>().is_some() {
-//             world.resource_mut::<ChunkMesher>().add_to_queue(id);
-//         } else {
-//             world.resource_mut::<ChunkGenerator>().add_to_queue(id);
-//         }
-//     }
-// }
-
 pub struct ChunkPlugin<T: PhoxelGeneratorData = ()>(PhantomData<T>);
 
 impl<T: PhoxelGeneratorData> Default for ChunkPlugin<T> {
@@ -302,9 +515,31 @@ impl<T: PhoxelGeneratorData> Default for ChunkPlugin<T> {
 
 impl<T: PhoxelGeneratorData> Plugin for ChunkPlugin<T> {
     fn build(&self, app: &mut App) {
+        app.register_type::<ChunkData>()
+            .register_type::<block_entity::BlockEntity>()
+            .register_type::<block_entity::BlockEntities>()
+            .register_type::<connectivity::ChunkConnectivity>()
+            .register_type::<seed::WorldSeed>()
+            .init_resource::<seed::WorldSeed>()
+            .register_type::<failure::ChunkErrored>()
+            .register_type::<failure::FailurePolicy>()
+            .init_resource::<failure::FailurePolicy>()
+            .add_event::<failure::ChunkGenerationFailed>();
+        #[cfg(feature = "spatial")]
+        app.init_resource::<spatial::ChunkMap>()
+            .init_resource::<pending::PendingWrites>()
+            .register_type::<spatial::ChunkId>()
+            .register_type::<pipeline::ChunkStage>()
+            .add_systems(
+                bevy::app::PostUpdate,
+                cave_culling::cave_cull_chunks
+                    .after(bevy::transform::TransformSystem::TransformPropagate)
+                    .before(bevy::render::view::VisibilitySystems::VisibilityPropagate),
+            );
         app.init_resource::<ChunkGenerator>()
             .init_resource::<ChunkMesher>()
-            .init_resource::<GeneratorLimits>();
+            .init_resource::<GeneratorLimits>()
+            .init_resource::<budget::FrameBudget>();
 
         app.configure_sets(
             Update,
@@ -316,16 +551,31 @@ impl<T: PhoxelGeneratorData> Plugin for ChunkPlugin<T> {
         app.add_systems(
             Update,
             (
+                budget::reset_frame_budget,
+                manager::cancel_despawned_chunks,
                 #[cfg(not(target_arch = "wasm32"))]
                 manager::extract_finished_chunk_data,
+                #[cfg(all(feature = "spatial", not(target_arch = "wasm32")))]
+                pipeline::extract_finished_passes,
                 manager::start_generating_chunk_data::<T>,
+                #[cfg(feature = "spatial")]
+                pipeline::start_generation_passes,
+                #[cfg(feature = "spatial")]
+                pending::apply_pending_writes,
             )
                 .chain()
                 .in_set(ChunkSets::Generate),
         );
+        app.add_systems(
+            Update,
+            block_entity::sync_block_entities
+                .after(ChunkSets::Generate)
+                .before(ChunkSets::Mesh),
+        );
         app.add_systems(
             Update,
             (
+                manager::queue_edited_chunks,
                 #[cfg(not(target_arch = "wasm32"))]
                 manager::extract_finished_chunk_mesh,
                 manager::start_generating_chunk_mesh,
@@ -370,3 +620,148 @@ fn add_mesh_works() {
     chunk.set_block(0, 0, 0, TestBlock);
     assert_ne!(chunk.block_meta, [BlockMeta::EMPTY; 256]);
 }
+
+#[test]
+fn replacing_a_block_resets_it() {
+    #[derive(Clone, Copy)]
+    struct Furnace;
+    impl Block for Furnace {
+        fn id(&self) -> u8 {
+            1
+        }
+        fn is_solid(&self) -> bool {
+            true
+        }
+        fn is_transparent(&self) -> bool {
+            false
+        }
+    }
+    // the same with and without the diagnostics feature
+    let mut chunk = ChunkData::empty();
+    chunk.set_block_rotated(0, 0, 0, Furnace, BlockRotation::facing(BlockFace::East));
+    chunk.set_state(0, 0, 0, 3);
+    chunk.set_block(0, 0, 0, Furnace);
+    assert_eq!(chunk.state(0, 0, 0), 0);
+    assert_eq!(chunk.rotation(0, 0, 0), BlockRotation::IDENTITY);
+}
+
+#[test]
+#[should_panic(expected = "block index out of bounds")]
+fn states_out_of_bounds_panic() {
+    // x wraps onto the next row, so this would set the state of (0, 0, 1)
+    ChunkData::empty().set_state(CHUNK_SIZE.size(), 0, 0, 1);
+}
+
+#[test]
+fn state_round_trips() {
+    use bevy::reflect::{
+        FromReflect, TypeRegistry,
+        serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    };
+    use serde::de::DeserializeSeed;
+
+    #[derive(Clone, Copy)]
+    struct Crop;
+    impl Block for Crop {
+        fn id(&self) -> u8 {
+            2
+        }
+        fn is_solid(&self) -> bool {
+            false
+        }
+        fn is_transparent(&self) -> bool {
+            false
+        }
+        fn textured_by_state(&self) -> bool {
+            true
+        }
+    }
+    let mut chunk = ChunkData::empty();
+    chunk.set_block_with_state(1, 2, 3, Crop, 5);
+    assert_eq!(chunk.get_state(1, 2, 3), Some(5));
+    assert_eq!(chunk.texture_state(1, 2, 3), 5);
+    assert_eq!(chunk.get_state(0, 0, 0), None);
+
+    let mut registry = TypeRegistry::new();
+    registry.register::<ChunkData>();
+    let serialized =
+        ron::to_string(&TypedReflectSerializer::new(&chunk, &registry)).expect("serializes");
+    let registration = registry.get(std::any::TypeId::of::<ChunkData>()).unwrap();
+    let mut deserializer = ron::Deserializer::from_str(&serialized).unwrap();
+    let reflected = TypedReflectDeserializer::new(registration, &registry)
+        .deserialize(&mut deserializer)
+        .expect("deserializes");
+    let mut loaded = ChunkData::from_reflect(reflected.as_partial_reflect()).unwrap();
+    assert_eq!(loaded.state(1, 2, 3), 5);
+    assert_eq!(loaded.block_meta(1, 2, 3), BlockMeta::from(Crop));
+
+    #[derive(Clone, Copy)]
+    struct Air;
+    impl Block for Air {
+        fn id(&self) -> u8 {
+            0
+        }
+        fn is_solid(&self) -> bool {
+            false
+        }
+        fn is_transparent(&self) -> bool {
+            true
+        }
+    }
+    loaded.set_block(1, 2, 3, Air);
+    assert_eq!(loaded.get_state(1, 2, 3), None);
+}
+
+#[test]
+fn tints_follow_biome_and_state() {
+    #[derive(Clone, Copy)]
+    struct Grass;
+    impl Block for Grass {
+        fn id(&self) -> u8 {
+            3
+        }
+        fn is_solid(&self) -> bool {
+            true
+        }
+        fn is_transparent(&self) -> bool {
+            false
+        }
+        fn tint(&self) -> BlockTint {
+            BlockTint::Biome
+        }
+    }
+    #[derive(Clone, Copy)]
+    struct Wool;
+    impl Block for Wool {
+        fn id(&self) -> u8 {
+            4
+        }
+        fn is_solid(&self) -> bool {
+            true
+        }
+        fn is_transparent(&self) -> bool {
+            false
+        }
+        fn tint(&self) -> BlockTint {
+            BlockTint::State
+        }
+    }
+    let mut chunk = ChunkData::empty();
+    chunk.set_block(0, 0, 0, Grass);
+    chunk.set_block(1, 0, 0, Grass);
+    // a tint of 0 on an untinted chunk does not allocate
+    chunk.set_biome_tint(0, 0, 0);
+    assert!(chunk.biome_tints.is_empty());
+    assert_eq!(chunk.mesh_key(0, 0, 0), chunk.mesh_key(1, 0, 0));
+
+    chunk.set_biome_tint(1, 0, 40);
+    assert_eq!(chunk.tint(1, 0, 0), 40);
+    // different tints are not greedy meshed together
+    assert_ne!(chunk.mesh_key(0, 0, 0), chunk.mesh_key(1, 0, 0));
+
+    chunk.set_block_with_state(2, 0, 0, Wool, 9);
+    assert_eq!(chunk.tint(2, 0, 0), 9);
+    // the biome tint is ignored by blocks tinted by state
+    chunk.set_biome_tint(2, 0, 7);
+    assert_eq!(chunk.tint(2, 0, 0), 9);
+}
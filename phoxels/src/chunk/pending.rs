@@ -0,0 +1,265 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    math::{IVec3, UVec3},
+    platform::collections::HashMap,
+    prelude::{Query, Res, Resource},
+};
+
+use super::{
+    manager::ChunkGenerator,
+    spatial::{ChunkId, ChunkMap},
+};
+use crate::core::*;
+
+type PendingWrite = Box<dyn FnOnce(&mut ChunkData) + Send + Sync>;
+
+/// Blocks waiting to be written to chunks by world position.
+/// Writes to chunks that have no `ChunkData` yet are kept until the chunk is generated,
+/// so structures placed from a `PhoxelGenerator` that reach into other chunks are never lost.
+/// Writes are kept by chunk position even if the chunk is unloaded, so they are applied when it is loaded again;
+/// drop writes you no longer need with `forget` or `retain_near`.
+/// This is a handle; clone it into your generator to write from there
+#[derive(Resource, Clone, Default)]
+pub struct PendingWrites(Arc<Mutex<HashMap<IVec3, Vec<PendingWrite>>>>);
+
+impl PendingWrites {
+    fn push(&self, chunk: IVec3, write: PendingWrite) {
+        self.0
+            .lock()
+            .expect("pending writes lock poisoned")
+            .entry(chunk)
+            .or_default()
+            .push(write);
+    }
+
+    /// Set the block at a world position once its chunk has data
+    pub fn set_block<B: Block + Send + Sync + 'static>(&self, position: IVec3, block: B) {
+        let (chunk, local) = ChunkId::split(position);
+        self.push(
+            *chunk,
+            Box::new(move |data| data.set_block(local.x, local.y, local.z, block)),
+        );
+    }
+
+    /// Set the block at a world position with a rotation once its chunk has data
+    pub fn set_block_rotated<B: Block + Send + Sync + 'static>(
+        &self,
+        position: IVec3,
+        block: B,
+        rotation: BlockRotation,
+    ) {
+        let (chunk, local) = ChunkId::split(position);
+        self.push(
+            *chunk,
+            Box::new(move |data| {
+                data.set_block_rotated(local.x, local.y, local.z, block, rotation)
+            }),
+        );
+    }
+
+    /// Place every block of a structure with its origin at a world position
+    pub fn place<B: Block + Send + Sync + 'static>(&self, origin: IVec3, structure: &Structure<B>) {
+        let mut by_chunk = HashMap::<IVec3, Vec<(UVec3, B, BlockRotation)>>::new();
+        for (offset, block, rotation) in structure.blocks.iter() {
+            let (chunk, local) = ChunkId::split(origin + *offset);
+            by_chunk
+                .entry(*chunk)
+                .or_default()
+                .push((local, *block, *rotation));
+        }
+        let mut pending = self.0.lock().expect("pending writes lock poisoned");
+        for (chunk, blocks) in by_chunk {
+            pending
+                .entry(chunk)
+                .or_default()
+                .push(Box::new(move |data| {
+                    for (local, block, rotation) in blocks {
+                        data.set_block_rotated(local.x, local.y, local.z, block, rotation);
+                    }
+                }));
+        }
+    }
+
+    /// Does the chunk have writes waiting for it
+    pub fn has_writes(&self, chunk: ChunkId) -> bool {
+        self.0
+            .lock()
+            .expect("pending writes lock poisoned")
+            .contains_key(&*chunk)
+    }
+
+    /// Apply any writes waiting for a chunk to its data,
+    /// returns true if there were any
+    pub fn apply(&self, chunk: ChunkId, data: &mut ChunkData) -> bool {
+        let Some(writes) = self
+            .0
+            .lock()
+            .expect("pending writes lock poisoned")
+            .remove(&*chunk)
+        else {
+            return false;
+        };
+        for write in writes {
+            write(data);
+        }
+        true
+    }
+
+    /// Drop any writes waiting for a chunk
+    pub fn forget(&self, chunk: ChunkId) {
+        self.0
+            .lock()
+            .expect("pending writes lock poisoned")
+            .remove(&*chunk);
+    }
+
+    /// Drop the writes waiting for chunks more then `distance` chunks from `center` on any axis,
+    /// call this as the player moves so writes for places they will not go back to do not pile up
+    pub fn retain_near(&self, center: ChunkId, distance: u32) {
+        self.0
+            .lock()
+            .expect("pending writes lock poisoned")
+            .retain(|chunk, _| (*chunk - *center).abs().max_element() as u32 <= distance);
+    }
+
+    fn waiting_chunks(&self) -> Vec<IVec3> {
+        self.0
+            .lock()
+            .expect("pending writes lock poisoned")
+            .keys()
+            .copied()
+            .collect()
+    }
+}
+
+/// A set of blocks placed together, like a tree or a building
+#[derive(Debug, Clone)]
+pub struct Structure<B: Block> {
+    blocks: Vec<(IVec3, B, BlockRotation)>,
+}
+
+impl<B: Block> Default for Structure<B> {
+    fn default() -> Self {
+        Self { blocks: Vec::new() }
+    }
+}
+
+impl<B: Block> Structure<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a block at an offset from the origin of the structure
+    pub fn with_block(mut self, offset: IVec3, block: B) -> Self {
+        self.add_block(offset, block, BlockRotation::IDENTITY);
+        self
+    }
+
+    /// Add a block at an offset from the origin of the structure
+    pub fn add_block(&mut self, offset: IVec3, block: B, rotation: BlockRotation) -> &mut Self {
+        self.blocks.push((offset, block, rotation));
+        self
+    }
+
+    /// The blocks in the structure in the order they are placed
+    pub fn blocks(&self) -> impl Iterator<Item = (IVec3, B, BlockRotation)> + '_ {
+        self.blocks.iter().copied()
+    }
+}
+
+/// Write pending blocks to chunks that already have data.
+/// The data is edited in place, so `queue_edited_chunks` remeshes the chunk
+pub(super) fn apply_pending_writes(
+    pending: Res<PendingWrites>,
+    generator: Res<ChunkGenerator>,
+    map: Res<ChunkMap>,
+    mut chunks: Query<(&ChunkId, &mut ChunkData)>,
+) {
+    for chunk in pending.waiting_chunks() {
+        let Some(entity) = map.get(ChunkId::from_ivec3(chunk)) else {
+            continue;
+        };
+        // chunks that are being generated get their writes when they are done
+        if generator.is_busy(entity) {
+            continue;
+        }
+        let Ok((id, mut data)) = chunks.get_mut(entity) else {
+            continue;
+        };
+        if pending.apply(*id, &mut data) {
+            #[cfg(feature = "log")]
+            bevy::log::trace!("Applied pending writes to Chunk({:?})", entity);
+        }
+    }
+}
+
+#[test]
+fn writes_wait_for_chunks() {
+    use crate::block::BlockId;
+    use crate::test_utils::{Log, test_world};
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world();
+
+    let tree = Structure::new()
+        .with_block(IVec3::new(0, 0, 0), Log)
+        .with_block(IVec3::new(0, 1, 0), Log);
+    // the top of the tree crosses into the chunk above
+    world
+        .resource::<PendingWrites>()
+        .place(IVec3::new(-1, 15, 3), &tree);
+    assert!(
+        world
+            .resource::<PendingWrites>()
+            .has_writes(ChunkId::new(-1, 1, 0))
+    );
+
+    let below = world
+        .spawn((ChunkId::new(-1, 0, 0), ChunkData::empty()))
+        .id();
+    let above = world.spawn(ChunkId::new(-1, 1, 0)).id();
+    world.run_system_once(apply_pending_writes).unwrap();
+    let data = world.get::<ChunkData>(below).unwrap();
+    assert_eq!(data.get_block_id(15, 15, 3), Some(BlockId(20)));
+    assert!(world.get::<ChunkData>(above).is_none());
+
+    world.entity_mut(above).insert(ChunkData::empty());
+    world.run_system_once(apply_pending_writes).unwrap();
+    let data = world.get::<ChunkData>(above).unwrap();
+    assert_eq!(data.get_block_id(15, 0, 3), Some(BlockId(20)));
+    assert!(
+        !world
+            .resource::<PendingWrites>()
+            .has_writes(ChunkId::new(-1, 1, 0))
+    );
+
+    // writes for a chunk that is unloaded before it has data wait for it to be loaded again
+    world
+        .resource::<PendingWrites>()
+        .set_block(IVec3::new(40, 0, 0), Log);
+    let unloaded = world.spawn(ChunkId::new(2, 0, 0)).id();
+    world.run_system_once(apply_pending_writes).unwrap();
+    world.despawn(unloaded);
+    assert!(
+        world
+            .resource::<PendingWrites>()
+            .has_writes(ChunkId::new(2, 0, 0))
+    );
+    let reloaded = world
+        .spawn((ChunkId::new(2, 0, 0), ChunkData::empty()))
+        .id();
+    world.run_system_once(apply_pending_writes).unwrap();
+    let data = world.get::<ChunkData>(reloaded).unwrap();
+    assert_eq!(data.get_block_id(8, 0, 0), Some(BlockId(20)));
+
+    // writes far from the player are only dropped when asked
+    let pending = world.resource::<PendingWrites>();
+    pending.set_block(IVec3::new(0, 0, 100), Log);
+    pending.set_block(IVec3::new(0, 0, 20), Log);
+    pending.retain_near(ChunkId::new(0, 0, 0), 2);
+    assert!(!pending.has_writes(ChunkId::new(0, 0, 6)));
+    assert!(pending.has_writes(ChunkId::new(0, 0, 1)));
+    pending.forget(ChunkId::new(0, 0, 1));
+    assert!(!pending.has_writes(ChunkId::new(0, 0, 1)));
+}
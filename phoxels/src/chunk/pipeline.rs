@@ -0,0 +1,543 @@
+use std::{panic::AssertUnwindSafe, sync::Arc};
+
+use bevy::{
+    ecs::{
+        change_detection::{DetectChanges, Ref},
+        component::Tick,
+        event::EventWriter,
+    },
+    math::IVec3,
+    platform::time::Instant,
+    prelude::{Commands, Component, Entity, Query, ReflectComponent, Res, ResMut, Resource},
+    reflect::Reflect,
+};
+
+use super::{
+    budget::FrameBudget,
+    failure::{ChunkErrored, ChunkGenerationFailed, ChunkTask, FailurePolicy, Recovery},
+    manager::{ChunkGenerator, GeneratorLimits},
+    spatial::{ChunkId, ChunkMap},
+};
+use crate::{block::BlockId, core::*};
+
+/// A running pass and whether it panicked
+pub(super) type PassTask = bevy::tasks::Task<(Neighbourhood, Result<(), String>)>;
+
+/// How many passes of the `GenerationPipeline` have been run on a chunk.
+/// Chunks are not meshed until every pass has run
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Reflect)]
+#[reflect(Component)]
+pub struct ChunkStage(pub(crate) u8);
+
+impl ChunkStage {
+    pub fn passes_done(&self) -> u8 {
+        self.0
+    }
+}
+
+type GenerationPass = Arc<dyn Fn(&mut Neighbourhood) + Send + Sync>;
+
+/// Passes that are run in order on a chunk after its data is made by the `PhoxelGenerator`;
+/// for things like carving caves and decorating with trees that cross chunk borders.
+/// A pass is only run on a chunk once the chunks around it have finished the pass before it,
+/// and it can read and write to all of them through the `Neighbourhood`.
+/// A neighbour that has not been spawned yet holds the pass back, so the chunks on the border of
+/// the loaded area wait until the chunks past them are loaded.
+/// Set bounds with `with_bounds` to make the chunks outside them the edge of the world instead.
+/// A pass works on copies of the chunks, if one it would write back is changed while it runs
+/// the pass is thrown away and run again on the changed data
+#[derive(Resource, Clone, Default)]
+pub struct GenerationPipeline {
+    passes: Vec<GenerationPass>,
+    /// the first and last chunks of the world on each axis, None for an endless world
+    bounds: Option<(ChunkId, ChunkId)>,
+}
+
+impl GenerationPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pass that runs after the passes already added
+    pub fn with_pass<F: Fn(&mut Neighbourhood) + Send + Sync + 'static>(mut self, pass: F) -> Self {
+        self.add_pass(pass);
+        self
+    }
+
+    /// Add a pass that runs after the passes already added
+    pub fn add_pass<F: Fn(&mut Neighbourhood) + Send + Sync + 'static>(
+        &mut self,
+        pass: F,
+    ) -> &mut Self {
+        debug_assert!(self.passes.len() < u8::MAX as usize, "too many passes");
+        self.passes.push(Arc::new(pass));
+        self
+    }
+
+    /// Only wait for neighbours from `min` to `max` inclusive,
+    /// passes see the chunks outside as missing rather then waiting for them
+    pub fn with_bounds(mut self, min: ChunkId, max: ChunkId) -> Self {
+        self.set_bounds(min, max);
+        self
+    }
+
+    /// Only wait for neighbours from `min` to `max` inclusive,
+    /// passes see the chunks outside as missing rather then waiting for them
+    pub fn set_bounds(&mut self, min: ChunkId, max: ChunkId) -> &mut Self {
+        self.bounds = Some((min, max));
+        self
+    }
+
+    /// Is the chunk inside the world, chunks outside are the edge of the world
+    pub fn in_world(&self, chunk: ChunkId) -> bool {
+        self.bounds
+            .is_none_or(|(min, max)| chunk.cmpge(*min).all() && chunk.cmple(*max).all())
+    }
+
+    /// The number of passes in the pipeline
+    pub fn passes(&self) -> u8 {
+        self.passes.len() as u8
+    }
+
+    /// Has a chunk at this stage run every pass
+    pub fn is_complete(&self, stage: ChunkStage) -> bool {
+        stage.0 >= self.passes()
+    }
+}
+
+const CENTER: usize = 13;
+
+/// A chunk and the 26 chunks around it, given to a generation pass.
+/// Positions are in blocks relative to the origin of the center chunk,
+/// so anything from `-CHUNK_SIZE` to `CHUNK_SIZE * 2 - 1` on each axis can be reached.
+/// Chunks past the bounds of the `GenerationPipeline` are missing, they are the edge of the world
+pub struct Neighbourhood {
+    center: ChunkId,
+    pass: u8,
+    entities: [Option<Entity>; 27],
+    chunks: [Option<ChunkData>; 27],
+    /// when each chunk was last changed before it was copied for the pass
+    ticks: [Tick; 27],
+    changed: u32,
+}
+
+impl Neighbourhood {
+    fn index(offset: IVec3) -> Option<usize> {
+        if offset.abs().max_element() > 1 {
+            return None;
+        }
+        let offset = offset + IVec3::ONE;
+        Some((offset.x + offset.z * 3 + offset.y * 9) as usize)
+    }
+
+    fn offset(index: usize) -> IVec3 {
+        let index = index as i32;
+        IVec3::new(index % 3, index / 9, (index / 3) % 3) - IVec3::ONE
+    }
+
+    /// Has a chunk the pass would write back been changed since it was copied for the pass
+    #[cfg(not(target_arch = "wasm32"))]
+    fn edited_since_copied(&self, chunks: &Query<Ref<ChunkData>>, this_run: Tick) -> bool {
+        self.entities.iter().enumerate().any(|(index, entity)| {
+            let writes = index == CENTER || self.changed & (1 << index) != 0;
+            writes
+                && entity
+                    .and_then(|entity| chunks.get(entity).ok())
+                    .is_some_and(|data| {
+                        data.last_changed()
+                            .is_newer_than(self.ticks[index], this_run)
+                    })
+        })
+    }
+
+    /// The chunk the passes are being run for
+    pub fn center(&self) -> ChunkId {
+        self.center
+    }
+
+    /// The index of the pass that is running
+    pub fn pass(&self) -> u8 {
+        self.pass
+    }
+
+    /// Get the chunk at an offset of -1..=1 on each axis from the center
+    pub fn chunk(&self, offset: IVec3) -> Option<&ChunkData> {
+        self.chunks[Self::index(offset)?].as_ref()
+    }
+
+    /// Get the chunk at an offset of -1..=1 on each axis from the center to change it
+    pub fn chunk_mut(&mut self, offset: IVec3) -> Option<&mut ChunkData> {
+        let index = Self::index(offset)?;
+        let chunk = self.chunks[index].as_mut()?;
+        self.changed |= 1 << index;
+        Some(chunk)
+    }
+
+    /// Get the block at a position relative to the center chunk
+    pub fn get_block_id(&self, position: IVec3) -> Option<BlockId> {
+        let (chunk, local) = ChunkId::split(position);
+        self.chunk(*chunk)?.get_block_id(local.x, local.y, local.z)
+    }
+
+    /// Get the meta of the block at a position relative to the center chunk
+    /// returns BlockMeta::EMPTY if there is no chunk there
+    pub fn block_meta(&self, position: IVec3) -> BlockMeta {
+        let (chunk, local) = ChunkId::split(position);
+        self.chunk(*chunk)
+            .map(|chunk| chunk.block_meta(local.x, local.y, local.z))
+            .unwrap_or(BlockMeta::EMPTY)
+    }
+
+    /// Set the block at a position relative to the center chunk
+    /// returns false if there is no chunk there
+    pub fn set_block(&mut self, position: IVec3, block: impl Block) -> bool {
+        let (chunk, local) = ChunkId::split(position);
+        let Some(chunk) = self.chunk_mut(*chunk) else {
+            return false;
+        };
+        chunk.set_block(local.x, local.y, local.z, block);
+        true
+    }
+
+    /// Set the block at a position relative to the center chunk with a rotation
+    /// returns false if there is no chunk there
+    pub fn set_block_rotated(
+        &mut self,
+        position: IVec3,
+        block: impl Block,
+        rotation: BlockRotation,
+    ) -> bool {
+        let (chunk, local) = ChunkId::split(position);
+        let Some(chunk) = self.chunk_mut(*chunk) else {
+            return false;
+        };
+        chunk.set_block_rotated(local.x, local.y, local.z, block, rotation);
+        true
+    }
+}
+
+/// Hand the chunks of a finished pass back to their entities
+fn finish_pass(
+    commands: &mut Commands,
+    generator: &mut ChunkGenerator,
+    pipeline: &GenerationPipeline,
+    neighbourhood: Neighbourhood,
+) {
+    let Neighbourhood {
+        pass,
+        entities,
+        chunks,
+        changed,
+        ..
+    } = neighbourhood;
+    for (index, (entity, chunk)) in entities.into_iter().zip(chunks).enumerate() {
+        let Some(entity) = entity else {
+            continue;
+        };
+        generator.locked.remove(&entity);
+        let Some(chunk) = chunk else {
+            continue;
+        };
+        if index == CENTER {
+            let stage = ChunkStage(pass + 1);
+            if !pipeline.is_complete(stage) {
+                generator.waiting.insert(entity);
+            }
+            commands.entity(entity).try_insert((chunk, stage));
+        } else if changed & (1 << index) != 0 {
+            commands.entity(entity).try_insert(chunk);
+        }
+    }
+}
+
+/// Start the next pass for chunks whose neighbours have caught up
+#[allow(clippy::type_complexity)]
+pub(super) fn start_generation_passes(
+    pipeline: Option<Res<GenerationPipeline>>,
+    mut generator: ResMut<ChunkGenerator>,
+    limits: Res<GeneratorLimits>,
+    map: Res<ChunkMap>,
+    chunks: Query<(
+        Option<&ChunkId>,
+        Option<Ref<ChunkData>>,
+        Option<&ChunkStage>,
+    )>,
+    #[cfg(target_arch = "wasm32")] mut commands: Commands,
+) {
+    let Some(pipeline) = pipeline else {
+        return;
+    };
+    if generator.waiting.is_empty() {
+        return;
+    }
+    let task_pool = bevy::tasks::AsyncComputeTaskPool::get();
+    let mut index = 0;
+    while index < generator.waiting.len() {
+        if generator.generating() >= limits.max_generating_chunks {
+            return;
+        }
+        let chunk = generator.waiting[index];
+        let Ok((id, Some(_), stage)) = chunks.get(chunk) else {
+            // the chunk was despawned or had its data removed
+            generator.waiting.shift_remove_index(index);
+            continue;
+        };
+        let pass = stage.copied().unwrap_or_default().0;
+        if pipeline.is_complete(ChunkStage(pass)) {
+            generator.waiting.shift_remove_index(index);
+            continue;
+        }
+        let center = id.copied().unwrap_or_default();
+        let mut entities = [None; 27];
+        let mut ready = true;
+        for (slot, entity) in entities.iter_mut().enumerate() {
+            *entity = if slot == CENTER {
+                Some(chunk)
+            } else if id.is_some() {
+                let neighbour = ChunkId::from_ivec3(*center + Neighbourhood::offset(slot));
+                let found = map.get(neighbour);
+                // wait for the neighbour to be spawned unless it is past the edge of the world
+                if found.is_none() && pipeline.in_world(neighbour) {
+                    ready = false;
+                    break;
+                }
+                found
+            } else {
+                None
+            };
+            let Some(entity) = *entity else {
+                continue;
+            };
+            ready &= !generator.locked.contains(&entity)
+                && chunks.get(entity).is_ok_and(|(_, data, stage)| {
+                    // chunks loaded without a stage are already complete
+                    data.is_some() && stage.is_none_or(|stage| stage.0 >= pass)
+                });
+            if !ready {
+                break;
+            }
+        }
+        if !ready {
+            index += 1;
+            continue;
+        }
+        generator.waiting.shift_remove_index(index);
+        let mut neighbourhood = Neighbourhood {
+            center,
+            pass,
+            entities,
+            chunks: [const { None }; 27],
+            ticks: [Tick::default(); 27],
+            changed: 0,
+        };
+        for (slot, entity) in entities.iter().enumerate() {
+            let Some(entity) = entity else {
+                continue;
+            };
+            generator.locked.insert(*entity);
+            let Ok((_, Some(data), _)) = chunks.get(*entity) else {
+                continue;
+            };
+            neighbourhood.ticks[slot] = data.last_changed();
+            neighbourhood.chunks[slot] = Some(data.clone());
+        }
+        #[cfg(feature = "log")]
+        bevy::log::trace!("Running generation pass {} on chunk: {:?}", pass, chunk);
+        let run = pipeline.passes[pass as usize].clone();
+        #[cfg(target_arch = "wasm32")]
+        {
+            run(&mut neighbourhood);
+            finish_pass(&mut commands, &mut generator, &pipeline, neighbourhood);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        generator.passing.insert(
+            chunk,
+            task_pool.spawn(async move {
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| run(&mut neighbourhood)))
+                    .map_err(super::failure::panic_message);
+                (neighbourhood, result)
+            }),
+        );
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(clippy::too_many_arguments)]
+pub(super) fn extract_finished_passes(
+    mut commands: Commands,
+    pipeline: Option<Res<GenerationPipeline>>,
+    mut generator: ResMut<ChunkGenerator>,
+    policy: Res<FailurePolicy>,
+    mut failed: EventWriter<ChunkGenerationFailed>,
+    mut budget: ResMut<FrameBudget>,
+    chunks: Query<Ref<ChunkData>>,
+    ticks: bevy::ecs::system::SystemChangeTick,
+) {
+    let Some(pipeline) = pipeline else {
+        return;
+    };
+    if generator.passing.is_empty() {
+        return;
+    }
+    let mut passing = std::mem::take(&mut generator.passing);
+    let started = Instant::now();
+    for (entity, task) in passing.drain() {
+        if !task.is_finished() || !budget.has_room(started, ChunkTask::Pass(0)) {
+            generator.passing.insert(entity, task);
+            continue;
+        }
+        #[cfg(feature = "log")]
+        bevy::log::trace!("Extracting finished pass for chunk: {:?}", entity);
+        let (neighbourhood, result) = bevy::tasks::block_on(task);
+        budget.record(ChunkTask::Pass(neighbourhood.pass));
+        let Err(message) = result else {
+            generator.failures.remove(&entity);
+            if neighbourhood.edited_since_copied(&chunks, ticks.this_run()) {
+                #[cfg(feature = "log")]
+                bevy::log::trace!("Rerunning pass of chunk {:?} over edited chunks", entity);
+                for locked in neighbourhood.entities.iter().flatten() {
+                    generator.locked.remove(locked);
+                }
+                generator.waiting.insert(entity);
+                continue;
+            }
+            finish_pass(&mut commands, &mut generator, &pipeline, neighbourhood);
+            continue;
+        };
+        // the chunks keep the blocks they had before the pass
+        for locked in neighbourhood.entities.iter().flatten() {
+            generator.locked.remove(locked);
+        }
+        let task = ChunkTask::Pass(neighbourhood.pass);
+        let generator = generator.as_mut();
+        match policy.fail(&mut generator.failures, entity, task, message, &mut failed) {
+            Recovery::Retry => {
+                generator.waiting.insert(entity);
+            }
+            Recovery::GiveUp(_) => {
+                // skip the rest of the passes so neighbours waiting on this chunk carry on
+                let Ok(data) = chunks.get(entity) else {
+                    continue;
+                };
+                commands.entity(entity).try_insert((
+                    data.clone(),
+                    ChunkStage(pipeline.passes()),
+                    ChunkErrored,
+                ));
+            }
+        }
+    }
+    budget.finish(started);
+}
+
+#[test]
+fn passes_write_to_neighbours() {
+    use crate::test_utils::{Stone, test_world};
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::prelude::World;
+
+    let mut world = test_world();
+    // a world two chunks long
+    world.insert_resource(
+        GenerationPipeline::new()
+            .with_bounds(ChunkId::new(0, 0, 0), ChunkId::new(1, 0, 0))
+            .with_pass(|neighbourhood| {
+                // reach over the border into the next chunk along x
+                neighbourhood.set_block(IVec3::new(16, 0, 0), Stone);
+            }),
+    );
+    let spawn = |world: &mut World, id: ChunkId| {
+        let chunk = world
+            .spawn((id, ChunkData::empty(), ChunkStage::default()))
+            .id();
+        world.resource_mut::<ChunkGenerator>().waiting.insert(chunk);
+        chunk
+    };
+
+    // the next chunk is inside the world so the first waits for it
+    let first = spawn(&mut world, ChunkId::new(0, 0, 0));
+    world.run_system_once(start_generation_passes).unwrap();
+    assert!(
+        !world
+            .resource::<ChunkGenerator>()
+            .passing
+            .contains_key(&first)
+    );
+    assert!(world.resource::<ChunkGenerator>().waiting.contains(&first));
+
+    let chunks = [first, spawn(&mut world, ChunkId::new(1, 0, 0))];
+
+    for _ in 0..1000 {
+        world.run_system_once(start_generation_passes).unwrap();
+        world.run_system_once(extract_finished_passes).unwrap();
+        if !chunks
+            .iter()
+            .any(|c| world.resource::<ChunkGenerator>().is_passing(*c))
+        {
+            break;
+        }
+        std::thread::yield_now();
+    }
+
+    for chunk in chunks {
+        assert_eq!(world.get::<ChunkStage>(chunk), Some(&ChunkStage(1)));
+    }
+    let next = world.get::<ChunkData>(chunks[1]).unwrap();
+    assert_eq!(next.get_block_id(0, 0, 0), Some(BlockId(1)));
+    assert!(world.resource::<ChunkGenerator>().locked.is_empty());
+    // writing the passes back replaced the data, the one stone is only counted once
+    #[cfg(feature = "diagnostics")]
+    assert_eq!(world.resource::<crate::diagnostics::VoxelCount>().loaded, 1);
+}
+
+#[test]
+fn edits_during_a_pass_are_kept() {
+    use crate::test_utils::{Glass, Stone, test_world};
+    use bevy::ecs::system::RunSystemOnce;
+
+    let mut world = test_world();
+    world.insert_resource(
+        GenerationPipeline::new()
+            .with_bounds(ChunkId::new(0, 0, 0), ChunkId::new(1, 0, 0))
+            .with_pass(|neighbourhood| {
+                if neighbourhood.center() == ChunkId::new(0, 0, 0) {
+                    neighbourhood.set_block(IVec3::new(16, 0, 0), Stone);
+                }
+            }),
+    );
+    let first = world
+        .spawn((
+            ChunkId::new(0, 0, 0),
+            ChunkData::empty(),
+            ChunkStage::default(),
+        ))
+        .id();
+    // the next chunk is already complete so only the first runs the pass
+    let next = world
+        .spawn((ChunkId::new(1, 0, 0), ChunkData::empty(), ChunkStage(1)))
+        .id();
+    world.resource_mut::<ChunkGenerator>().waiting.insert(first);
+    world.run_system_once(start_generation_passes).unwrap();
+    assert!(world.resource::<ChunkGenerator>().is_passing(first));
+
+    // gameplay edits the locked neighbour while the pass is running
+    world
+        .get_mut::<ChunkData>(next)
+        .unwrap()
+        .set_block(5, 0, 0, Glass);
+
+    for _ in 0..1000 {
+        world.run_system_once(start_generation_passes).unwrap();
+        world.run_system_once(extract_finished_passes).unwrap();
+        if world.get::<ChunkStage>(first) == Some(&ChunkStage(1)) {
+            break;
+        }
+        std::thread::yield_now();
+    }
+
+    assert_eq!(world.get::<ChunkStage>(first), Some(&ChunkStage(1)));
+    let data = world.get::<ChunkData>(next).unwrap();
+    assert_eq!(data.get_block_id(5, 0, 0), Some(BlockId(2)));
+    assert_eq!(data.get_block_id(0, 0, 0), Some(BlockId(1)));
+    assert!(world.resource::<ChunkGenerator>().locked.is_empty());
+}
@@ -0,0 +1,264 @@
+use std::cmp::Ordering;
+
+use bevy::{
+    ecs::event::EventWriter,
+    platform::{collections::HashMap, time::Instant},
+    prelude::Entity,
+    tasks::Task,
+};
+use indexmap::IndexSet;
+
+use super::{
+    budget::FrameBudget,
+    failure::{ChunkFallback, ChunkGenerationFailed, ChunkTask, FailurePolicy, Recovery},
+};
+
+/// A running task and the version of the chunk it was started for
+type Stamped<T> = (u32, Task<Result<T, String>>);
+
+/// Counts of what a `JobQueue` has done since it was made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JobStats {
+    /// Tasks that were started
+    pub started: u64,
+    /// Tasks whose result was used
+    pub finished: u64,
+    /// Tasks that panicked
+    pub failed: u64,
+    /// Tasks that were dropped because their chunk was queued again, cancelled or despawned
+    pub cancelled: u64,
+}
+
+/// A queue of chunks waiting for a task and the tasks that are running.
+/// Each chunk has at most one task, queuing a chunk again cancels its running task
+pub struct JobQueue<T> {
+    queued: IndexSet<Entity>,
+    running: HashMap<Entity, Stamped<T>>,
+    old_running: HashMap<Entity, Stamped<T>>,
+    /// bumped every time a chunk is queued, results of older tasks are thrown away
+    versions: HashMap<Entity, u32>,
+    /// how many times the tasks of a chunk have panicked in a row
+    pub(super) failures: HashMap<Entity, u32>,
+    stats: JobStats,
+}
+
+impl<T> Default for JobQueue<T> {
+    fn default() -> Self {
+        Self {
+            queued: IndexSet::new(),
+            running: HashMap::new(),
+            old_running: HashMap::new(),
+            versions: HashMap::new(),
+            failures: HashMap::new(),
+            stats: JobStats::default(),
+        }
+    }
+}
+
+impl<T> JobQueue<T> {
+    /// Adds a chunk to the queue.
+    /// Cancels any task already running for the chunk, it is out of date.
+    pub fn add_to_queue(&mut self, chunk_id: Entity) {
+        if self.running.remove(&chunk_id).is_some() {
+            self.stats.cancelled += 1;
+        }
+        *self.versions.entry(chunk_id).or_default() += 1;
+        self.queued.insert(chunk_id);
+    }
+
+    /// Remove a chunk from the queue and drop its running task.
+    /// Returns true if the chunk was queued or running
+    pub fn cancel(&mut self, chunk_id: Entity) -> bool {
+        self.versions.remove(&chunk_id);
+        self.failures.remove(&chunk_id);
+        let running = self.running.remove(&chunk_id).is_some();
+        if running {
+            self.stats.cancelled += 1;
+        }
+        self.queued.shift_remove(&chunk_id) | running
+    }
+
+    /// Sort the queue, chunks ordered greater are started first.
+    /// Call this from a system to prioritise chunks, like the ones closest to the player
+    pub fn set_priority<F: FnMut(&Entity, &Entity) -> Ordering>(&mut self, func: F) {
+        self.queued.sort_by(func);
+    }
+
+    pub fn is_queued(&self, chunk_id: Entity) -> bool {
+        self.queued.contains(&chunk_id)
+    }
+
+    pub fn is_running(&self, chunk_id: Entity) -> bool {
+        self.running.contains_key(&chunk_id)
+    }
+
+    /// How many chunks are waiting for a task
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// How many tasks are running
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// The chunks waiting for a task in the order they were queued
+    pub fn iter_queued(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.queued.iter().copied()
+    }
+
+    /// The chunks that have a task running
+    pub fn iter_running(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.running.keys().copied()
+    }
+
+    pub fn stats(&self) -> JobStats {
+        self.stats
+    }
+
+    /// How many tasks can be started without going over `limit`,
+    /// `busy` is other work counted against the same limit
+    pub(super) fn free_slots(&self, limit: usize, busy: usize) -> usize {
+        limit
+            .saturating_sub(self.running.len() + busy)
+            .min(self.queued.len())
+    }
+
+    /// Take the chunk with the highest priority off the queue
+    pub(super) fn pop(&mut self) -> Option<Entity> {
+        self.queued.pop()
+    }
+
+    /// Put a chunk back on the queue without cancelling anything, like after a failure
+    pub(super) fn requeue(&mut self, chunk_id: Entity) {
+        self.queued.insert(chunk_id);
+    }
+
+    pub(super) fn start(&mut self, chunk_id: Entity, task: Task<Result<T, String>>) {
+        self.stats.started += 1;
+        let version = self.version(chunk_id);
+        self.running.insert(chunk_id, (version, task));
+    }
+
+    /// Record a job that was run without a task, like on wasm
+    #[allow(dead_code)]
+    pub(super) fn ran_now(&mut self) {
+        self.stats.started += 1;
+        self.stats.finished += 1;
+    }
+
+    pub(super) fn version(&self, chunk_id: Entity) -> u32 {
+        self.versions.get(&chunk_id).copied().unwrap_or_default()
+    }
+
+    /// Hand the results of finished tasks to `done` while there is budget left this frame,
+    /// `done` returns the bytes it uploaded.
+    /// Panicked tasks are retried, or handed to `done` as their fallback once they are out of retries
+    pub(super) fn extract(
+        &mut self,
+        budget: &mut FrameBudget,
+        task: ChunkTask,
+        policy: &FailurePolicy,
+        failed: &mut EventWriter<ChunkGenerationFailed>,
+        mut done: impl FnMut(Entity, Result<T, ChunkFallback>) -> usize,
+    ) {
+        if self.running.is_empty() {
+            return;
+        }
+        // reuse the allocation of the last frame for the tasks that are still running
+        let mut tasks = std::mem::replace(&mut self.running, std::mem::take(&mut self.old_running));
+        let started = Instant::now();
+        for (entity, (version, running)) in tasks.drain() {
+            if !running.is_finished() || !budget.has_room(started, task) {
+                self.running.insert(entity, (version, running));
+                continue;
+            }
+            // the chunk was queued again or cancelled after the task started
+            if self.version(entity) != version {
+                self.stats.cancelled += 1;
+                continue;
+            }
+            budget.record(task);
+            match bevy::tasks::block_on(running) {
+                Ok(result) => {
+                    // the chunk is only versioned again if it is queued again
+                    self.versions.remove(&entity);
+                    self.failures.remove(&entity);
+                    self.stats.finished += 1;
+                    let bytes = done(entity, Ok(result));
+                    budget.spend_bytes(bytes);
+                }
+                Err(message) => {
+                    self.stats.failed += 1;
+                    match policy.fail(&mut self.failures, entity, task, message, failed) {
+                        Recovery::Retry => self.requeue(entity),
+                        Recovery::GiveUp(fallback) => {
+                            self.versions.remove(&entity);
+                            done(entity, Err(fallback));
+                        }
+                    }
+                }
+            }
+        }
+        self.old_running = tasks;
+        budget.finish(started);
+    }
+}
+
+#[test]
+fn queuing_again_drops_running_tasks() {
+    let pool = bevy::tasks::AsyncComputeTaskPool::get_or_init(bevy::tasks::TaskPool::default);
+    let chunk = Entity::from_raw(1);
+
+    let mut queue = JobQueue::<u32>::default();
+    queue.add_to_queue(chunk);
+    assert_eq!(queue.pop(), Some(chunk));
+    queue.start(chunk, pool.spawn(super::failure::catch(async { 1 })));
+    assert!(queue.is_running(chunk));
+    // queuing again drops the running task
+    queue.add_to_queue(chunk);
+    assert!(!queue.is_running(chunk));
+    assert!(queue.is_queued(chunk));
+    assert_eq!(queue.stats().cancelled, 1);
+}
+
+#[test]
+fn finished_chunks_drop_their_version() {
+    use bevy::ecs::system::{Res, ResMut, SystemState};
+
+    let mut world = crate::test_utils::test_world();
+    let pool = bevy::tasks::AsyncComputeTaskPool::get();
+    let chunk = Entity::from_raw(1);
+
+    let mut queue = JobQueue::<u32>::default();
+    queue.add_to_queue(chunk);
+    assert_eq!(queue.pop(), Some(chunk));
+    queue.start(chunk, pool.spawn(super::failure::catch(async { 1 })));
+
+    let mut state = SystemState::<(
+        ResMut<FrameBudget>,
+        Res<FailurePolicy>,
+        EventWriter<ChunkGenerationFailed>,
+    )>::new(&mut world);
+    let mut result = None;
+    for _ in 0..1000 {
+        let (mut budget, policy, mut failed) = state.get_mut(&mut world);
+        queue.extract(
+            &mut budget,
+            ChunkTask::Mesh,
+            &policy,
+            &mut failed,
+            |_, r| {
+                result = Some(r.ok());
+                0
+            },
+        );
+        if result.is_some() {
+            break;
+        }
+        std::thread::yield_now();
+    }
+    assert_eq!(result, Some(Some(1)));
+    // chunks that are never cancelled don't keep a version around
+    assert!(queue.versions.is_empty());
+}
@@ -0,0 +1,149 @@
+use bevy::{
+    math::IVec3,
+    prelude::{ReflectResource, Resource},
+    reflect::Reflect,
+};
+
+use crate::core::*;
+
+/// The seed the world is generated from.
+/// Each chunk is handed a `ChunkRng` made from this and its position,
+/// so a world generates the same whatever order its chunks are generated in
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+#[reflect(Resource)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// The low 32 bits of the seed, for noise that takes a u32 seed
+    pub fn noise_seed(&self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// A small deterministic random number generator (splitmix64) for one chunk.
+/// Made from the `WorldSeed` and the chunks position;
+/// chunks without a position, so without the `spatial` feature or a `ChunkId`,
+/// all get the stream of the chunk at 0, 0, 0
+#[derive(Debug, Clone)]
+pub struct ChunkRng(u64);
+
+impl ChunkRng {
+    pub fn new(seed: WorldSeed, chunk: IVec3) -> Self {
+        Self::with_salt(seed, chunk, 0)
+    }
+
+    /// A separate stream for the same chunk, for generators that need more then one
+    pub fn with_salt(seed: WorldSeed, chunk: IVec3, salt: u64) -> Self {
+        let mut rng = ChunkRng(
+            seed.0
+                ^ (chunk.x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                ^ (chunk.y as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+                ^ (chunk.z as u32 as u64).wrapping_mul(0x1656_67b1_9e37_79f9)
+                ^ salt.wrapping_mul(0xd6e8_feb8_6659_fd93),
+        );
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// A number in 0..1
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A number in -1..1
+    pub fn signed(&mut self) -> f32 {
+        self.unit() * 2. - 1.
+    }
+
+    /// True `chance` of the time
+    pub fn chance(&mut self, chance: f32) -> bool {
+        self.unit() < chance
+    }
+
+    /// A number in `0..max`
+    pub fn below(&mut self, max: u32) -> u32 {
+        debug_assert!(max > 0, "max must be more then 0");
+        ((self.next_u32() as u64 * max as u64) >> 32) as u32
+    }
+}
+
+/// The voxels of a chunk as bytes; the block, rotation and state of every voxel in order.
+/// Two chunks with the same bytes mesh the same
+pub fn chunk_bytes(data: &ChunkData) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(CHUNK_SIZE.volume() as usize * 3);
+    for (x, y, z) in crate::utils::DynBlockIter::new(data.size) {
+        bytes.push(data.get_block_id(x, y, z).map_or(0, |id| id.0));
+        bytes.push(data.rotation(x, y, z).0);
+        bytes.push(data.state(x, y, z));
+    }
+    bytes
+}
+
+/// Generate every chunk sequentially, in reverse and in parallel on the task pool
+/// and check they come out byte for byte the same each time.
+/// Returns the position of the first chunk that differs
+pub fn check_deterministic<T: PhoxelGeneratorData>(
+    generator: &PhoxelGenerator<T>,
+    seed: WorldSeed,
+    chunks: &[(T, IVec3)],
+) -> Result<(), IVec3> {
+    let generate = |(data, chunk): &(T, IVec3)| {
+        chunk_bytes(&generator.generate_now(data.clone(), ChunkRng::new(seed, *chunk)))
+    };
+    let expected = chunks.iter().map(generate).collect::<Vec<_>>();
+    for (index, chunk) in chunks.iter().enumerate().rev() {
+        if generate(chunk) != expected[index] {
+            return Err(chunk.1);
+        }
+    }
+    let pool = bevy::tasks::AsyncComputeTaskPool::get_or_init(bevy::tasks::TaskPool::default);
+    let parallel = pool.scope(|scope| {
+        for chunk in chunks.iter() {
+            scope.spawn(async move { generate(chunk) });
+        }
+    });
+    for (index, bytes) in parallel.into_iter().enumerate() {
+        if bytes != expected[index] {
+            return Err(chunks[index].1);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn same_seed_same_chunks() {
+    use crate::test_utils::Stone;
+
+    let generator = PhoxelGenerator::seeded(|_: (), rng: &mut ChunkRng| {
+        let mut data = ChunkData::empty();
+        for (x, y, z) in crate::utils::DynBlockIter::default() {
+            if rng.chance(0.3) {
+                data.set_block(x, y, z, Stone);
+            }
+        }
+        data
+    });
+    let chunks = (-3..3)
+        .flat_map(|x| (-3..3).map(move |z| ((), IVec3::new(x, 0, z))))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        check_deterministic(&generator, WorldSeed(42), &chunks),
+        Ok(())
+    );
+
+    let a = ChunkRng::new(WorldSeed(42), IVec3::ZERO).next_u64();
+    assert_ne!(a, ChunkRng::new(WorldSeed(43), IVec3::ZERO).next_u64());
+    assert_ne!(a, ChunkRng::new(WorldSeed(42), IVec3::X).next_u64());
+}
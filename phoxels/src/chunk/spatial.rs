@@ -0,0 +1,101 @@
+use bevy::{
+    ecs::{component::HookContext, world::DeferredWorld},
+    math::{IVec3, UVec3},
+    platform::collections::HashMap,
+    prelude::{Component, Deref, Entity, ReflectComponent, Resource, Transform, Visibility},
+    reflect::Reflect,
+};
+
+use super::CHUNK_SIZE;
+
+/// The position of a chunk in chunks; used when a chunks position also uniquely identifies it.
+/// Inserting a ChunkId moves the chunk to its place in the world and adds it to the `ChunkMap`
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deref, Reflect)]
+#[reflect(Component)]
+#[component(
+    immutable,
+    on_insert = ChunkId::on_insert,
+    on_replace = ChunkId::on_replace
+)]
+#[require(Transform, Visibility)]
+pub struct ChunkId(IVec3);
+
+impl ChunkId {
+    pub const fn new(x: i32, y: i32, z: i32) -> Self {
+        ChunkId(IVec3::new(x, y, z))
+    }
+
+    pub const fn from_ivec3(position: IVec3) -> Self {
+        ChunkId(position)
+    }
+
+    /// The chunk that holds the block at the given world position
+    pub fn containing(block: IVec3) -> Self {
+        ChunkId(block.div_euclid(IVec3::splat(CHUNK_SIZE.size() as i32)))
+    }
+
+    /// The chunk that holds the block at the given world position and where in the chunk it is
+    pub fn split(block: IVec3) -> (Self, UVec3) {
+        (
+            Self::containing(block),
+            block
+                .rem_euclid(IVec3::splat(CHUNK_SIZE.size() as i32))
+                .as_uvec3(),
+        )
+    }
+
+    /// The world position of the first block in the chunk
+    pub fn origin(&self) -> IVec3 {
+        self.0 * CHUNK_SIZE.size() as i32
+    }
+
+    fn on_insert(mut world: DeferredWorld, ctx: HookContext) {
+        let id = *world
+            .entity(ctx.entity)
+            .get::<ChunkId>()
+            .expect("on_insert of ChunkId");
+        world
+            .get_mut::<Transform>(ctx.entity)
+            .expect("ChunkId Requires Transform")
+            .translation = id.origin().as_vec3();
+        if let Some(old) = world.resource_mut::<ChunkMap>().0.insert(id.0, ctx.entity) {
+            #[cfg(feature = "log")]
+            bevy::log::warn!("{:?} replaced Chunk({:?}) at {:?}", ctx.entity, old, id);
+            #[cfg(not(feature = "log"))]
+            let _ = old;
+        }
+    }
+
+    fn on_replace(mut world: DeferredWorld, ctx: HookContext) {
+        let id = *world
+            .entity(ctx.entity)
+            .get::<ChunkId>()
+            .expect("on_replace of ChunkId");
+        let mut map = world.resource_mut::<ChunkMap>();
+        if map.0.get(&id.0) == Some(&ctx.entity) {
+            map.0.remove(&id.0);
+        }
+    }
+}
+
+/// Find the chunk entity at a `ChunkId`
+#[derive(Resource, Debug, Default)]
+pub struct ChunkMap(HashMap<IVec3, Entity>);
+
+impl ChunkMap {
+    pub fn get(&self, id: ChunkId) -> Option<Entity> {
+        self.0.get(&id.0).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkId, Entity)> + '_ {
+        self.0.iter().map(|(id, entity)| (ChunkId(*id), *entity))
+    }
+}
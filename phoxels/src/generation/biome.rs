@@ -0,0 +1,335 @@
+use bevy::math::IVec3;
+use noise::{Fbm, MultiFractal, NoiseFn, SuperSimplex};
+
+use crate::core::*;
+
+/// Maps the height noise, which is roughly -1..=1, to a height in blocks.
+/// Made of points sorted by their noise value that are linearly interpolated between
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightCurve(Vec<(f32, f32)>);
+
+impl HeightCurve {
+    /// The same height everywhere
+    pub fn flat(height: f32) -> HeightCurve {
+        HeightCurve(vec![(0., height)])
+    }
+
+    /// `base` at a noise of 0 going up and down by `amplitude`
+    pub fn linear(base: f32, amplitude: f32) -> HeightCurve {
+        HeightCurve(vec![(-1., base - amplitude), (1., base + amplitude)])
+    }
+
+    /// Points of (noise, height), they are sorted by noise
+    pub fn from_points(points: impl Into<Vec<(f32, f32)>>) -> HeightCurve {
+        let mut points = points.into();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        HeightCurve(points)
+    }
+
+    pub fn sample(&self, noise: f32) -> f32 {
+        let Some(first) = self.0.first() else {
+            return 0.;
+        };
+        if noise <= first.0 {
+            return first.1;
+        }
+        for pair in self.0.windows(2) {
+            let [(x0, y0), (x1, y1)] = [pair[0], pair[1]];
+            if noise <= x1 {
+                let t = (noise - x0) / (x1 - x0).max(f32::EPSILON);
+                return y0 + (y1 - y0) * t;
+            }
+        }
+        self.0.last().expect("not empty").1
+    }
+}
+
+/// Something placed on the surface of a biome, like a tree or a rock
+#[derive(Debug, Clone)]
+pub struct Decoration<B: Block> {
+    /// The chance of the decoration being placed on a column, 0..=1
+    pub chance: f32,
+    /// Placed with its origin on the block above the surface
+    pub structure: Structure<B>,
+}
+
+/// A biome is placed where its temperature and humidity are closest to the climate
+#[derive(Debug, Clone)]
+pub struct Biome<B: Block> {
+    pub name: &'static str,
+    /// Where the biome sits on the climate map, roughly -1..=1
+    pub temperature: f32,
+    /// Where the biome sits on the climate map, roughly -1..=1
+    pub humidity: f32,
+    /// The top block of each column
+    pub surface: B,
+    /// The blocks under the surface
+    pub subsurface: B,
+    /// How many subsurface blocks there are under the surface
+    pub subsurface_depth: u32,
+    /// Everything under the subsurface
+    pub base: B,
+    pub height: HeightCurve,
+    pub decorations: Vec<Decoration<B>>,
+    /// The biome tint of its columns, where blocks tinted by `BlockTint::Biome` get their colour
+    pub tint: u8,
+}
+
+impl<B: Block> Biome<B> {
+    /// A biome of only `block` at a height of 0
+    pub fn new(name: &'static str, block: B) -> Self {
+        Biome {
+            name,
+            temperature: 0.,
+            humidity: 0.,
+            surface: block,
+            subsurface: block,
+            subsurface_depth: 0,
+            base: block,
+            height: HeightCurve::flat(0.),
+            decorations: Vec::new(),
+            tint: 0,
+        }
+    }
+
+    pub fn with_climate(mut self, temperature: f32, humidity: f32) -> Self {
+        self.temperature = temperature;
+        self.humidity = humidity;
+        self
+    }
+
+    pub fn with_layers(mut self, surface: B, subsurface: B, subsurface_depth: u32) -> Self {
+        self.surface = surface;
+        self.subsurface = subsurface;
+        self.subsurface_depth = subsurface_depth;
+        self
+    }
+
+    pub fn with_height(mut self, height: HeightCurve) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: u8) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_decoration(mut self, chance: f32, structure: Structure<B>) -> Self {
+        self.decorations.push(Decoration { chance, structure });
+        self
+    }
+
+    /// The block at a depth below the surface, 0 being the surface
+    pub fn block_at_depth(&self, depth: u32) -> B {
+        if depth == 0 {
+            self.surface
+        } else if depth <= self.subsurface_depth {
+            self.subsurface
+        } else {
+            self.base
+        }
+    }
+}
+
+/// The climate and height of a column of the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    pub temperature: f32,
+    pub humidity: f32,
+    /// The index of the closest biome
+    pub biome: usize,
+    /// The height of the surface, blended between biomes
+    pub height: i32,
+}
+
+/// Picks biomes from temperature and humidity noise and blends the heights of neighbouring biomes.
+/// Use `generate` from inside a `PhoxelGenerator` and `decorate` as a `GenerationPipeline` pass
+pub struct BiomeMap<B: Block> {
+    temperature: Fbm<SuperSimplex>,
+    humidity: Fbm<SuperSimplex>,
+    height: Fbm<SuperSimplex>,
+    biomes: Vec<Biome<B>>,
+    /// How far apart in climate biomes are blended over
+    blend: f32,
+    seed: u32,
+}
+
+impl<B: Block> BiomeMap<B> {
+    pub const DEFAULT_CLIMATE_FREQUENCY: f64 = 0.002;
+    pub const DEFAULT_HEIGHT_FREQUENCY: f64 = 0.005;
+
+    pub fn new(seed: u32, biomes: Vec<Biome<B>>) -> Self {
+        assert!(!biomes.is_empty(), "a BiomeMap needs at least one biome");
+        BiomeMap {
+            temperature: Fbm::new(seed).set_frequency(Self::DEFAULT_CLIMATE_FREQUENCY),
+            humidity: Fbm::new(seed.wrapping_add(1)).set_frequency(Self::DEFAULT_CLIMATE_FREQUENCY),
+            height: Fbm::new(seed.wrapping_add(2))
+                .set_frequency(Self::DEFAULT_HEIGHT_FREQUENCY)
+                .set_persistence(0.7),
+            biomes,
+            blend: 0.15,
+            seed,
+        }
+    }
+
+    /// How large biomes are, smaller is larger
+    pub fn with_climate_frequency(mut self, frequency: f64) -> Self {
+        self.temperature = self.temperature.set_frequency(frequency);
+        self.humidity = self.humidity.set_frequency(frequency);
+        self
+    }
+
+    /// How quickly the height changes, smaller is smoother
+    pub fn with_height_frequency(mut self, frequency: f64) -> Self {
+        self.height = self.height.set_frequency(frequency);
+        self
+    }
+
+    /// How far apart in climate biomes are blended over,
+    /// 0 gives cliffs at the edge of every biome
+    pub fn with_blend(mut self, blend: f32) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn biomes(&self) -> &[Biome<B>] {
+        &self.biomes
+    }
+
+    fn climate_distance(biome: &Biome<B>, temperature: f32, humidity: f32) -> f32 {
+        let t = biome.temperature - temperature;
+        let h = biome.humidity - humidity;
+        t * t + h * h
+    }
+
+    /// Get the climate, biome and height of the column at a world position
+    pub fn column(&self, x: i32, z: i32) -> Column {
+        let point = [x as f64, z as f64];
+        let temperature = self.temperature.get(point) as f32;
+        let humidity = self.humidity.get(point) as f32;
+        let height_noise = self.height.get(point) as f32;
+
+        let mut biome = 0;
+        let mut closest = f32::MAX;
+        let mut height = 0.;
+        let mut total = 0.;
+        let spread = 2. * self.blend * self.blend;
+        for (index, candidate) in self.biomes.iter().enumerate() {
+            let distance = Self::climate_distance(candidate, temperature, humidity);
+            if distance < closest {
+                closest = distance;
+                biome = index;
+            }
+            if spread > 0. {
+                let weight = (-distance / spread).exp();
+                height += candidate.height.sample(height_noise) * weight;
+                total += weight;
+            }
+        }
+        // weights can all round to 0 far from every biome
+        let height = if total > f32::EPSILON {
+            height / total
+        } else {
+            self.biomes[biome].height.sample(height_noise)
+        };
+        Column {
+            temperature,
+            humidity,
+            biome,
+            height: height.floor() as i32,
+        }
+    }
+
+    /// Get the biome of the column at a world position
+    pub fn biome_at(&self, x: i32, z: i32) -> &Biome<B> {
+        &self.biomes[self.column(x, z).biome]
+    }
+
+    /// Fill a chunk with the layers of the biomes in it
+    pub fn generate(&self, chunk: ChunkId) -> ChunkData {
+        let mut data = ChunkData::empty();
+        self.fill(chunk, &mut data);
+        data
+    }
+
+    /// Fill the layers of the biomes into chunk data
+    pub fn fill(&self, chunk: ChunkId, data: &mut ChunkData) {
+        let size = CHUNK_SIZE.size() as i32;
+        let origin = chunk.origin();
+        for x in 0..size {
+            for z in 0..size {
+                let column = self.column(origin.x + x, origin.z + z);
+                let biome = &self.biomes[column.biome];
+                data.set_biome_tint(x as u32, z as u32, biome.tint);
+                let top = (column.height - origin.y).min(size - 1);
+                for y in 0..=top {
+                    let depth = (column.height - origin.y - y) as u32;
+                    data.set_block(x as u32, y as u32, z as u32, biome.block_at_depth(depth));
+                }
+            }
+        }
+    }
+
+    /// Place the decorations of the biomes in the center chunk,
+    /// they can reach into the chunks around it
+    pub fn decorate(&self, neighbourhood: &mut Neighbourhood) {
+        let size = CHUNK_SIZE.size() as i32;
+        let origin = neighbourhood.center().origin();
+        for x in 0..size {
+            for z in 0..size {
+                let column = self.column(origin.x + x, origin.z + z);
+                let surface = column.height - origin.y;
+                if !(0..size).contains(&surface) {
+                    continue;
+                }
+                let biome = &self.biomes[column.biome];
+                for (index, decoration) in biome.decorations.iter().enumerate() {
+                    let roll = column_hash(self.seed, origin.x + x, origin.z + z, index as u32);
+                    if (roll as f32 / u32::MAX as f32) >= decoration.chance {
+                        continue;
+                    }
+                    let base = IVec3::new(x, surface + 1, z);
+                    for (offset, block, rotation) in decoration.structure.blocks() {
+                        neighbourhood.set_block_rotated(base + offset, block, rotation);
+                    }
+                    // one decoration per column
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A hash of a column used to place decorations
+pub(crate) fn column_hash(seed: u32, x: i32, z: i32, salt: u32) -> u32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (z as u32).wrapping_mul(0xd816_3841)
+        ^ salt.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+#[test]
+fn biome_heights_blend() {
+    use crate::test_utils::Stone;
+
+    let low = Biome::new("low", Stone)
+        .with_climate(-1., 0.)
+        .with_height(HeightCurve::flat(0.));
+    let high = Biome::new("high", Stone)
+        .with_climate(1., 0.)
+        .with_height(HeightCurve::flat(40.));
+    let map = BiomeMap::new(7, vec![low, high]).with_blend(0.5);
+    // with a wide blend neighbouring columns never jump by more than a few blocks
+    for x in 0..512 {
+        let a = map.column(x, 0).height;
+        let b = map.column(x + 1, 0).height;
+        assert!((a - b).abs() <= 2, "height jumped from {a} to {b} at {x}");
+    }
+    assert_eq!(HeightCurve::linear(10., 5.).sample(0.), 10.);
+}
@@ -0,0 +1,310 @@
+use bevy::math::{IVec3, Vec3};
+use noise::{Fbm, MultiFractal, NoiseFn, SuperSimplex};
+
+use crate::core::*;
+
+/// A density field for a chunk made from coarse samples that are trilinearly interpolated,
+/// much cheaper then sampling noise for every voxel
+pub struct DensityField {
+    step: u32,
+    samples: u32,
+    values: Vec<f64>,
+}
+
+impl DensityField {
+    /// Sample `density` at world positions every `step` blocks across the chunk,
+    /// `step` has to divide the size of the chunk
+    pub fn new(chunk: ChunkId, step: u32, density: impl Fn([f64; 3]) -> f64) -> Self {
+        let size = CHUNK_SIZE.size();
+        assert!(
+            step > 0 && size.is_multiple_of(step),
+            "step {step} does not divide the chunk size {size}"
+        );
+        let samples = size / step + 1;
+        let origin = chunk.origin();
+        let mut values = Vec::with_capacity((samples * samples * samples) as usize);
+        for y in 0..samples {
+            for z in 0..samples {
+                for x in 0..samples {
+                    let position =
+                        origin + (IVec3::new(x as i32, y as i32, z as i32) * step as i32);
+                    values.push(density(position.as_dvec3().to_array()));
+                }
+            }
+        }
+        DensityField {
+            step,
+            samples,
+            values,
+        }
+    }
+
+    fn value(&self, x: u32, y: u32, z: u32) -> f64 {
+        self.values[(y * self.samples * self.samples + z * self.samples + x) as usize]
+    }
+
+    /// Get the interpolated density at a position in the chunk
+    pub fn get(&self, x: u32, y: u32, z: u32) -> f64 {
+        let (x0, y0, z0) = (x / self.step, y / self.step, z / self.step);
+        let (x1, y1, z1) = (
+            (x0 + 1).min(self.samples - 1),
+            (y0 + 1).min(self.samples - 1),
+            (z0 + 1).min(self.samples - 1),
+        );
+        let step = self.step as f64;
+        let tx = (x % self.step) as f64 / step;
+        let ty = (y % self.step) as f64 / step;
+        let tz = (z % self.step) as f64 / step;
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let bottom = lerp(
+            lerp(self.value(x0, y0, z0), self.value(x1, y0, z0), tx),
+            lerp(self.value(x0, y0, z1), self.value(x1, y0, z1), tx),
+            tz,
+        );
+        let top = lerp(
+            lerp(self.value(x0, y1, z0), self.value(x1, y1, z0), tx),
+            lerp(self.value(x0, y1, z1), self.value(x1, y1, z1), tx),
+            tz,
+        );
+        lerp(bottom, top, ty)
+    }
+}
+
+/// Replace the solid blocks of a chunk with `air` where `carve` is true
+fn carve_where(data: &mut ChunkData, air: impl Block, carve: impl Fn(u32, u32, u32) -> bool) {
+    for (x, y, z) in crate::utils::DynBlockIter::default() {
+        if data.block_meta(x, y, z).is_solid() && carve(x, y, z) {
+            data.set_block(x, y, z, air);
+        }
+    }
+}
+
+/// Caves made from 3D noise.
+/// Cheese caves are large caverns where a noise is low,
+/// spaghetti caves are long tunnels where two noises are both close to 0
+pub struct NoiseCaves {
+    cheese: Fbm<SuperSimplex>,
+    /// where the cheese noise is below this it is a cave, roughly -1..=1
+    pub cheese_threshold: f64,
+    spaghetti: [Fbm<SuperSimplex>; 2],
+    /// how close to 0 both spaghetti noises have to be, larger is wider tunnels
+    pub spaghetti_width: f64,
+    /// how far apart noise samples are, see `DensityField`
+    pub step: u32,
+}
+
+impl NoiseCaves {
+    pub fn new(seed: u32) -> Self {
+        NoiseCaves {
+            cheese: Fbm::new(seed).set_frequency(0.02).set_octaves(3),
+            cheese_threshold: -0.45,
+            spaghetti: [
+                Fbm::new(seed.wrapping_add(1))
+                    .set_frequency(0.015)
+                    .set_octaves(2),
+                Fbm::new(seed.wrapping_add(2))
+                    .set_frequency(0.015)
+                    .set_octaves(2),
+            ],
+            spaghetti_width: 0.06,
+            step: 4,
+        }
+    }
+
+    /// Turn off cheese caves
+    pub fn without_cheese(mut self) -> Self {
+        self.cheese_threshold = f64::NEG_INFINITY;
+        self
+    }
+
+    /// Turn off spaghetti caves
+    pub fn without_spaghetti(mut self) -> Self {
+        self.spaghetti_width = 0.;
+        self
+    }
+
+    /// The density at a world position, caves are below 0
+    pub fn density(&self, point: [f64; 3]) -> f64 {
+        let cheese = self.cheese.get(point) - self.cheese_threshold;
+        let spaghetti = self.spaghetti[0]
+            .get(point)
+            .abs()
+            .max(self.spaghetti[1].get(point).abs())
+            - self.spaghetti_width;
+        cheese.min(spaghetti)
+    }
+
+    /// Carve the caves out of the solid blocks of a chunk
+    pub fn carve(&self, chunk: ChunkId, data: &mut ChunkData, air: impl Block) {
+        let field = DensityField::new(chunk, self.step, |point| self.density(point));
+        carve_where(data, air, |x, y, z| field.get(x, y, z) < 0.);
+    }
+}
+
+/// Caves dug by worms that wander from a random point in some chunks.
+/// Worms are seeded by the chunk they start in so they carve the same tunnel
+/// whichever of the chunks they pass through is generated first
+#[derive(Debug, Clone, Copy)]
+pub struct WormCaves {
+    pub seed: u32,
+    /// The chance of a chunk having a worm start in it
+    pub chance: f32,
+    /// How many blocks a worm moves
+    pub length: u32,
+    /// The radius of the tunnel
+    pub radius: f32,
+    /// How much taller then wide the tunnel is
+    pub stretch: f32,
+    /// How much a worm can turn up or down, 0 keeps it level
+    pub pitch: f32,
+}
+
+impl WormCaves {
+    pub fn new(seed: u32) -> Self {
+        WormCaves {
+            seed,
+            chance: 0.15,
+            length: 64,
+            radius: 2.5,
+            stretch: 1.,
+            pitch: 0.3,
+        }
+    }
+
+    /// Long, narrow and very tall level cuts through the ground
+    pub fn ravines(seed: u32) -> Self {
+        WormCaves {
+            seed: seed ^ 0x5a5a_5a5a,
+            chance: 0.01,
+            length: 96,
+            radius: 2.,
+            stretch: 5.,
+            pitch: 0.,
+        }
+    }
+
+    /// Carve every worm that reaches into a chunk out of its solid blocks
+    pub fn carve(&self, chunk: ChunkId, data: &mut ChunkData, air: impl Block) {
+        let size = CHUNK_SIZE.size() as i32;
+        let reach = (self.length as f32 + self.radius * self.stretch) / size as f32;
+        let range = reach.ceil() as i32;
+        let min = chunk.origin().as_vec3();
+        let max = min + Vec3::splat(size as f32);
+        for x in -range..=range {
+            for y in -range..=range {
+                for z in -range..=range {
+                    let start = *chunk + IVec3::new(x, y, z);
+                    self.carve_worm(start, min, max, data, air);
+                }
+            }
+        }
+    }
+
+    fn carve_worm(
+        &self,
+        start: IVec3,
+        min: Vec3,
+        max: Vec3,
+        data: &mut ChunkData,
+        air: impl Block,
+    ) {
+        let mut rng = ChunkRng::with_salt(WorldSeed(self.seed as u64), start, 0x776f_726d);
+        if rng.unit() >= self.chance {
+            return;
+        }
+        let size = CHUNK_SIZE.size() as f32;
+        let mut position =
+            start.as_vec3() * size + Vec3::new(rng.unit(), rng.unit(), rng.unit()) * size;
+        let mut yaw = rng.unit() * std::f32::consts::TAU;
+        let mut pitch = rng.signed() * self.pitch;
+        let reach = Vec3::new(self.radius, self.radius * self.stretch, self.radius);
+        for _ in 0..self.length {
+            position += Vec3::new(
+                yaw.cos() * pitch.cos(),
+                pitch.sin(),
+                yaw.sin() * pitch.cos(),
+            );
+            yaw += rng.signed() * 0.3;
+            pitch = (pitch + rng.signed() * 0.1).clamp(-self.pitch, self.pitch);
+            if (position + reach).cmplt(min).any() || (position - reach).cmpge(max).any() {
+                continue;
+            }
+            let low = (position - reach).max(min).floor() - min;
+            let high = (position + reach).min(max - 1.).ceil() - min;
+            for x in low.x as u32..=high.x as u32 {
+                for y in low.y as u32..=high.y as u32 {
+                    for z in low.z as u32..=high.z as u32 {
+                        let offset = (min + Vec3::new(x as f32, y as f32, z as f32) + 0.5
+                            - position)
+                            / reach;
+                        if offset.length_squared() <= 1. && data.block_meta(x, y, z).is_solid() {
+                            data.set_block(x, y, z, air);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn density_field_interpolates() {
+    let field = DensityField::new(ChunkId::new(1, 0, 0), 4, |[x, y, z]| x + y * 2. + z * 3.);
+    // a linear field is reproduced exactly
+    for (x, y, z) in [(0, 0, 0), (3, 5, 7), (15, 15, 15), (9, 2, 13)] {
+        let expected = (16 + x) as f64 + y as f64 * 2. + z as f64 * 3.;
+        assert!((field.get(x, y, z) - expected).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn worms_line_up_across_chunks() {
+    use crate::test_utils::{Air, Stone};
+
+    let worms = WormCaves {
+        chance: 1.,
+        ..WormCaves::new(3)
+    };
+    let carve = |chunk| {
+        let mut data = ChunkData::solid(Stone);
+        worms.carve(chunk, &mut data, Air);
+        data
+    };
+    let (west, east) = (ChunkId::new(0, 0, 0), ChunkId::new(1, 0, 0));
+    // carve the east chunk first the second time around
+    let first = [carve(west), carve(east)];
+    let second = {
+        let east = carve(east);
+        [carve(west), east]
+    };
+    for (a, b) in first.iter().zip(second.iter()) {
+        assert!(
+            crate::utils::DynBlockIter::default()
+                .all(|(x, y, z)| a.get_block_id(x, y, z) == b.get_block_id(x, y, z))
+        );
+    }
+
+    // the blocks either side of the shared face, where a worm crosses it both are carved
+    // and only the edges of its tunnel are carved on one side
+    let last = CHUNK_SIZE.size() - 1;
+    let carved = |data: &ChunkData, x, y, z| !data.block_meta(x, y, z).is_solid();
+    let (mut both, mut west_only, mut east_only) = (0, 0, 0);
+    for y in 0..=last {
+        for z in 0..=last {
+            match (carved(&first[0], last, y, z), carved(&first[1], 0, y, z)) {
+                (true, true) => both += 1,
+                (true, false) => west_only += 1,
+                (false, true) => east_only += 1,
+                _ => {}
+            }
+        }
+    }
+    assert!(
+        both > 0,
+        "a worm starts in every chunk so one crosses the face"
+    );
+    assert!(
+        both > west_only + east_only,
+        "tunnels do not meet at the face: {both} carved on both sides, {west_only} and {east_only} on one"
+    );
+}
@@ -0,0 +1,474 @@
+use std::sync::{Arc, RwLock};
+
+use bevy::{
+    asset::{Asset, AssetEvent, AssetId, AssetLoader, Assets, Handle, LoadContext, io::Reader},
+    platform::collections::HashMap,
+    prelude::{Entity, EventReader, Query, Res, ResMut, Resource, With},
+    reflect::TypePath,
+};
+use noise::{Fbm, MultiFractal, NoiseFn, SuperSimplex};
+use serde::Deserialize;
+
+use super::BlockInfo;
+use crate::{ChunkGenerator, core::*};
+
+/// A node of a `NoiseGraph`, every node outputs a single number for a point in the world
+#[derive(Debug, Clone, Deserialize)]
+pub enum NoiseNode {
+    Constant(f64),
+    /// The world position of the point on an axis
+    X,
+    Y,
+    Z,
+    /// Fractal simplex noise, roughly -1..=1
+    Noise {
+        seed: u32,
+        frequency: f64,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_persistence")]
+        persistence: f64,
+        #[serde(default = "default_lacunarity")]
+        lacunarity: f64,
+    },
+    /// A node from `nodes` by name
+    Ref(String),
+    Add(Vec<NoiseNode>),
+    Mul(Vec<NoiseNode>),
+    Min(Vec<NoiseNode>),
+    Max(Vec<NoiseNode>),
+    Neg(Box<NoiseNode>),
+    Abs(Box<NoiseNode>),
+    Clamp(Box<NoiseNode>, f64, f64),
+    /// Map the input through points of (input, output) that are linearly interpolated between
+    Spline(Box<NoiseNode>, Vec<(f64, f64)>),
+    /// 1 if the input is above the threshold otherwise 0
+    Threshold(Box<NoiseNode>, f64),
+}
+
+fn default_octaves() -> usize {
+    Fbm::<SuperSimplex>::DEFAULT_OCTAVE_COUNT
+}
+
+fn default_persistence() -> f64 {
+    Fbm::<SuperSimplex>::DEFAULT_PERSISTENCE
+}
+
+fn default_lacunarity() -> f64 {
+    Fbm::<SuperSimplex>::DEFAULT_LACUNARITY
+}
+
+/// How the output of a graph makes terrain
+#[derive(Debug, Clone, Deserialize)]
+pub enum Terrain {
+    /// The height of the surface, evaluated once per column with a `Y` of 0
+    Column(NoiseNode),
+    /// Evaluated for every voxel, solid where it is above 0
+    Density(NoiseNode),
+}
+
+/// The blocks used to fill solid terrain
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Layers {
+    /// The top solid block of each column
+    pub surface: BlockInfo,
+    /// The blocks under the surface
+    pub subsurface: BlockInfo,
+    /// How many subsurface blocks there are under the surface
+    #[serde(default)]
+    pub depth: u32,
+    /// Everything under the subsurface
+    pub base: BlockInfo,
+}
+
+/// A data driven terrain generator loaded from a `.noise.ron` file.
+/// `nodes` can be used from anywhere in the graph with `Ref`
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct NoiseGraph {
+    #[serde(default)]
+    pub nodes: HashMap<String, NoiseNode>,
+    pub terrain: Terrain,
+    pub layers: Layers,
+}
+
+#[derive(Debug)]
+pub enum NoiseGraphError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    UnknownNode(String),
+    /// A node refers back to itself through `Ref`
+    Cycle(String),
+}
+
+impl std::fmt::Display for NoiseGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoiseGraphError::Io(e) => write!(f, "failed to read noise graph: {e}"),
+            NoiseGraphError::Ron(e) => write!(f, "failed to parse noise graph: {e}"),
+            NoiseGraphError::UnknownNode(name) => write!(f, "no node named {name:?}"),
+            NoiseGraphError::Cycle(name) => write!(f, "node {name:?} refers to itself"),
+        }
+    }
+}
+
+impl std::error::Error for NoiseGraphError {}
+
+/// A `NoiseNode` with its references resolved and noise built, ready to be sampled
+#[derive(Clone)]
+enum Compiled {
+    Constant(f64),
+    X,
+    Y,
+    Z,
+    Noise(Box<Fbm<SuperSimplex>>),
+    Add(Vec<Compiled>),
+    Mul(Vec<Compiled>),
+    Min(Vec<Compiled>),
+    Max(Vec<Compiled>),
+    Neg(Box<Compiled>),
+    Abs(Box<Compiled>),
+    Clamp(Box<Compiled>, f64, f64),
+    Spline(Box<Compiled>, Vec<(f64, f64)>),
+    Threshold(Box<Compiled>, f64),
+}
+
+impl Compiled {
+    fn sample(&self, point: [f64; 3]) -> f64 {
+        match self {
+            Compiled::Constant(v) => *v,
+            Compiled::X => point[0],
+            Compiled::Y => point[1],
+            Compiled::Z => point[2],
+            Compiled::Noise(noise) => noise.get(point),
+            Compiled::Add(nodes) => nodes.iter().map(|n| n.sample(point)).sum(),
+            Compiled::Mul(nodes) => nodes.iter().map(|n| n.sample(point)).product(),
+            Compiled::Min(nodes) => nodes
+                .iter()
+                .map(|n| n.sample(point))
+                .fold(f64::INFINITY, f64::min),
+            Compiled::Max(nodes) => nodes
+                .iter()
+                .map(|n| n.sample(point))
+                .fold(f64::NEG_INFINITY, f64::max),
+            Compiled::Neg(node) => -node.sample(point),
+            Compiled::Abs(node) => node.sample(point).abs(),
+            Compiled::Clamp(node, min, max) => node.sample(point).clamp(*min, *max),
+            Compiled::Spline(node, points) => spline(points, node.sample(point)),
+            Compiled::Threshold(node, threshold) => {
+                if node.sample(point) > *threshold {
+                    1.
+                } else {
+                    0.
+                }
+            }
+        }
+    }
+}
+
+fn spline(points: &[(f64, f64)], input: f64) -> f64 {
+    let Some(first) = points.first() else {
+        return input;
+    };
+    if input <= first.0 {
+        return first.1;
+    }
+    for pair in points.windows(2) {
+        let [(x0, y0), (x1, y1)] = [pair[0], pair[1]];
+        if input <= x1 {
+            let t = (input - x0) / (x1 - x0).max(f64::EPSILON);
+            return y0 + (y1 - y0) * t;
+        }
+    }
+    points.last().expect("not empty").1
+}
+
+struct Compiler<'a> {
+    nodes: &'a HashMap<String, NoiseNode>,
+    /// refs that are being compiled, to find cycles
+    stack: Vec<&'a str>,
+}
+
+impl<'a> Compiler<'a> {
+    fn all(&mut self, nodes: &'a [NoiseNode]) -> Result<Vec<Compiled>, NoiseGraphError> {
+        nodes.iter().map(|node| self.compile(node)).collect()
+    }
+
+    fn boxed(&mut self, node: &'a NoiseNode) -> Result<Box<Compiled>, NoiseGraphError> {
+        self.compile(node).map(Box::new)
+    }
+
+    fn compile(&mut self, node: &'a NoiseNode) -> Result<Compiled, NoiseGraphError> {
+        Ok(match node {
+            NoiseNode::Constant(v) => Compiled::Constant(*v),
+            NoiseNode::X => Compiled::X,
+            NoiseNode::Y => Compiled::Y,
+            NoiseNode::Z => Compiled::Z,
+            NoiseNode::Noise {
+                seed,
+                frequency,
+                octaves,
+                persistence,
+                lacunarity,
+            } => Compiled::Noise(Box::new(
+                Fbm::new(*seed)
+                    .set_frequency(*frequency)
+                    .set_octaves(*octaves)
+                    .set_persistence(*persistence)
+                    .set_lacunarity(*lacunarity),
+            )),
+            NoiseNode::Ref(name) => {
+                if self.stack.contains(&name.as_str()) {
+                    return Err(NoiseGraphError::Cycle(name.clone()));
+                }
+                let node = self
+                    .nodes
+                    .get(name)
+                    .ok_or_else(|| NoiseGraphError::UnknownNode(name.clone()))?;
+                self.stack.push(name);
+                let compiled = self.compile(node)?;
+                self.stack.pop();
+                compiled
+            }
+            NoiseNode::Add(nodes) => Compiled::Add(self.all(nodes)?),
+            NoiseNode::Mul(nodes) => Compiled::Mul(self.all(nodes)?),
+            NoiseNode::Min(nodes) => Compiled::Min(self.all(nodes)?),
+            NoiseNode::Max(nodes) => Compiled::Max(self.all(nodes)?),
+            NoiseNode::Neg(node) => Compiled::Neg(self.boxed(node)?),
+            NoiseNode::Abs(node) => Compiled::Abs(self.boxed(node)?),
+            NoiseNode::Clamp(node, min, max) => Compiled::Clamp(self.boxed(node)?, *min, *max),
+            NoiseNode::Spline(node, points) => {
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Compiled::Spline(self.boxed(node)?, points)
+            }
+            NoiseNode::Threshold(node, threshold) => {
+                Compiled::Threshold(self.boxed(node)?, *threshold)
+            }
+        })
+    }
+}
+
+/// A `NoiseGraph` that has been checked and built so it can generate chunks
+#[derive(Clone)]
+pub struct CompiledNoiseGraph {
+    terrain: Compiled,
+    per_voxel: bool,
+    layers: Layers,
+}
+
+impl NoiseGraph {
+    /// Parse a graph from RON
+    pub fn from_ron(ron: &str) -> Result<NoiseGraph, NoiseGraphError> {
+        ron::from_str(ron).map_err(NoiseGraphError::Ron)
+    }
+
+    /// Resolve the references of the graph and build its noise
+    pub fn compile(&self) -> Result<CompiledNoiseGraph, NoiseGraphError> {
+        let mut compiler = Compiler {
+            nodes: &self.nodes,
+            stack: Vec::new(),
+        };
+        let (terrain, per_voxel) = match &self.terrain {
+            Terrain::Column(node) => (compiler.compile(node)?, false),
+            Terrain::Density(node) => (compiler.compile(node)?, true),
+        };
+        Ok(CompiledNoiseGraph {
+            terrain,
+            per_voxel,
+            layers: self.layers,
+        })
+    }
+}
+
+impl CompiledNoiseGraph {
+    /// Sample the terrain node at a world position
+    pub fn sample(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.terrain.sample([x, y, z])
+    }
+
+    fn layer(&self, depth: u32) -> BlockInfo {
+        if depth == 0 {
+            self.layers.surface
+        } else if depth <= self.layers.depth {
+            self.layers.subsurface
+        } else {
+            self.layers.base
+        }
+    }
+
+    pub fn generate(&self, chunk: ChunkId) -> ChunkData {
+        let mut data = ChunkData::empty();
+        let size = CHUNK_SIZE.size() as i32;
+        let origin = chunk.origin();
+        for x in 0..size {
+            for z in 0..size {
+                let (wx, wz) = ((origin.x + x) as f64, (origin.z + z) as f64);
+                if !self.per_voxel {
+                    let height = self.sample(wx, 0., wz).floor() as i32;
+                    for y in 0..=(height - origin.y).min(size - 1) {
+                        let depth = (height - origin.y - y) as u32;
+                        data.set_block(x as u32, y as u32, z as u32, self.layer(depth));
+                    }
+                    continue;
+                }
+                // look above the chunk so the surface is known at its top
+                let mut depth = None;
+                for y in (0..size + self.layers.depth as i32 + 1).rev() {
+                    let solid = self.sample(wx, (origin.y + y) as f64, wz) > 0.;
+                    depth = solid.then(|| depth.map_or(0, |d| d + 1));
+                    if let (Some(depth), true) = (depth, y < size) {
+                        data.set_block(x as u32, y as u32, z as u32, self.layer(depth));
+                    }
+                }
+            }
+        }
+        data
+    }
+}
+
+#[derive(Default)]
+pub struct NoiseGraphLoader;
+
+impl AssetLoader for NoiseGraphLoader {
+    type Asset = NoiseGraph;
+    type Settings = ();
+    type Error = NoiseGraphError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<NoiseGraph, NoiseGraphError> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(NoiseGraphError::Io)?;
+        let graph: NoiseGraph = ron::de::from_bytes(&bytes).map_err(NoiseGraphError::Ron)?;
+        // catch bad references when the file is loaded rather then when generating
+        graph.compile()?;
+        Ok(graph)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["noise.ron"]
+    }
+}
+
+/// Generates chunks from a `NoiseGraph` asset.
+/// Insert this as a resource and use `generator` as the `PhoxelGenerator`;
+/// chunks are regenerated when the graph loads or changes, so editing the file
+/// with the `file_watcher` feature of bevy hot reloads the terrain
+#[derive(Resource, Clone)]
+pub struct NoiseGraphGenerator {
+    handle: Handle<NoiseGraph>,
+    compiled: Arc<RwLock<Option<CompiledNoiseGraph>>>,
+}
+
+impl NoiseGraphGenerator {
+    pub fn new(handle: Handle<NoiseGraph>) -> Self {
+        NoiseGraphGenerator {
+            handle,
+            compiled: Arc::default(),
+        }
+    }
+
+    pub fn handle(&self) -> &Handle<NoiseGraph> {
+        &self.handle
+    }
+
+    /// Chunks generated before the graph has loaded are empty
+    pub fn generator(&self) -> PhoxelGenerator<ChunkId> {
+        let compiled = self.compiled.clone();
+        PhoxelGenerator::new(move |chunk: ChunkId| {
+            match compiled.read().expect("noise graph lock poisoned").as_ref() {
+                Some(graph) => graph.generate(chunk),
+                None => ChunkData::empty(),
+            }
+        })
+    }
+
+    fn is_for(&self, id: AssetId<NoiseGraph>) -> bool {
+        self.handle.id() == id
+    }
+}
+
+/// Rebuild the graph of the `NoiseGraphGenerator` when it changes and regenerate every chunk
+pub(super) fn reload_noise_graphs(
+    generator: Option<Res<NoiseGraphGenerator>>,
+    mut events: EventReader<AssetEvent<NoiseGraph>>,
+    graphs: Res<Assets<NoiseGraph>>,
+    mut chunk_generator: ResMut<ChunkGenerator>,
+    chunks: Query<Entity, With<ChunkId>>,
+) {
+    let Some(generator) = generator else {
+        events.clear();
+        return;
+    };
+    let mut changed = false;
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        changed |= generator.is_for(*id);
+    }
+    if !changed {
+        return;
+    }
+    let Some(graph) = graphs.get(&generator.handle) else {
+        return;
+    };
+    let compiled = match graph.compile() {
+        Ok(compiled) => compiled,
+        Err(_e) => {
+            #[cfg(feature = "log")]
+            bevy::log::error!("Failed to build noise graph: {}", _e);
+            return;
+        }
+    };
+    *generator
+        .compiled
+        .write()
+        .expect("noise graph lock poisoned") = Some(compiled);
+    #[cfg(feature = "log")]
+    bevy::log::info!("Noise graph changed, regenerating chunks");
+    for chunk in chunks.iter() {
+        chunk_generator.add_to_queue(chunk);
+    }
+}
+
+#[test]
+fn graph_from_ron() {
+    let graph = NoiseGraph::from_ron(
+        r#"(
+            nodes: {
+                "hills": Noise(seed: 1, frequency: 0.01),
+                "height": Add([Constant(4.0), Mul([Ref("hills"), Constant(2.0)])]),
+            },
+            terrain: Column(Clamp(Ref("height"), 2.0, 6.0)),
+            layers: (
+                surface: (id: 3),
+                subsurface: (id: 2),
+                depth: 1,
+                base: (id: 1),
+            ),
+        )"#,
+    )
+    .expect("parses");
+    let compiled = graph.compile().expect("compiles");
+    let chunk = compiled.generate(ChunkId::new(0, 0, 0));
+    for x in 0..16 {
+        for z in 0..16 {
+            assert!(chunk.get_block_id(x, 2, z).is_some_and(|b| b != 0));
+            assert!(chunk.get_block_id(x, 7, z).is_some_and(|b| b == 0));
+        }
+    }
+
+    let mut cycle = graph.clone();
+    cycle
+        .nodes
+        .insert("hills".into(), NoiseNode::Ref("height".into()));
+    assert!(matches!(cycle.compile(), Err(NoiseGraphError::Cycle(_))));
+}
@@ -0,0 +1,58 @@
+use bevy::{
+    app::{App, Plugin, Update},
+    asset::AssetApp,
+    ecs::schedule::IntoScheduleConfigs,
+};
+use serde::Deserialize;
+
+use crate::core::*;
+
+mod biome;
+mod caves;
+mod graph;
+
+pub use biome::{Biome, BiomeMap, Column, Decoration, HeightCurve};
+pub use caves::{DensityField, NoiseCaves, WormCaves};
+pub use graph::{
+    CompiledNoiseGraph, Layers, NoiseGraph, NoiseGraphError, NoiseGraphGenerator, NoiseGraphLoader,
+    NoiseNode, Terrain,
+};
+
+/// A block described by data rather then a type, for generators loaded from files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct BlockInfo {
+    pub id: u8,
+    #[serde(default = "solid")]
+    pub solid: bool,
+    #[serde(default)]
+    pub transparent: bool,
+}
+
+fn solid() -> bool {
+    true
+}
+
+impl Block for BlockInfo {
+    fn id(&self) -> u8 {
+        self.id
+    }
+    fn is_solid(&self) -> bool {
+        self.solid
+    }
+    fn is_transparent(&self) -> bool {
+        self.transparent
+    }
+}
+
+pub(crate) struct GenerationPlugin;
+
+impl Plugin for GenerationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<NoiseGraph>()
+            .init_asset_loader::<NoiseGraphLoader>()
+            .add_systems(
+                Update,
+                graph::reload_noise_graphs.before(ChunkSets::Generate),
+            );
+    }
+}
@@ -0,0 +1,518 @@
+use bevy::{
+    asset::{load_internal_asset, weak_handle},
+    core_pipeline::core_3d::graph::{Core3d, Node3d},
+    ecs::query::QueryItem,
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+    render::{
+        Render, RenderApp, RenderSet,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries,
+            CachedComputePipelineId, ComputePassDescriptor, ComputePipelineDescriptor, Extent3d,
+            PipelineCache, ShaderStages, StorageTextureAccess, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor,
+            UniformBuffer,
+            binding_types::{
+                storage_buffer_read_only_sized, storage_buffer_sized, texture_2d,
+                texture_2d_multisampled, texture_depth_2d, texture_storage_2d, uniform_buffer,
+            },
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        view::{ExtractedView, ViewDepthTexture},
+    },
+};
+
+use super::render::{ChunkBuffers, prepare_indirect_buffers};
+
+/// Drives the culling shader, the depth pyramid is built by `voxel_depth_pyramid.wgsl`
+pub const CULL_SHADER: Handle<Shader> = weak_handle!("2b8f6e1d-93c4-4d57-a0e2-6f4b1c7d8e35");
+pub const DEPTH_PYRAMID_SHADER: Handle<Shader> =
+    weak_handle!("c5d1a9f3-7e26-4b80-9f4c-3a8e2d6b1f70");
+
+/// Add to a camera to cull the chunks drawn by `IndirectChunks` on the gpu.
+/// Each frame a compute pass tests every chunk Bevy didn't frustum cull against the frustum
+/// and against a depth pyramid built from the depth of the frame before,
+/// chunks that are hidden are left in the indirect draw with no instances.
+/// A chunk that comes out from behind something can be missing for the first frame it is seen.
+/// The pyramid is built by sampling the depth texture of the camera,
+/// so `TEXTURE_BINDING` is added to the `depth_texture_usages` of its `Camera3d`
+#[derive(Component, Clone, Copy, Debug, Default, ExtractComponent)]
+pub struct ChunkOcclusionCulling;
+
+use cull_uniform::CullUniform;
+
+// encase emits an unused `check` fn beside the struct for every field,
+// keeping the uniform in its own module lets that be allowed without hiding dead code in the culling
+#[allow(dead_code)]
+mod cull_uniform {
+    use bevy::{
+        math::{Mat4, UVec2},
+        render::render_resource::ShaderType,
+    };
+
+    #[derive(Clone, Copy, Debug, Default, ShaderType)]
+    pub(super) struct CullUniform {
+        pub(super) clip_from_world: Mat4,
+        /// the view the depth pyramid was built from
+        pub(super) previous_clip_from_world: Mat4,
+        pub(super) pyramid_size: UVec2,
+        pub(super) mip_count: u32,
+        pub(super) draw_count: u32,
+        /// zero until there is a depth pyramid to test against
+        pub(super) occlusion: u32,
+    }
+}
+
+/// The depth pyramid of a view and everything needed to cull its chunks
+struct ViewChunkCull {
+    size: UVec2,
+    mip_count: u32,
+    /// every mip, read by the culling pass
+    all_mips: TextureView,
+    /// one view per mip, written by the pass that builds it
+    mips: Vec<TextureView>,
+    /// reads each mip to write the next
+    mip_bind_groups: Vec<BindGroup>,
+    depth_bind_group: Option<BindGroup>,
+    cull_bind_group: Option<BindGroup>,
+    uniform: UniformBuffer<CullUniform>,
+    /// the part of the depth texture the view draws to, the origin then the size
+    viewport: UniformBuffer<UVec4>,
+    previous_clip_from_world: Option<Mat4>,
+    draw_count: u32,
+    multisampled: bool,
+}
+
+impl ViewChunkCull {
+    fn new(device: &RenderDevice, pipelines: &ChunkCullPipelines, size: UVec2) -> Self {
+        let mip_count = 32 - size.x.max(size.y).leading_zeros();
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("phoxels_chunk_depth_pyramid"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let all_mips = texture.create_view(&TextureViewDescriptor::default());
+        let mips = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mip_bind_groups = mips
+            .windows(2)
+            .map(|mips| {
+                device.create_bind_group(
+                    "phoxels_chunk_depth_pyramid_mip",
+                    &pipelines.pyramid_layout,
+                    &BindGroupEntries::sequential((&mips[0], &mips[1])),
+                )
+            })
+            .collect();
+        ViewChunkCull {
+            size,
+            mip_count,
+            all_mips,
+            mips,
+            mip_bind_groups,
+            depth_bind_group: None,
+            cull_bind_group: None,
+            uniform: UniformBuffer::default(),
+            viewport: UniformBuffer::default(),
+            previous_clip_from_world: None,
+            draw_count: 0,
+            multisampled: false,
+        }
+    }
+}
+
+/// The depth pyramid samples the depth texture, which isn't a texture binding by default
+fn configure_depth_texture_usages(mut cameras: Query<&mut Camera3d, With<ChunkOcclusionCulling>>) {
+    for mut camera in &mut cameras {
+        let usages = TextureUsages::from(camera.depth_texture_usages);
+        if !usages.contains(TextureUsages::TEXTURE_BINDING) {
+            camera.depth_texture_usages = (usages | TextureUsages::TEXTURE_BINDING).into();
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct ChunkCullViews(HashMap<Entity, ViewChunkCull>);
+
+#[derive(Resource)]
+struct ChunkCullPipelines {
+    cull_layout: BindGroupLayout,
+    depth_layout: BindGroupLayout,
+    depth_multisampled_layout: BindGroupLayout,
+    pyramid_layout: BindGroupLayout,
+    cull: CachedComputePipelineId,
+    depth: CachedComputePipelineId,
+    depth_multisampled: CachedComputePipelineId,
+    pyramid: CachedComputePipelineId,
+}
+
+impl FromWorld for ChunkCullPipelines {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let cull_layout = device.create_bind_group_layout(
+            "phoxels_chunk_cull_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<CullUniform>(false),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+            ),
+        );
+        let pyramid_output =
+            texture_storage_2d(TextureFormat::R32Float, StorageTextureAccess::WriteOnly);
+        let depth_layout = device.create_bind_group_layout(
+            "phoxels_chunk_depth_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    texture_depth_2d(),
+                    pyramid_output,
+                    uniform_buffer::<UVec4>(false),
+                ),
+            ),
+        );
+        let depth_multisampled_layout = device.create_bind_group_layout(
+            "phoxels_chunk_depth_multisampled_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    texture_2d_multisampled(TextureSampleType::Depth),
+                    pyramid_output,
+                    uniform_buffer::<UVec4>(false),
+                ),
+            ),
+        );
+        let pyramid_layout = device.create_bind_group_layout(
+            "phoxels_chunk_depth_pyramid_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    pyramid_output,
+                ),
+            ),
+        );
+
+        let cache = world.resource::<PipelineCache>();
+        let downsample = |label: &'static str, layout: &BindGroupLayout, shader_defs: Vec<&str>| {
+            cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(label.into()),
+                layout: vec![layout.clone()],
+                push_constant_ranges: vec![],
+                shader: DEPTH_PYRAMID_SHADER,
+                shader_defs: shader_defs.into_iter().map(Into::into).collect(),
+                entry_point: "downsample".into(),
+                zero_initialize_workgroup_memory: false,
+            })
+        };
+        let depth = downsample("phoxels_chunk_depth", &depth_layout, vec!["DEPTH_INPUT"]);
+        let depth_multisampled = downsample(
+            "phoxels_chunk_depth_multisampled",
+            &depth_multisampled_layout,
+            vec!["DEPTH_INPUT", "MULTISAMPLED"],
+        );
+        let pyramid = downsample("phoxels_chunk_depth_pyramid", &pyramid_layout, vec![]);
+        let cull = cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("phoxels_chunk_cull".into()),
+            layout: vec![cull_layout.clone()],
+            push_constant_ranges: vec![],
+            shader: CULL_SHADER,
+            shader_defs: vec![],
+            entry_point: "cull".into(),
+            zero_initialize_workgroup_memory: false,
+        });
+
+        ChunkCullPipelines {
+            cull_layout,
+            depth_layout,
+            depth_multisampled_layout,
+            pyramid_layout,
+            cull,
+            depth,
+            depth_multisampled,
+            pyramid,
+        }
+    }
+}
+
+fn prepare_chunk_culling(
+    mut cull_views: ResMut<ChunkCullViews>,
+    pipelines: Res<ChunkCullPipelines>,
+    buffers: Res<ChunkBuffers>,
+    views: Query<(Entity, &ExtractedView, &Msaa), With<ChunkOcclusionCulling>>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    let mut seen = HashSet::new();
+    for (entity, view, msaa) in &views {
+        seen.insert(entity);
+        // half the size of the viewport, the first mip already takes the farthest of 2x2
+        let size = (view.viewport.zw() / 2).max(UVec2::ONE);
+        let cull = cull_views
+            .0
+            .entry(entity)
+            .or_insert_with(|| ViewChunkCull::new(&device, &pipelines, size));
+        if cull.size != size {
+            // the depth of last frame is no use at another size
+            *cull = ViewChunkCull::new(&device, &pipelines, size);
+        }
+
+        let clip_from_world = view.clip_from_world.unwrap_or_else(|| {
+            view.clip_from_view * view.world_from_view.compute_matrix().inverse()
+        });
+        cull.draw_count = buffers.draws(entity).map_or(0, |draws| draws.len() as u32);
+        cull.multisampled = msaa.samples() > 1;
+        cull.uniform.set(CullUniform {
+            clip_from_world,
+            previous_clip_from_world: cull.previous_clip_from_world.unwrap_or(clip_from_world),
+            pyramid_size: size,
+            mip_count: cull.mip_count,
+            draw_count: cull.draw_count,
+            occlusion: cull.previous_clip_from_world.is_some() as u32,
+        });
+        cull.uniform.write_buffer(&device, &queue);
+        cull.viewport.set(view.viewport);
+        cull.viewport.write_buffer(&device, &queue);
+        cull.previous_clip_from_world = Some(clip_from_world);
+    }
+    cull_views.0.retain(|view, _| seen.contains(view));
+}
+
+fn prepare_chunk_cull_bind_groups(
+    mut cull_views: ResMut<ChunkCullViews>,
+    pipelines: Res<ChunkCullPipelines>,
+    buffers: Res<ChunkBuffers>,
+    views: Query<(Entity, &ViewDepthTexture), With<ChunkOcclusionCulling>>,
+    device: Res<RenderDevice>,
+) {
+    for (entity, depth) in &views {
+        let Some(cull) = cull_views.0.get_mut(&entity) else {
+            continue;
+        };
+        let depth_layout = if cull.multisampled {
+            &pipelines.depth_multisampled_layout
+        } else {
+            &pipelines.depth_layout
+        };
+        cull.depth_bind_group = cull.viewport.binding().map(|viewport| {
+            device.create_bind_group(
+                "phoxels_chunk_depth",
+                depth_layout,
+                &BindGroupEntries::sequential((depth.view(), &cull.mips[0], viewport)),
+            )
+        });
+        cull.cull_bind_group = match (
+            cull.uniform.binding(),
+            buffers.draws(entity).and_then(|draws| draws.buffer()),
+            buffers.transforms(),
+            buffers.bounds(),
+        ) {
+            (Some(uniform), Some(draws), Some(transforms), Some(bounds)) => {
+                Some(device.create_bind_group(
+                    "phoxels_chunk_cull",
+                    &pipelines.cull_layout,
+                    &BindGroupEntries::sequential((
+                        uniform,
+                        draws.as_entire_binding(),
+                        transforms.as_entire_binding(),
+                        bounds.as_entire_binding(),
+                        &cull.all_mips,
+                    )),
+                ))
+            }
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct ChunkCullLabel;
+
+/// Sets the instance count of each draw of the view to 0 or 1
+#[derive(Default)]
+struct ChunkCullNode;
+
+impl ViewNode for ChunkCullNode {
+    type ViewQuery = &'static ChunkOcclusionCulling;
+
+    fn run<'w>(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        _: QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let pipelines = world.resource::<ChunkCullPipelines>();
+        let Some(cull) = world
+            .resource::<ChunkCullViews>()
+            .0
+            .get(&graph.view_entity())
+        else {
+            return Ok(());
+        };
+        let (Some(pipeline), Some(bind_group)) = (
+            world
+                .resource::<PipelineCache>()
+                .get_compute_pipeline(pipelines.cull),
+            cull.cull_bind_group.as_ref(),
+        ) else {
+            return Ok(());
+        };
+        if cull.draw_count == 0 {
+            return Ok(());
+        }
+
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("phoxels_chunk_cull"),
+                    timestamp_writes: None,
+                });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(cull.draw_count.div_ceil(64), 1, 1);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct ChunkDepthPyramidLabel;
+
+/// Builds the depth pyramid the next frame is culled against, from the depth of the opaque pass
+#[derive(Default)]
+struct ChunkDepthPyramidNode;
+
+impl ViewNode for ChunkDepthPyramidNode {
+    type ViewQuery = &'static ChunkOcclusionCulling;
+
+    fn run<'w>(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        _: QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let pipelines = world.resource::<ChunkCullPipelines>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(cull) = world
+            .resource::<ChunkCullViews>()
+            .0
+            .get(&graph.view_entity())
+        else {
+            return Ok(());
+        };
+        let depth_pipeline = if cull.multisampled {
+            pipelines.depth_multisampled
+        } else {
+            pipelines.depth
+        };
+        let (Some(depth_pipeline), Some(pyramid_pipeline), Some(depth_bind_group)) = (
+            pipeline_cache.get_compute_pipeline(depth_pipeline),
+            pipeline_cache.get_compute_pipeline(pipelines.pyramid),
+            cull.depth_bind_group.as_ref(),
+        ) else {
+            return Ok(());
+        };
+
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("phoxels_chunk_depth_pyramid"),
+                    timestamp_writes: None,
+                });
+        pass.set_pipeline(depth_pipeline);
+        pass.set_bind_group(0, depth_bind_group, &[]);
+        pass.dispatch_workgroups(cull.size.x.div_ceil(8), cull.size.y.div_ceil(8), 1);
+        pass.set_pipeline(pyramid_pipeline);
+        for (mip, bind_group) in cull.mip_bind_groups.iter().enumerate() {
+            let size = (cull.size >> (mip as u32 + 1)).max(UVec2::ONE);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(size.x.div_ceil(8), size.y.div_ceil(8), 1);
+        }
+        Ok(())
+    }
+}
+
+pub(super) struct ChunkCullPlugin;
+
+impl Plugin for ChunkCullPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, CULL_SHADER, "../voxel_cull.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            DEPTH_PYRAMID_SHADER,
+            "../voxel_depth_pyramid.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugins(ExtractComponentPlugin::<ChunkOcclusionCulling>::default())
+            .add_systems(PostUpdate, configure_depth_texture_usages);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .init_resource::<ChunkCullViews>()
+            .add_systems(
+                Render,
+                (
+                    prepare_chunk_culling
+                        .in_set(RenderSet::PrepareResources)
+                        .after(prepare_indirect_buffers),
+                    prepare_chunk_cull_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<ChunkCullNode>>(Core3d, ChunkCullLabel)
+            .add_render_graph_node::<ViewNodeRunner<ChunkDepthPyramidNode>>(
+                Core3d,
+                ChunkDepthPyramidLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::StartMainPass,
+                    ChunkCullLabel,
+                    Node3d::MainOpaquePass,
+                ),
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::MainOpaquePass,
+                    ChunkDepthPyramidLabel,
+                    Node3d::MainTransmissivePass,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<ChunkCullPipelines>();
+        }
+    }
+}
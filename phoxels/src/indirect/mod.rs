@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use bevy::{
+    asset::{AssetId, Assets, Handle, load_internal_asset, weak_handle},
+    ecs::schedule::IntoScheduleConfigs,
+    prelude::*,
+    render::{
+        Render, RenderApp, RenderSet,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::{Indices, Mesh, VertexAttributeValues},
+        render_phase::AddRenderCommand,
+        render_resource::SpecializedRenderPipelines,
+        renderer::RenderDevice,
+        settings::WgpuFeatures,
+        sync_world::SyncToRenderWorld,
+        view::{self, VisibilityClass},
+    },
+};
+
+use crate::simple_shader::{BLOCK_DATA, BLOCK_EXTRA, VoxelMaterial, VoxelMaterialKey};
+
+mod cull;
+mod render;
+mod slab;
+
+pub use cull::{CULL_SHADER, ChunkOcclusionCulling, DEPTH_PYRAMID_SHADER};
+pub use slab::{Slab, SlabAllocator};
+
+/// Draws the chunks that use the indirect renderer, everything else about a chunk is the same
+pub const INDIRECT_SHADER: Handle<Shader> = weak_handle!("7a0e5b7c-2f6d-4a43-9d0c-5b1c8e3f6a21");
+
+/// Insert to draw every chunk with one multi draw indirect call instead of a `Mesh3d` each.
+/// Chunks get a `ChunkMesh` rather then a `Mesh3d`, their meshes are packed into shared buffers.
+/// Only `VoxelLighting::Simple` is supported and chunks don't cast shadows.
+/// Needs the `INDIRECT_FIRST_INSTANCE` gpu feature, without it this is removed when inserted
+/// and chunks get a `Mesh3d` as normal. `MULTI_DRAW_INDIRECT` is used when there is one.
+/// Cameras with `ChunkOcclusionCulling` also cull the chunks on the gpu
+#[derive(Resource, Clone)]
+pub struct IndirectChunks {
+    pub material: Handle<VoxelMaterial>,
+}
+
+/// The mesh of a chunk drawn by `IndirectChunks`, inserted in place of a `Mesh3d`.
+/// `ChunkData` is still the source of truth, this is remade whenever the chunk is meshed.
+/// Chunks are culled by their `Aabb`, which `ChunkData` requires
+#[derive(Component, Clone, Debug)]
+#[require(VisibilityClass, SyncToRenderWorld)]
+#[component(on_add = view::add_visibility_class::<ChunkMesh>)]
+pub struct ChunkMesh(pub(crate) Arc<ChunkMeshData>);
+
+#[derive(Debug, Default)]
+pub(crate) struct ChunkMeshData {
+    pub positions: Vec<u32>,
+    /// the same length as `positions`, zeros when the chunk has no extra data
+    pub extras: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    /// Take the packed vertices and indices out of a chunk mesh made by phoxels,
+    /// returns None if the mesh has no `BLOCK_DATA`
+    pub fn from_mesh(mesh: &Mesh) -> Option<ChunkMesh> {
+        let Some(VertexAttributeValues::Uint32(positions)) = mesh.attribute(BLOCK_DATA) else {
+            return None;
+        };
+        let extras = match mesh.attribute(BLOCK_EXTRA) {
+            Some(VertexAttributeValues::Uint32(extras)) => extras.clone(),
+            _ => vec![0; positions.len()],
+        };
+        let indices = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            Some(Indices::U16(indices)) => indices.iter().map(|i| *i as u32).collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        Some(ChunkMesh(Arc::new(ChunkMeshData {
+            positions: positions.clone(),
+            extras,
+            indices,
+        })))
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.0.positions.len()
+    }
+
+    pub fn index_count(&self) -> usize {
+        self.0.indices.len()
+    }
+}
+
+/// The entity the draw of all the chunks is queued for, it is never culled
+#[derive(Component, Clone, ExtractComponent)]
+#[require(VisibilityClass, Visibility, Transform, SyncToRenderWorld)]
+#[component(on_add = view::add_visibility_class::<IndirectChunkBatch>)]
+struct IndirectChunkBatch;
+
+/// The material of `IndirectChunks` and the parts of it that change the pipeline
+#[derive(Resource, Clone, Copy)]
+struct ExtractedIndirectMaterial {
+    id: AssetId<VoxelMaterial>,
+    key: VoxelMaterialKey,
+}
+
+pub(crate) struct IndirectPlugin;
+
+impl Plugin for IndirectPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            INDIRECT_SHADER,
+            "../voxel_indirect.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugins((
+            ExtractComponentPlugin::<IndirectChunkBatch>::default(),
+            cull::ChunkCullPlugin,
+        ))
+        .add_systems(Startup, |mut commands: Commands| {
+            commands.spawn((Name::new("Indirect Chunks"), IndirectChunkBatch));
+        })
+        .add_systems(
+            First,
+            refuse_unsupported_indirect.run_if(resource_added::<IndirectChunks>),
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .init_resource::<render::ChunkBuffers>()
+            .init_resource::<SpecializedRenderPipelines<render::IndirectChunkPipeline>>()
+            .add_render_command::<bevy::core_pipeline::core_3d::Opaque3d, render::DrawIndirectChunks>()
+            .add_systems(
+                ExtractSchedule,
+                (extract_indirect_material, render::extract_chunk_meshes),
+            )
+            .add_systems(
+                Render,
+                (
+                    render::upload_chunk_meshes.in_set(RenderSet::PrepareAssets),
+                    render::queue_indirect_chunks.in_set(RenderSet::Queue),
+                    render::prepare_indirect_buffers.in_set(RenderSet::PrepareResources),
+                    render::prepare_chunk_bind_group.in_set(RenderSet::PrepareBindGroups),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<render::IndirectChunkPipeline>();
+        }
+    }
+}
+
+/// Without `INDIRECT_FIRST_INSTANCE` every chunk would be drawn at the first chunks transform,
+/// so fall back to a `Mesh3d` per chunk
+fn refuse_unsupported_indirect(mut commands: Commands, device: Option<Res<RenderDevice>>) {
+    if device.is_none_or(|device| {
+        device
+            .features()
+            .contains(WgpuFeatures::INDIRECT_FIRST_INSTANCE)
+    }) {
+        return;
+    }
+    #[cfg(feature = "log")]
+    bevy::log::warn!(
+        "IndirectChunks needs the INDIRECT_FIRST_INSTANCE gpu feature, chunks will use a Mesh3d instead"
+    );
+    commands.remove_resource::<IndirectChunks>();
+}
+
+fn extract_indirect_material(
+    mut commands: Commands,
+    indirect: bevy::render::Extract<Option<Res<IndirectChunks>>>,
+    materials: bevy::render::Extract<Res<Assets<VoxelMaterial>>>,
+) {
+    let Some(material) = indirect
+        .as_ref()
+        .and_then(|indirect| Some((indirect.material.id(), materials.get(&indirect.material)?)))
+    else {
+        commands.remove_resource::<ExtractedIndirectMaterial>();
+        return;
+    };
+    commands.insert_resource(ExtractedIndirectMaterial {
+        id: material.0,
+        key: material.1.into(),
+    });
+}
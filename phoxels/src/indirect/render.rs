@@ -0,0 +1,670 @@
+use std::sync::Arc;
+
+use bevy::{
+    core_pipeline::{
+        core_3d::{CORE_3D_DEPTH_FORMAT, Opaque3d, Opaque3dBatchSetKey, Opaque3dBinKey},
+        prepass::{DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass},
+    },
+    ecs::{
+        component::Tick,
+        query::ROQueryItem,
+        system::{SystemParamItem, lifetimeless::SRes},
+    },
+    image::BevyDefault,
+    pbr::{
+        MaterialBindGroupAllocator, MaterialPipeline, MeshPipeline, MeshPipelineKey,
+        MeshPipelineViewLayoutKey, PreparedMaterial, SetMeshViewBindGroup,
+    },
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+    render::{
+        Extract,
+        primitives::Aabb,
+        render_asset::RenderAssets,
+        render_phase::{
+            BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem, RenderCommand,
+            RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+        },
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
+            BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites,
+            CommandEncoderDescriptor, CompareFunction, DepthStencilState, Face, FragmentState,
+            IndexFormat, MultisampleState, PipelineCache, PrimitiveState, RawBufferVec,
+            RenderPipelineDescriptor, ShaderStages, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureFormat, VertexAttribute, VertexBufferLayout,
+            VertexFormat, VertexState, VertexStepMode, binding_types::storage_buffer_read_only,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        settings::WgpuFeatures,
+        sync_world::MainEntity,
+        view::{ExtractedView, RenderVisibleEntities, ViewTarget},
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+use super::{
+    ChunkMesh, ChunkMeshData, ExtractedIndirectMaterial, INDIRECT_SHADER, IndirectChunkBatch,
+    slab::{Slab, SlabAllocator},
+};
+use crate::simple_shader::{VoxelMaterial, VoxelMaterialKey};
+
+/// The arguments of one indexed indirect draw, laid out like wgpu expects
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct ChunkDrawArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    /// the slot of the chunk in the transform buffer
+    first_instance: u32,
+}
+
+/// The local bounds of a chunk for the culling pass, indexed by the slot of the chunk
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct ChunkBounds {
+    center: Vec4,
+    half_extents: Vec4,
+}
+
+impl From<&Aabb> for ChunkBounds {
+    fn from(aabb: &Aabb) -> Self {
+        ChunkBounds {
+            center: Vec3::from(aabb.center).extend(1.),
+            half_extents: Vec3::from(aabb.half_extents).extend(0.),
+        }
+    }
+}
+
+/// A buffer of u32 that grows as the slabs in it do, keeping what was written to it
+struct PoolBuffer {
+    buffer: Option<Buffer>,
+    capacity: u32,
+    usage: BufferUsages,
+    label: &'static str,
+}
+
+impl PoolBuffer {
+    const MIN_CAPACITY: u32 = 1 << 16;
+
+    fn new(label: &'static str, usage: BufferUsages) -> Self {
+        PoolBuffer {
+            buffer: None,
+            capacity: 0,
+            usage: usage | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            label,
+        }
+    }
+
+    /// Make sure the buffer can hold `len` elements, copying the old contents over when it grows
+    fn reserve(&mut self, len: u32, device: &RenderDevice, queue: &RenderQueue) {
+        if len <= self.capacity {
+            return;
+        }
+        let capacity = len.next_power_of_two().max(Self::MIN_CAPACITY);
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some(self.label),
+            size: capacity as u64 * 4,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+        if let Some(old) = self.buffer.take() {
+            // writes queued to the old buffer land before this copy runs
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some(self.label),
+            });
+            encoder.copy_buffer_to_buffer(&old, 0, &buffer, 0, self.capacity as u64 * 4);
+            queue.submit([encoder.finish()]);
+        }
+        self.buffer = Some(buffer);
+        self.capacity = capacity;
+    }
+
+    fn write(&self, queue: &RenderQueue, offset: u32, data: &[u32]) {
+        if let Some(buffer) = &self.buffer
+            && !data.is_empty()
+        {
+            queue.write_buffer(buffer, offset as u64 * 4, bytemuck::cast_slice(data));
+        }
+    }
+}
+
+/// Where a chunk lives in the shared buffers
+struct ChunkAllocation {
+    vertices: Slab,
+    indices: Slab,
+    index_count: u32,
+    /// the index of its transform, used as the instance index of its draw
+    slot: u32,
+}
+
+/// The meshes of every chunk packed into shared buffers
+#[derive(Resource)]
+pub(super) struct ChunkBuffers {
+    positions: PoolBuffer,
+    extras: PoolBuffer,
+    indices: PoolBuffer,
+    vertex_slabs: SlabAllocator,
+    index_slabs: SlabAllocator,
+    chunks: HashMap<MainEntity, ChunkAllocation>,
+    free_slots: Vec<u32>,
+    slot_transforms: Vec<Mat4>,
+    transforms: RawBufferVec<Mat4>,
+    slot_bounds: Vec<ChunkBounds>,
+    bounds: RawBufferVec<ChunkBounds>,
+    bind_group: Option<BindGroup>,
+    /// the draws of the chunks each view can see
+    views: HashMap<Entity, RawBufferVec<ChunkDrawArgs>>,
+    /// the chunks this frame, the mesh is only uploaded if it changed
+    extracted: Vec<(MainEntity, Arc<ChunkMeshData>, bool, Mat4, ChunkBounds)>,
+    multi_draw: bool,
+}
+
+impl FromWorld for ChunkBuffers {
+    fn from_world(world: &mut World) -> Self {
+        let features = world.resource::<RenderDevice>().features();
+        ChunkBuffers {
+            positions: PoolBuffer::new("phoxels_chunk_positions", BufferUsages::VERTEX),
+            extras: PoolBuffer::new("phoxels_chunk_extras", BufferUsages::VERTEX),
+            indices: PoolBuffer::new("phoxels_chunk_indices", BufferUsages::INDEX),
+            vertex_slabs: SlabAllocator::default(),
+            index_slabs: SlabAllocator::default(),
+            chunks: HashMap::new(),
+            free_slots: Vec::new(),
+            slot_transforms: Vec::new(),
+            transforms: RawBufferVec::new(BufferUsages::STORAGE),
+            slot_bounds: Vec::new(),
+            bounds: RawBufferVec::new(BufferUsages::STORAGE),
+            bind_group: None,
+            views: HashMap::new(),
+            extracted: Vec::new(),
+            multi_draw: features.contains(WgpuFeatures::MULTI_DRAW_INDIRECT),
+        }
+    }
+}
+
+impl ChunkBuffers {
+    /// The draws queued for a view
+    pub(super) fn draws(&self, view: Entity) -> Option<&RawBufferVec<ChunkDrawArgs>> {
+        self.views.get(&view)
+    }
+
+    pub(super) fn transforms(&self) -> Option<&Buffer> {
+        self.transforms.buffer()
+    }
+
+    pub(super) fn bounds(&self) -> Option<&Buffer> {
+        self.bounds.buffer()
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub(super) fn extract_chunk_meshes(
+    mut buffers: ResMut<ChunkBuffers>,
+    chunks: Extract<Query<(Entity, Ref<ChunkMesh>, &GlobalTransform, &Aabb)>>,
+) {
+    buffers.extracted.clear();
+    for (entity, mesh, transform, aabb) in &chunks {
+        buffers.extracted.push((
+            entity.into(),
+            mesh.0.clone(),
+            mesh.is_changed(),
+            transform.compute_matrix(),
+            aabb.into(),
+        ));
+    }
+}
+
+/// Free the slabs of chunks that are gone and upload the meshes that changed
+pub(super) fn upload_chunk_meshes(
+    mut buffers: ResMut<ChunkBuffers>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    let ChunkBuffers {
+        positions,
+        extras,
+        indices,
+        vertex_slabs,
+        index_slabs,
+        chunks,
+        free_slots,
+        slot_transforms,
+        transforms,
+        slot_bounds,
+        bounds,
+        extracted,
+        ..
+    } = buffers.as_mut();
+
+    let alive = extracted
+        .iter()
+        .map(|(entity, ..)| *entity)
+        .collect::<HashSet<_>>();
+    chunks.retain(|entity, allocation| {
+        if alive.contains(entity) {
+            return true;
+        }
+        vertex_slabs.free(allocation.vertices);
+        index_slabs.free(allocation.indices);
+        free_slots.push(allocation.slot);
+        false
+    });
+
+    for (entity, mesh, changed, transform, chunk_bounds) in extracted.drain(..) {
+        let uploaded = chunks.contains_key(&entity);
+        if changed || !uploaded {
+            let slot = match chunks.remove(&entity) {
+                Some(old) => {
+                    vertex_slabs.free(old.vertices);
+                    index_slabs.free(old.indices);
+                    old.slot
+                }
+                None => free_slots.pop().unwrap_or_else(|| {
+                    slot_transforms.push(Mat4::IDENTITY);
+                    slot_bounds.push(ChunkBounds::default());
+                    slot_transforms.len() as u32 - 1
+                }),
+            };
+            let vertices = vertex_slabs.allocate(mesh.positions.len() as u32);
+            let index_slab = index_slabs.allocate(mesh.indices.len() as u32);
+            positions.reserve(vertex_slabs.len(), &device, &queue);
+            extras.reserve(vertex_slabs.len(), &device, &queue);
+            indices.reserve(index_slabs.len(), &device, &queue);
+            positions.write(&queue, vertices.offset, &mesh.positions);
+            extras.write(&queue, vertices.offset, &mesh.extras);
+            indices.write(&queue, index_slab.offset, &mesh.indices);
+            chunks.insert(
+                entity,
+                ChunkAllocation {
+                    vertices,
+                    indices: index_slab,
+                    index_count: mesh.indices.len() as u32,
+                    slot,
+                },
+            );
+        }
+        let slot = chunks[&entity].slot;
+        slot_transforms[slot as usize] = transform;
+        slot_bounds[slot as usize] = chunk_bounds;
+    }
+
+    transforms.clear();
+    for transform in slot_transforms.iter() {
+        transforms.push(*transform);
+    }
+    // the bind group needs a buffer even before there are chunks
+    if transforms.is_empty() {
+        transforms.push(Mat4::IDENTITY);
+    }
+    transforms.write_buffer(&device, &queue);
+
+    bounds.clear();
+    for chunk_bounds in slot_bounds.iter() {
+        bounds.push(*chunk_bounds);
+    }
+    if bounds.is_empty() {
+        bounds.push(ChunkBounds::default());
+    }
+    bounds.write_buffer(&device, &queue);
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(super) fn queue_indirect_chunks(
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<IndirectChunkPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<IndirectChunkPipeline>>,
+    material: Option<Res<ExtractedIndirectMaterial>>,
+    mut buffers: ResMut<ChunkBuffers>,
+    mut opaque_phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
+    draw_functions: Res<DrawFunctions<Opaque3d>>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Has<DepthPrepass>,
+        Has<NormalPrepass>,
+        Has<MotionVectorPrepass>,
+        Has<DeferredPrepass>,
+    )>,
+    mut next_tick: Local<Tick>,
+) {
+    let Some(material) = material else {
+        return;
+    };
+    let draw_chunks = draw_functions.read().id::<DrawIndirectChunks>();
+    let ChunkBuffers {
+        chunks,
+        views: view_draws,
+        ..
+    } = buffers.as_mut();
+    let mut seen = HashSet::new();
+
+    for (view_entity, view, visible, msaa, depth, normal, motion_vector, deferred) in &views {
+        let Some(opaque_phase) = opaque_phases.get_mut(&view.retained_view_entity) else {
+            continue;
+        };
+        seen.insert(view_entity);
+
+        // chunks are frustum culled by bevy with the `Aabb` from their `ChunkData`
+        let draws = view_draws
+            .entry(view_entity)
+            // written by the culling pass when the view has `ChunkOcclusionCulling`
+            .or_insert_with(|| RawBufferVec::new(BufferUsages::INDIRECT | BufferUsages::STORAGE));
+        draws.clear();
+        for (_, main_entity) in visible.get::<ChunkMesh>() {
+            let Some(chunk) = chunks.get(main_entity) else {
+                continue;
+            };
+            if chunk.index_count == 0 {
+                continue;
+            }
+            draws.push(ChunkDrawArgs {
+                index_count: chunk.index_count,
+                instance_count: 1,
+                first_index: chunk.indices.offset,
+                base_vertex: chunk.vertices.offset as i32,
+                first_instance: chunk.slot,
+            });
+        }
+
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_hdr(view.hdr);
+        if depth {
+            view_key |= MeshPipelineKey::DEPTH_PREPASS;
+        }
+        if normal {
+            view_key |= MeshPipelineKey::NORMAL_PREPASS;
+        }
+        if motion_vector {
+            view_key |= MeshPipelineKey::MOTION_VECTOR_PREPASS;
+        }
+        if deferred {
+            view_key |= MeshPipelineKey::DEFERRED_PREPASS;
+        }
+        let pipeline_id = pipelines.specialize(
+            &pipeline_cache,
+            &pipeline,
+            IndirectChunkKey {
+                view: view_key,
+                material: material.key,
+            },
+        );
+
+        for &entity in visible.get::<IndirectChunkBatch>() {
+            // bump the change tick so bevy rebuilds the bin
+            let this_tick = next_tick.get() + 1;
+            next_tick.set(this_tick);
+            opaque_phase.add(
+                Opaque3dBatchSetKey {
+                    draw_function: draw_chunks,
+                    pipeline: pipeline_id,
+                    material_bind_group_index: None,
+                    lightmap_slab: None,
+                    vertex_slab: default(),
+                    index_slab: None,
+                },
+                Opaque3dBinKey {
+                    asset_id: AssetId::<Mesh>::invalid().untyped(),
+                },
+                entity,
+                InputUniformIndex::default(),
+                BinnedRenderPhaseType::NonMesh,
+                *next_tick,
+            );
+        }
+    }
+    view_draws.retain(|view, _| seen.contains(view));
+}
+
+pub(super) fn prepare_indirect_buffers(
+    mut buffers: ResMut<ChunkBuffers>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    for draws in buffers.views.values_mut() {
+        draws.write_buffer(&device, &queue);
+    }
+}
+
+pub(super) fn prepare_chunk_bind_group(
+    mut buffers: ResMut<ChunkBuffers>,
+    pipeline: Res<IndirectChunkPipeline>,
+    device: Res<RenderDevice>,
+) {
+    // the transform buffer is remade when it grows so the bind group is too
+    buffers.bind_group = buffers.transforms.buffer().map(|buffer| {
+        device.create_bind_group(
+            "phoxels_indirect_chunks",
+            &pipeline.chunk_layout,
+            &BindGroupEntries::single(buffer.as_entire_binding()),
+        )
+    });
+}
+
+#[derive(Resource)]
+pub(super) struct IndirectChunkPipeline {
+    mesh_pipeline: MeshPipeline,
+    material_layout: BindGroupLayout,
+    chunk_layout: BindGroupLayout,
+}
+
+impl FromWorld for IndirectChunkPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let chunk_layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            "phoxels_indirect_chunks_layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                storage_buffer_read_only::<Mat4>(false),
+            ),
+        );
+        IndirectChunkPipeline {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+            material_layout: world
+                .resource::<MaterialPipeline<VoxelMaterial>>()
+                .material_layout
+                .clone(),
+            chunk_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct IndirectChunkKey {
+    view: MeshPipelineKey,
+    material: VoxelMaterialKey,
+}
+
+impl SpecializedRenderPipeline for IndirectChunkPipeline {
+    type Key = IndirectChunkKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = key
+            .material
+            .shader_defs()
+            .into_iter()
+            // needs the mesh flags of a `Mesh3d` for shadows
+            .filter(|def| *def != "VOXEL_PBR")
+            .map(Into::into)
+            .collect::<Vec<_>>();
+        // every chunk has extras in the shared buffer
+        shader_defs.push("BLOCK_EXTRA".into());
+
+        let view_layout = self
+            .mesh_pipeline
+            .get_view_layout(MeshPipelineViewLayoutKey::from(key.view))
+            .clone();
+        let format = if key.view.contains(MeshPipelineKey::HDR) {
+            ViewTarget::TEXTURE_FORMAT_HDR
+        } else {
+            TextureFormat::bevy_default()
+        };
+        let attribute = |shader_location| VertexBufferLayout {
+            array_stride: 4,
+            step_mode: VertexStepMode::Vertex,
+            attributes: vec![VertexAttribute {
+                format: VertexFormat::Uint32,
+                offset: 0,
+                shader_location,
+            }],
+        };
+
+        RenderPipelineDescriptor {
+            label: Some("phoxels_indirect_chunk_pipeline".into()),
+            layout: vec![
+                view_layout,
+                self.chunk_layout.clone(),
+                self.material_layout.clone(),
+            ],
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: INDIRECT_SHADER,
+                shader_defs: shader_defs.clone(),
+                entry_point: "vertex".into(),
+                buffers: vec![attribute(0), attribute(1)],
+            },
+            fragment: Some(FragmentState {
+                shader: INDIRECT_SHADER,
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                cull_mode: Some(Face::Back),
+                ..default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: default(),
+                bias: default(),
+            }),
+            multisample: MultisampleState {
+                count: key.view.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+pub(super) type DrawIndirectChunks = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetChunkBindGroup<1>,
+    SetIndirectMaterialBindGroup<2>,
+    DrawChunks,
+);
+
+pub(super) struct SetChunkBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetChunkBindGroup<I> {
+    type Param = SRes<ChunkBuffers>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _: &P,
+        _: ROQueryItem<'w, Self::ViewQuery>,
+        _: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        buffers: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = buffers.into_inner().bind_group.as_ref() else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Binds the material of `IndirectChunks`, there is no entity with a `MeshMaterial3d` to look it up from
+pub(super) struct SetIndirectMaterialBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetIndirectMaterialBindGroup<I> {
+    type Param = (
+        SRes<RenderAssets<PreparedMaterial<VoxelMaterial>>>,
+        SRes<MaterialBindGroupAllocator<VoxelMaterial>>,
+        Option<SRes<ExtractedIndirectMaterial>>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _: &P,
+        _: ROQueryItem<'w, Self::ViewQuery>,
+        _: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        (materials, allocator, material): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(material) = material else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(prepared) = materials.into_inner().get(material.id) else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(bind_group) = allocator
+            .into_inner()
+            .get(prepared.binding.group)
+            .and_then(|slab| slab.bind_group())
+        else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+pub(super) struct DrawChunks;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawChunks {
+    type Param = SRes<ChunkBuffers>;
+    type ViewQuery = Entity;
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _: &P,
+        view: ROQueryItem<'w, Self::ViewQuery>,
+        _: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        buffers: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let buffers = buffers.into_inner();
+        let (Some(positions), Some(extras), Some(indices)) = (
+            buffers.positions.buffer.as_ref(),
+            buffers.extras.buffer.as_ref(),
+            buffers.indices.buffer.as_ref(),
+        ) else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(draws) = buffers.views.get(&view) else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(args) = draws.buffer() else {
+            return RenderCommandResult::Skip;
+        };
+        if draws.is_empty() {
+            return RenderCommandResult::Skip;
+        }
+
+        pass.set_vertex_buffer(0, positions.slice(..));
+        pass.set_vertex_buffer(1, extras.slice(..));
+        pass.set_index_buffer(indices.slice(..), 0, IndexFormat::Uint32);
+        if buffers.multi_draw {
+            pass.multi_draw_indexed_indirect(args, 0, draws.len() as u32);
+        } else {
+            for i in 0..draws.len() {
+                pass.draw_indexed_indirect(args, (i * size_of::<ChunkDrawArgs>()) as u64);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
@@ -0,0 +1,81 @@
+/// A range of a shared buffer handed out by a `SlabAllocator`, in elements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slab {
+    pub offset: u32,
+    /// The size class of the slab, the slab is `1 << class` elements long
+    class: u8,
+}
+
+impl Slab {
+    /// How many elements the slab can hold
+    pub fn capacity(&self) -> u32 {
+        1 << self.class
+    }
+}
+
+/// Hands out ranges of a shared buffer in power of two size classes.
+/// Freed slabs are reused by the next allocation of the same class,
+/// so a chunk that is remeshed with about the same size lands back where it was
+#[derive(Debug, Default)]
+pub struct SlabAllocator {
+    free: Vec<Vec<u32>>,
+    /// The end of the highest slab ever handed out
+    end: u32,
+}
+
+impl SlabAllocator {
+    /// The smallest slab, so chunks with a few faces don't fragment the buffer
+    pub const MIN_CLASS: u8 = 6;
+
+    pub fn allocate(&mut self, len: u32) -> Slab {
+        let class =
+            (32 - len.max(1).saturating_sub(1).leading_zeros()).max(Self::MIN_CLASS as u32) as u8;
+        if let Some(offset) = self
+            .free
+            .get_mut(class as usize)
+            .and_then(|free| free.pop())
+        {
+            return Slab { offset, class };
+        }
+        let offset = self.end;
+        self.end += 1 << class;
+        Slab { offset, class }
+    }
+
+    pub fn free(&mut self, slab: Slab) {
+        let class = slab.class as usize;
+        if self.free.len() <= class {
+            self.free.resize_with(class + 1, Vec::new);
+        }
+        self.free[class].push(slab.offset);
+    }
+
+    /// How many elements the buffer needs to hold every slab
+    pub fn len(&self) -> u32 {
+        self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == 0
+    }
+}
+
+#[test]
+fn slabs_are_reused() {
+    let mut slabs = SlabAllocator::default();
+    let a = slabs.allocate(10);
+    let b = slabs.allocate(100);
+    assert_eq!(a.capacity(), 64);
+    assert_eq!(b.capacity(), 128);
+    // slabs never overlap
+    assert_eq!(b.offset, a.offset + a.capacity());
+    assert_eq!(slabs.len(), 192);
+
+    slabs.free(b);
+    let c = slabs.allocate(128);
+    assert_eq!(c, b);
+    // a bigger slab does not fit in the hole
+    let d = slabs.allocate(129);
+    assert_eq!(d.offset, 192);
+    assert_eq!(slabs.len(), 192 + 256);
+}
@@ -1,33 +1,83 @@
 mod block;
 mod chunk;
 mod simple_shader;
+mod texture_array;
 
 pub mod core {
     pub use crate::block::BlockMeta;
+    pub use crate::block::ShapeBox;
     pub use crate::chunk::CHUNK_SIZE;
+    #[cfg(feature = "spatial")]
+    pub use crate::chunk::cave_culling::reachable_chunks;
     pub use crate::chunk::manager::PhoxelGeneratorData;
+    pub use crate::chunk::queue::{JobQueue, JobStats};
+    #[cfg(feature = "generation")]
+    pub use crate::generation::{
+        CompiledNoiseGraph, Layers, NoiseGraphError, NoiseGraphLoader, Terrain,
+    };
+    #[cfg(feature = "indirect")]
+    pub use crate::indirect::{
+        CULL_SHADER, DEPTH_PYRAMID_SHADER, INDIRECT_SHADER, Slab, SlabAllocator,
+    };
     pub use crate::prelude::*;
+    pub use crate::texture_array::{
+        TextureArrayError, TextureArrayLoader, TextureArraySettings, atlas_to_array,
+    };
 }
 
 pub mod dev {
     pub use crate::chunk::mesh_gen::make_mesh;
+    pub use crate::chunk::seed::{check_deterministic, chunk_bytes};
 }
 
 #[cfg(feature = "diagnostics")]
 mod diagnostics;
+#[cfg(feature = "generation")]
+mod generation;
+#[cfg(feature = "indirect")]
+mod indirect;
+#[cfg(test)]
+mod test_utils;
 
 pub mod prelude {
     pub use crate::PhoxelsPlugin;
+    pub use crate::block::Axis;
     pub use crate::block::Block;
+    pub use crate::block::BlockFace;
     pub use crate::block::BlockId;
+    pub use crate::block::BlockRotation;
+    pub use crate::block::BlockShape;
+    pub use crate::block::BlockTint;
     pub use crate::chunk::ChunkData;
     pub use crate::chunk::ChunkSets;
     pub use crate::chunk::GeneratorLimits;
+    pub use crate::chunk::block_entity::{BlockEntities, BlockEntity};
+    pub use crate::chunk::budget::{ExtractLimit, FrameBudget};
+    #[cfg(feature = "spatial")]
+    pub use crate::chunk::cave_culling::CaveCulling;
+    pub use crate::chunk::connectivity::ChunkConnectivity;
+    pub use crate::chunk::failure::{
+        ChunkErrored, ChunkFallback, ChunkGenerationFailed, ChunkTask, FailurePolicy,
+    };
     pub use crate::chunk::manager::PhoxelGenerator;
+    #[cfg(feature = "spatial")]
+    pub use crate::chunk::pending::{PendingWrites, Structure};
+    #[cfg(feature = "spatial")]
+    pub use crate::chunk::pipeline::{ChunkStage, GenerationPipeline, Neighbourhood};
+    pub use crate::chunk::seed::{ChunkRng, WorldSeed};
+    #[cfg(feature = "spatial")]
+    pub use crate::chunk::spatial::{ChunkId, ChunkMap};
     #[cfg(feature = "diagnostics")]
     pub use crate::diagnostics::VoxelCount;
+    #[cfg(feature = "generation")]
+    pub use crate::generation::{
+        Biome, BiomeMap, BlockInfo, Column, Decoration, DensityField, HeightCurve, NoiseCaves,
+        NoiseGraph, NoiseGraphGenerator, NoiseNode, WormCaves,
+    };
+    #[cfg(feature = "indirect")]
+    pub use crate::indirect::{ChunkMesh, ChunkOcclusionCulling, IndirectChunks};
     pub use crate::simple_shader::VoxelMaterial;
-    pub use crate::simple_shader::{BlockOverride, BlockOverrides};
+    pub use crate::simple_shader::{BlockOverride, BlockOverrides, BlockProperties, VoxelLighting};
 }
 
 pub mod utils;
@@ -49,6 +99,10 @@ impl<T: PhoxelGeneratorData> Plugin for PhoxelsPlugin<T> {
         ));
         #[cfg(feature = "diagnostics")]
         app.add_plugins(diagnostics::PhoxelDiagnostics);
+        #[cfg(feature = "generation")]
+        app.add_plugins(generation::GenerationPlugin);
+        #[cfg(feature = "indirect")]
+        app.add_plugins(indirect::IndirectPlugin);
     }
 }
 
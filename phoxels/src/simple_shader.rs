@@ -11,23 +11,66 @@ use bevy::{
 
 pub const FRAGMENT_SHADER: Handle<Shader> = weak_handle!("de68ce2f-34b9-4c48-a82a-1981ec40d447");
 pub const VERTEX_SHADER: Handle<Shader> = weak_handle!("3e3a56da-f9a3-4619-8925-60158ccd3916");
+/// The depth, normal and motion vector prepass and the shadow pass, decodes `BLOCK_DATA` like the main pass
+pub const PREPASS_SHADER: Handle<Shader> = weak_handle!("f404fe46-9615-4238-b517-086cd5757fbb");
+/// `phoxels::voxel_functions`, the vertex decoding and atlas lookup shared by the voxel shaders
+pub const VOXEL_FUNCTIONS: Handle<Shader> = weak_handle!("ce424e8d-6f8d-43ba-8721-271dddfd2d59");
 
-use crate::core::Block;
+use crate::{
+    core::{Block, BlockTint},
+    texture_array::TextureArrayLoader,
+};
 
+/// The packed position of a vertex in its chunk,
+/// bits 0..15: the position with 5 bits per axis, more or less with other chunk sizes
+/// bits 18..26: the block id
+/// bits 29..32: the `BlockFace` + 1 of the triangles this is the first vertex of, 0 if it is not on the block grid
 pub const BLOCK_DATA: MeshVertexAttribute =
     MeshVertexAttribute::new("BlockData", 988540919, VertexFormat::Uint32);
 
+/// Extra per vertex data that only some chunks need,
+/// bits 0..12: position inside the block in 1/16ths for shaped blocks
+/// bits 12..15: the face of the unrotated block + 1 for rotated blocks
+/// bits 16..24: the state of blocks that are textured by state
+/// bits 24..32: where on the tint colormap blocks tinted by biome or state get their colour
+pub const BLOCK_EXTRA: MeshVertexAttribute =
+    MeshVertexAttribute::new("BlockExtra", 988540920, VertexFormat::Uint32);
+
 pub struct VoxelShaderPlugin;
 
 impl Plugin for VoxelShaderPlugin {
     fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            VOXEL_FUNCTIONS,
+            "voxel_functions.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(app, FRAGMENT_SHADER, "voxel.wgsl", Shader::from_wgsl);
         load_internal_asset!(app, VERTEX_SHADER, "voxel.wgsl", Shader::from_wgsl);
-        app.add_plugins(MaterialPlugin::<VoxelMaterial>::default());
+        load_internal_asset!(app, PREPASS_SHADER, "voxel_prepass.wgsl", Shader::from_wgsl);
+        app.add_plugins(MaterialPlugin::<VoxelMaterial>::default())
+            .init_asset_loader::<TextureArrayLoader>();
     }
 }
 
+/// How `VoxelMaterial` lights the blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum VoxelLighting {
+    /// A fixed brightness per face direction, ignores the lights in the scene
+    #[default]
+    Simple,
+    /// Bevy's PBR lighting; point, spot and directional lights, shadows and fog.
+    /// Each block can have its own roughness and metallic, see `VoxelMaterial::set_surface`
+    Pbr,
+}
+
+/// The material chunks are drawn with.
+/// The per block tables, such as animations, are uniforms instead of storage buffers because WebGL 2
+/// has no storage buffers, and they are packed into `blocks` because WebGPU only allows 12 uniform
+/// buffers per shader stage
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[bind_group_data(VoxelMaterialKey)]
 pub struct VoxelMaterial {
     #[uniform(0)]
     /// The shape of the atlas, in the format (width, height, 0, 0).
@@ -39,6 +82,119 @@ pub struct VoxelMaterial {
     pub alpha_mode: AlphaMode,
     #[uniform(3)]
     pub overrides: [BlockOverrides; 256 / 4],
+    /// The state stride, surface, animation, tint and emissive of each block, indexed by block id.
+    /// They share one uniform so the material stays inside the uniform buffer limit of WebGPU
+    #[uniform(4)]
+    pub blocks: [BlockProperties; 256],
+    pub lighting: VoxelLighting,
+    /// A texture array with one layer per texture, used instead of `base_color_texture` when set.
+    /// Each face samples its own layer so textures tile without bleeding into their neighbours,
+    /// see `TextureArrayLoader` to make one from an atlas
+    #[texture(6, dimension = "2d_array")]
+    #[sampler(7)]
+    pub texture_array: Option<Handle<Image>>,
+    /// The colours of `BlockTint::Biome` and `BlockTint::State`, a tint of `n`
+    /// is the colour `n / 255` of the way along the first row
+    #[texture(10)]
+    #[sampler(11)]
+    pub tint_colormap: Option<Handle<Image>>,
+    /// A tangent space normal map laid out like the base atlas, green is up the texture.
+    /// Only changes the lighting with `VoxelLighting::Pbr`, load it with `is_srgb: false`.
+    /// It is always an atlas of the shape in `atlas_shape`, even when `texture_array` is set,
+    /// so it can bleed between textures at lower mips like the base atlas
+    #[texture(13)]
+    #[sampler(14)]
+    pub normal_map_texture: Option<Handle<Image>>,
+    /// A height map laid out like the base atlas, white is the surface and black is the deepest.
+    /// Faces are parallax mapped when it is set.
+    /// Like `normal_map_texture` it must be an atlas matching `atlas_shape` with or without `texture_array`.
+    /// The parallax steps wrap around inside the texture, but filtering at its edges and lower mips
+    /// still read from the textures next to it
+    #[texture(15)]
+    #[sampler(16)]
+    pub height_map_texture: Option<Handle<Image>>,
+    /// The parallax mapping settings, in the format (depth scale, max layer count, 0, 0).
+    /// The depth scale is how deep black on the height map is in blocks
+    #[uniform(17)]
+    pub parallax: Vec4,
+}
+
+pub use block_properties::BlockProperties;
+
+// the `ShaderType` derive adds a `check` function for each field next to the struct that is never called,
+// it is only allowed in this module so other dead code in the file is still caught
+#[allow(dead_code)]
+mod block_properties {
+    /// Everything the shader knows about a block other then its face overrides, set these with the
+    /// `VoxelMaterial::set_*` functions
+    #[derive(Default, Clone, Copy, Debug, bevy::render::render_resource::ShaderType)]
+    pub struct BlockProperties {
+        /// How far along the atlas to move per step of the blocks state,
+        /// only used for blocks that are `textured_by_state`.
+        /// Aligned so each entry of the uniform array is 32 bytes long
+        #[align(16)]
+        pub state_stride: u32,
+        /// Roughness in bits 0..8, metallic in 8..16, bit 16 set when the block has a surface.
+        /// Only used with `VoxelLighting::Pbr`
+        pub surface: u32,
+        /// A linear rgb colour in bits 0..24, the mode in 24..32; 0 none, 1 constant, 2 biome, 3 state
+        pub tint: u32,
+        /// Blocks with less then 2 frames are not animated,
+        /// the frames are `animation_stride` textures apart along the atlas
+        pub animation_frames: u32,
+        /// Seconds each frame is shown for
+        pub frame_time: f32,
+        pub animation_stride: u32,
+        /// Multiplies the colour of the block, blocks with an intensity of 0 are not emissive.
+        /// The camera needs `hdr` and `Bloom` for it to glow
+        pub emissive_intensity: f32,
+        /// How far along the atlas the emissive texture is from the texture of the face,
+        /// 0 makes the whole block glow with its own colour
+        pub emissive_stride: u32,
+    }
+}
+
+/// The parts of a `VoxelMaterial` that change its pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxelMaterialKey {
+    lighting: VoxelLighting,
+    texture_array: bool,
+    normal_map: bool,
+    parallax: bool,
+}
+
+impl From<&VoxelMaterial> for VoxelMaterialKey {
+    fn from(material: &VoxelMaterial) -> Self {
+        Self {
+            lighting: material.lighting,
+            texture_array: material.texture_array.is_some(),
+            normal_map: material.normal_map_texture.is_some(),
+            parallax: material.height_map_texture.is_some(),
+        }
+    }
+}
+
+impl VoxelMaterialKey {
+    /// The shader defs of the key, without the ones that depend on the mesh
+    pub(crate) fn shader_defs(&self) -> Vec<&'static str> {
+        let mut defs = Vec::new();
+        if self.lighting == VoxelLighting::Pbr {
+            defs.push("VOXEL_PBR");
+        }
+        if self.texture_array {
+            defs.push("VOXEL_TEXTURE_ARRAY");
+        }
+        if self.normal_map {
+            defs.push("VOXEL_NORMAL_MAP");
+        }
+        if self.parallax {
+            defs.push("VOXEL_PARALLAX");
+        }
+        if self.normal_map || self.parallax {
+            defs.push("VOXEL_TANGENTS");
+        }
+        defs
+    }
 }
 
 impl Default for VoxelMaterial {
@@ -48,6 +204,13 @@ impl Default for VoxelMaterial {
             base_color_texture: None,
             alpha_mode: AlphaMode::Opaque,
             overrides: [BlockOverrides::default(); 256 / 4],
+            blocks: [BlockProperties::default(); 256],
+            lighting: VoxelLighting::Simple,
+            texture_array: None,
+            tint_colormap: None,
+            normal_map_texture: None,
+            height_map_texture: None,
+            parallax: Vec4::new(0.1, 16., 0., 0.),
         }
     }
 }
@@ -110,14 +273,71 @@ impl VoxelMaterial {
             data |= stride.get() as u32; // Store the stride value
         }
 
-        let overrides = &mut self.overrides[index];
-        match offset {
-            0 => overrides.block_a = data,
-            1 => overrides.block_b = data,
-            2 => overrides.block_c = data,
-            3 => overrides.block_d = data,
-            _ => unreachable!("Offset must be between 0 and 3, got: {}", offset),
+        self.overrides[index].set(offset, data);
+    }
+
+    /// Set how far along the atlas the texture of a block moves per step of its state.
+    /// The texture used is `block.id() + face override + state * stride`
+    pub fn set_state_stride(&mut self, block: impl Block, stride: u8) {
+        self.blocks[block.id() as usize].state_stride = stride as u32;
+    }
+
+    /// Animate the texture of a block, the shader picks the frame from the time
+    /// so the chunk does not need remeshing
+    pub fn set_animation(&mut self, block: impl Block, frames: u32, frame_time: f32, stride: u32) {
+        debug_assert!(
+            frame_time > 0.,
+            "Frame time must be positive: {}",
+            frame_time
+        );
+        let properties = &mut self.blocks[block.id() as usize];
+        properties.animation_frames = frames;
+        properties.frame_time = frame_time;
+        properties.animation_stride = stride;
+    }
+
+    /// Make a block glow with its own colour, it ignores the shading of its faces.
+    /// An intensity above 1 gives HDR values that feed bloom
+    pub fn set_emissive(&mut self, block: impl Block, intensity: f32) {
+        self.set_emissive_texture(block, intensity, 0);
+    }
+
+    /// Make the parts of a block glow that are covered by its emissive texture,
+    /// which is `stride` textures along the atlas from the texture of each face.
+    /// The rest of the block is shaded as normal
+    pub fn set_emissive_texture(&mut self, block: impl Block, intensity: f32, stride: u32) {
+        debug_assert!(
+            intensity >= 0.,
+            "Emissive intensity must not be negative: {}",
+            intensity
+        );
+        let properties = &mut self.blocks[block.id() as usize];
+        properties.emissive_intensity = intensity;
+        properties.emissive_stride = stride;
+    }
+
+    /// Set how a block is tinted, this should match `Block::tint`
+    pub fn set_tint(&mut self, block: impl Block, tint: BlockTint) {
+        // the mode goes in the top byte and a constant colour in the bottom three
+        let data = match tint {
+            BlockTint::None => 0,
+            BlockTint::Constant(color) => {
+                let [r, g, b, _] = color.to_linear().to_u8_array();
+                r as u32 | (g as u32) << 8 | (b as u32) << 16 | 1 << 24
+            }
+            BlockTint::Biome => 2 << 24,
+            BlockTint::State => 3 << 24,
         };
+        self.blocks[block.id() as usize].tint = data;
+    }
+
+    /// Set the roughness and metallic of a block, both in 0..=1.
+    /// Blocks without a surface are fully rough and not metallic
+    pub fn set_surface(&mut self, block: impl Block, roughness: f32, metallic: f32) {
+        let roughness = (roughness.clamp(0., 1.) * 255.).round() as u32;
+        let metallic = (metallic.clamp(0., 1.) * 255.).round() as u32;
+        // bit 16 marks the surface as set so all zeros can mean the default
+        self.blocks[block.id() as usize].surface = roughness | metallic << 8 | 1 << 16;
     }
 }
 
@@ -191,6 +411,18 @@ pub struct BlockOverrides {
     block_d: u32,
 }
 
+impl BlockOverrides {
+    fn set(&mut self, offset: u32, data: u32) {
+        match offset {
+            0 => self.block_a = data,
+            1 => self.block_b = data,
+            2 => self.block_c = data,
+            3 => self.block_d = data,
+            _ => unreachable!("Offset must be between 0 and 3, got: {}", offset),
+        };
+    }
+}
+
 impl Material for VoxelMaterial {
     fn fragment_shader() -> bevy::render::render_resource::ShaderRef {
         FRAGMENT_SHADER.into()
@@ -206,17 +438,35 @@ impl Material for VoxelMaterial {
         // "shaders/voxel.wgsl".into()
     }
 
-    // fn prepass_vertex_shader() -> bevy::render::render_resource::ShaderRef {
-    //     bevy::render::render_resource::ShaderRef::Default
-    // }
+    /// Chunks cast shadows and write to the depth, normal and motion vector prepasses
+    /// without needing `Mesh::ATTRIBUTE_POSITION`. The deferred prepass is not supported
+    fn prepass_vertex_shader() -> bevy::render::render_resource::ShaderRef {
+        PREPASS_SHADER.into()
+    }
+
+    fn prepass_fragment_shader() -> bevy::render::render_resource::ShaderRef {
+        PREPASS_SHADER.into()
+    }
 
     fn specialize(
         _pipeline: &bevy::pbr::MaterialPipeline<Self>,
         descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
         layout: &bevy::render::mesh::MeshVertexBufferLayoutRef,
-        _key: bevy::pbr::MaterialPipelineKey<Self>,
+        key: bevy::pbr::MaterialPipelineKey<Self>,
     ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
-        let vertex_layout = layout.0.get_layout(&[BLOCK_DATA.at_shader_location(0)])?;
+        let mut defs = key.bind_group_data.shader_defs();
+        let mut attributes = vec![BLOCK_DATA.at_shader_location(0)];
+        if layout.0.contains(BLOCK_EXTRA) {
+            attributes.push(BLOCK_EXTRA.at_shader_location(1));
+            defs.push("BLOCK_EXTRA");
+        }
+        for def in defs {
+            descriptor.vertex.shader_defs.push(def.into());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push(def.into());
+            }
+        }
+        let vertex_layout = layout.0.get_layout(&attributes)?;
         descriptor.vertex.buffers = vec![vertex_layout];
         Ok(())
     }
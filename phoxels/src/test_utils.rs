@@ -0,0 +1,124 @@
+//! Blocks and a world shared by the tests
+
+use bevy::{ecs::event::Events, prelude::World};
+
+use crate::block::Block;
+use crate::chunk::{
+    budget::FrameBudget,
+    failure::{ChunkGenerationFailed, FailurePolicy},
+    manager::{ChunkGenerator, ChunkMesher, GeneratorLimits},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Air;
+impl Block for Air {
+    fn id(&self) -> u8 {
+        0
+    }
+    fn is_solid(&self) -> bool {
+        false
+    }
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Stone;
+impl Block for Stone {
+    fn id(&self) -> u8 {
+        1
+    }
+    fn is_solid(&self) -> bool {
+        true
+    }
+    fn is_transparent(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Glass;
+impl Block for Glass {
+    fn id(&self) -> u8 {
+        2
+    }
+    fn is_solid(&self) -> bool {
+        true
+    }
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
+
+/// A see through block with a `BlockEntity`
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Chest;
+impl Block for Chest {
+    fn id(&self) -> u8 {
+        3
+    }
+    fn is_solid(&self) -> bool {
+        true
+    }
+    fn is_transparent(&self) -> bool {
+        true
+    }
+    fn has_block_entity(&self) -> bool {
+        true
+    }
+}
+
+/// A full block with a `BlockEntity`
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Furnace;
+impl Block for Furnace {
+    fn id(&self) -> u8 {
+        4
+    }
+    fn is_solid(&self) -> bool {
+        true
+    }
+    fn is_transparent(&self) -> bool {
+        false
+    }
+    fn has_block_entity(&self) -> bool {
+        true
+    }
+}
+
+/// Only the pending writes tests place logs
+#[cfg(feature = "spatial")]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Log;
+#[cfg(feature = "spatial")]
+impl Block for Log {
+    fn id(&self) -> u8 {
+        20
+    }
+    fn is_solid(&self) -> bool {
+        true
+    }
+    fn is_transparent(&self) -> bool {
+        false
+    }
+}
+
+/// A world with the resources the chunk systems need to run, and a task pool to run them on
+pub(crate) fn test_world() -> World {
+    bevy::tasks::AsyncComputeTaskPool::get_or_init(bevy::tasks::TaskPool::default);
+    let mut world = World::new();
+    world.init_resource::<ChunkGenerator>();
+    world.init_resource::<ChunkMesher>();
+    world.init_resource::<GeneratorLimits>();
+    world.init_resource::<FailurePolicy>();
+    world.init_resource::<FrameBudget>();
+    world.init_resource::<Events<ChunkGenerationFailed>>();
+    #[cfg(feature = "spatial")]
+    world.init_resource::<crate::chunk::spatial::ChunkMap>();
+    #[cfg(feature = "spatial")]
+    world.init_resource::<crate::chunk::pending::PendingWrites>();
+    #[cfg(feature = "diagnostics")]
+    world.init_resource::<crate::diagnostics::VoxelCount>();
+    world
+}
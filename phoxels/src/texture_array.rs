@@ -0,0 +1,319 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, RenderAssetUsages, io::Reader},
+    image::{
+        CompressedImageFormats, Image, ImageAddressMode, ImageFilterMode, ImageSampler,
+        ImageSamplerDescriptor, ImageType, TextureError,
+    },
+    render::render_resource::{
+        Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// How to cut an atlas into the layers of a texture array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextureArraySettings {
+    /// The number of textures across the atlas
+    pub columns: u32,
+    /// The number of textures down the atlas
+    pub rows: u32,
+    /// Build a full mip chain for each layer
+    pub mipmaps: bool,
+}
+
+impl Default for TextureArraySettings {
+    fn default() -> Self {
+        Self {
+            columns: 16,
+            rows: 16,
+            mipmaps: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TextureArrayError {
+    Io(std::io::Error),
+    Image(TextureError),
+    /// The image is not in a format that can be converted to `Rgba8UnormSrgb`
+    UnsupportedFormat(TextureFormat),
+    /// The atlas can not be split evenly into `columns` x `rows` textures
+    BadShape {
+        width: u32,
+        height: u32,
+        columns: u32,
+        rows: u32,
+    },
+}
+
+impl std::fmt::Display for TextureArrayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureArrayError::Io(e) => write!(f, "failed to read atlas: {e}"),
+            TextureArrayError::Image(e) => write!(f, "failed to decode atlas: {e}"),
+            TextureArrayError::UnsupportedFormat(format) => {
+                write!(f, "can't convert atlas from {format:?} to rgba")
+            }
+            TextureArrayError::BadShape {
+                width,
+                height,
+                columns,
+                rows,
+            } => write!(
+                f,
+                "a {width}x{height} atlas can't be split into {columns}x{rows} textures"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TextureArrayError {}
+
+/// Cut an atlas into a 2d texture array with one layer per texture,
+/// layer `i` is the texture at column `i % columns` and row `i / columns`,
+/// the same index `VoxelMaterial` uses for the atlas
+pub fn atlas_to_array(
+    atlas: &Image,
+    settings: &TextureArraySettings,
+) -> Result<Image, TextureArrayError> {
+    let format = atlas.texture_descriptor.format;
+    let converted;
+    let atlas = if format == TextureFormat::Rgba8UnormSrgb || format == TextureFormat::Rgba8Unorm {
+        atlas
+    } else {
+        converted = atlas
+            .convert(TextureFormat::Rgba8UnormSrgb)
+            .ok_or(TextureArrayError::UnsupportedFormat(format))?;
+        &converted
+    };
+    let format = atlas.texture_descriptor.format;
+    let width = atlas.width();
+    let height = atlas.height();
+    let TextureArraySettings {
+        columns,
+        rows,
+        mipmaps,
+    } = *settings;
+    if columns == 0 || rows == 0 || width % columns != 0 || height % rows != 0 {
+        return Err(TextureArrayError::BadShape {
+            width,
+            height,
+            columns,
+            rows,
+        });
+    }
+    let Some(data) = atlas.data.as_ref() else {
+        // the atlas only lives on the gpu so there is nothing to cut up
+        return Err(TextureArrayError::UnsupportedFormat(format));
+    };
+
+    let tile_width = width / columns;
+    let tile_height = height / rows;
+    let mip_levels = if mipmaps {
+        32 - tile_width.min(tile_height).leading_zeros()
+    } else {
+        1
+    };
+    let srgb = format.is_srgb();
+
+    // layer major, every mip of the first layer then every mip of the next
+    let mut out = Vec::new();
+    for layer in 0..columns * rows {
+        let x0 = (layer % columns) * tile_width;
+        let y0 = (layer / columns) * tile_height;
+        let mut level = Vec::with_capacity((tile_width * tile_height * 4) as usize);
+        for y in y0..y0 + tile_height {
+            let start = ((y * width + x0) * 4) as usize;
+            level.extend_from_slice(&data[start..start + (tile_width * 4) as usize]);
+        }
+        let (mut w, mut h) = (tile_width, tile_height);
+        out.extend_from_slice(&level);
+        for _ in 1..mip_levels {
+            (level, w, h) = downsample(&level, w, h, srgb);
+            out.extend_from_slice(&level);
+        }
+    }
+
+    // `Image::new` expects only the first mip, so the data is set after the mip count
+    let mut image = Image::new_uninit(
+        Extent3d {
+            width: tile_width,
+            height: tile_height,
+            depth_or_array_layers: columns * rows,
+        },
+        TextureDimension::D2,
+        format,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.mip_level_count = mip_levels;
+    // uploaded in the default `TextureDataOrder::LayerMajor`, the order `out` is built in
+    image.data = Some(out);
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    // blocks repeat their texture across greedy meshed faces, keep the pixels sharp up close
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Nearest,
+        min_filter: ImageFilterMode::Nearest,
+        mipmap_filter: ImageFilterMode::Linear,
+        ..Default::default()
+    });
+    Ok(image)
+}
+
+/// Halve an rgba8 image with a box filter, averaging colour in linear space
+fn downsample(data: &[u8], width: u32, height: u32, srgb: bool) -> (Vec<u8>, u32, u32) {
+    let out_width = (width / 2).max(1);
+    let out_height = (height / 2).max(1);
+    let mut out = Vec::with_capacity((out_width * out_height * 4) as usize);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut sum = [0.; 4];
+            let mut count = 0.;
+            for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let px = (x * 2 + sx).min(width - 1);
+                let py = (y * 2 + sy).min(height - 1);
+                let i = ((py * width + px) * 4) as usize;
+                for (c, sum) in sum.iter_mut().take(3).enumerate() {
+                    *sum += to_linear(data[i + c], srgb);
+                }
+                sum[3] += data[i + 3] as f32 / 255.;
+                count += 1.;
+            }
+            for (c, sum) in sum.iter().enumerate() {
+                let value = sum / count;
+                out.push(if c < 3 {
+                    from_linear(value, srgb)
+                } else {
+                    (value * 255.).round() as u8
+                });
+            }
+        }
+    }
+    (out, out_width, out_height)
+}
+
+fn to_linear(value: u8, srgb: bool) -> f32 {
+    let value = value as f32 / 255.;
+    if !srgb {
+        value
+    } else if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(value: f32, srgb: bool) -> u8 {
+    let value = if !srgb {
+        value
+    } else if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    };
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// Loads an atlas image as a texture array for `VoxelMaterial::texture_array`.
+/// Used for files ending in `.atlas.png`, or any image with a `.meta` file naming
+/// `phoxels::texture_array::TextureArrayLoader` as its loader
+#[derive(Default)]
+pub struct TextureArrayLoader;
+
+impl AssetLoader for TextureArrayLoader {
+    type Asset = Image;
+    type Settings = TextureArraySettings;
+    type Error = TextureArrayError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &TextureArraySettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Image, TextureArrayError> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(TextureArrayError::Io)?;
+        let extension = load_context
+            .path()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("png");
+        let atlas = Image::from_buffer(
+            &bytes,
+            ImageType::Extension(extension),
+            CompressedImageFormats::NONE,
+            true,
+            ImageSampler::Default,
+            RenderAssetUsages::default(),
+        )
+        .map_err(TextureArrayError::Image)?;
+        atlas_to_array(&atlas, settings)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["atlas.png"]
+    }
+}
+
+#[test]
+fn atlas_layers_in_order() {
+    // a 2 x 2 atlas of 4 x 4 textures, each a solid colour
+    let colours = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [255; 4],
+    ];
+    let mut data = Vec::new();
+    for y in 0..8 {
+        for x in 0..8 {
+            data.extend_from_slice(&colours[(y / 4) * 2 + x / 4]);
+        }
+    }
+    let atlas = Image::new(
+        Extent3d {
+            width: 8,
+            height: 8,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    let settings = TextureArraySettings {
+        columns: 2,
+        rows: 2,
+        mipmaps: true,
+    };
+    let array = atlas_to_array(&atlas, &settings).unwrap();
+    assert_eq!(array.texture_descriptor.size.depth_or_array_layers, 4);
+    assert_eq!(array.texture_descriptor.mip_level_count, 3);
+
+    // every mip of a layer before the next layer; 4 x 4, 2 x 2 then 1 x 1
+    let data = array.data.unwrap();
+    let layer_len = (16 + 4 + 1) * 4;
+    assert_eq!(data.len(), 4 * layer_len);
+    for (layer, colour) in colours.iter().enumerate() {
+        let layer = &data[layer * layer_len..(layer + 1) * layer_len];
+        assert!(layer.chunks(4).all(|pixel| pixel == colour));
+    }
+
+    let flat = atlas_to_array(
+        &atlas,
+        &TextureArraySettings {
+            mipmaps: false,
+            ..settings
+        },
+    )
+    .unwrap();
+    assert_eq!(flat.texture_descriptor.mip_level_count, 1);
+    assert_eq!(flat.data.unwrap().len(), 4 * 16 * 4);
+}
@@ -1,7 +1,25 @@
-struct Vertex {
-    @builtin(instance_index) instance_index: u32,
-    @location(0) position: u32,
-};
+#import phoxels::voxel_functions::{
+    Vertex,
+    block_emission,
+    block_tint,
+    blocks,
+    face_bitangent,
+    face_normal,
+    face_shade,
+    face_tangent,
+    fully_emissive,
+    inverse_scale,
+    local_position,
+    mapped_normal,
+    normal_bitangent,
+    normal_tangent,
+    parallax_position,
+    sample_block,
+    vertex_block_type,
+    vertex_extra,
+    vertex_face,
+}
+#import bevy_pbr::mesh_view_bindings::view
 
 struct VertexOutput {
     // This is `clip position` when the struct is used as a vertex stage output
@@ -11,6 +29,17 @@ struct VertexOutput {
     @location(1) world_normal: vec3<f32>,
     @location(2) block_type: u32,
     @location(3) scale: vec3<f32>,
+#ifdef BLOCK_EXTRA
+    @location(4) @interpolate(flat) extra: u32,
+#endif
+#ifdef VOXEL_PBR
+    @location(5) @interpolate(flat) instance_index: u32,
+#endif
+#ifdef VOXEL_TANGENTS
+    // zero for faces off the block grid
+    @location(6) @interpolate(flat) world_tangent: vec3<f32>,
+    @location(7) @interpolate(flat) world_bitangent: vec3<f32>,
+#endif
 }
 
 struct FragmentOutput {
@@ -21,122 +50,93 @@ struct FragmentOutput {
 // we can import items from shader modules in the assets folder with a quoted path
 const COLOR_MULTIPLIER: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 0.5);
 
-@group(2) @binding(0) var<uniform> atlas_size: vec4<u32>;
-@group(2) @binding(1) var material_color_texture: texture_2d<f32>;
-@group(2) @binding(2) var material_color_sampler: sampler;
-@group(2) @binding(3) var<uniform> face_overrides: array<FaceOverride, 256 / 4>;
-// @group(2) @binding(3) var<uniform> mesh_world_from_local: array<>;
-
-struct FaceOverride {
-    block_a: u32,
-    block_b: u32,
-    block_c: u32,
-    block_d: u32,
+#ifdef VOXEL_PBR
+#import bevy_pbr::{
+    mesh_bindings::mesh,
+    pbr_functions,
+    pbr_types,
 }
 
-const light: vec3<f32> = vec3(-0.57735027, 0.57735027, 0.57735027);
+// light the block with the lights of the scene
+fn pbr_lighting(in: VertexOutput, world_normal: vec3<f32>, N: vec3<f32>, color: vec4<f32>, emission: vec3<f32>) -> vec4<f32> {
+    var pbr_input = pbr_types::pbr_input_new();
+    pbr_input.material.base_color = color;
+    pbr_input.material.emissive = vec4(emission, 1.);
+    pbr_input.material.perceptual_roughness = 1.;
+    let surface = blocks[in.block_type].surface;
+    if (surface & (1u << 16)) != 0u {
+        pbr_input.material.perceptual_roughness = f32(surface & 255) / 255.;
+        pbr_input.material.metallic = f32((surface >> 8) & 255) / 255.;
+    }
+    pbr_input.material.flags |= pbr_types::STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT;
+    // the mesh flags say if the chunk receives shadows
+    pbr_input.flags = mesh[in.instance_index].flags;
+    pbr_input.frag_coord = in.position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = world_normal;
+    pbr_input.N = N;
+    pbr_input.is_orthographic = view.clip_from_view[3].w == 1.0;
+    pbr_input.V = pbr_functions::calculate_view(in.world_position, pbr_input.is_orthographic);
+
+    let lit = pbr_functions::apply_pbr_lighting(pbr_input);
+    return pbr_functions::main_pass_post_lighting_processing(pbr_input, lit);
+}
+#endif
 
 @fragment
 fn fragment(
     in: VertexOutput,
 ) -> @location(0) vec4<f32> {
-    let world_normal = normalize( cross( dpdy( in.world_position.xyz ), dpdx( in.world_position.xyz ) ) );
-    
-    var dp = 0.7;
-
-    if world_normal.y > 0.2 {
-        dp = 1.;
-    } else if world_normal.y < -0.2 {
-        dp = 0.5;
-    } else {
-        if world_normal.x > 0.2 {
-            dp += 0.05;   
-        } else if world_normal.x < -0.2 {
-            dp -= 0.1;
-        }
-        if world_normal.z > 0.2 {
-            dp += 0.1;
-        } else if world_normal.z < -0.2 {
-            dp -= 0.05;
-        }
-    }
+    let world_normal = face_normal(in.world_position.xyz);
 
-    // back, left, right, top, bottom
-    var face: u32 = 10;
-    if world_normal.x > 0.5 {
-        face = 15;
-    } else if world_normal.x < -0.5 {
-        face = 10;
-    } else if world_normal.y > 0.5 {
-        face = 5;
-    } else if world_normal.y < -0.5 {
-        face = 0;
-    } else if world_normal.z > 0.5 {
-        face = 20;
-    } else if world_normal.z < -0.5 {
-        face = 25;
-    };
-
-    let faceovers = face_overrides[in.block_type / 4];
-    var faceover: u32;
-    let index = in.block_type % 4;
-    if index == 0 {
-        faceover = faceovers.block_a;
-    } else if index == 1 {
-        faceover = faceovers.block_b;
-    } else if index == 2 {
-        faceover = faceovers.block_c;
-    } else if index == 3 {
-        faceover = faceovers.block_d;
-    } else {
-        faceover = 0;
+    let dp = face_shade(world_normal);
+
+#ifdef BLOCK_EXTRA
+    let extra = in.extra;
+#else
+    let extra = 0u;
+#endif
+    var position = in.world_position.xyz;
+    var N = world_normal;
+#ifdef VOXEL_TANGENTS
+    var tangent = in.world_tangent;
+    var bitangent = in.world_bitangent;
+    if dot(tangent, tangent) == 0. {
+        tangent = normal_tangent(world_normal);
+        bitangent = normal_bitangent(world_normal);
     }
-    var stride = (faceover >> face) & 31;
-    var block_type = in.block_type + stride;
-
-    let x = (block_type) % atlas_size.x;
-    let y = (block_type) / atlas_size.y;
-    var uvx = f32(x) /  f32(atlas_size.x);
-    var uvy = (1.+f32(y)) /  f32(atlas_size.x);
-    
-    let texture_step = 1. / vec4<f32>(atlas_size);
-
-    
-    var axis: f32;
-    if abs(world_normal.y) < 0.5 {
-        axis = (in.world_position.y * in.scale.y) % 1;
-    } else {
-        axis = (in.world_position.z * in.scale.z) % 1;
-    };
-    if axis < 0 {
-        axis += 1;
+#endif
+#ifdef VOXEL_PARALLAX
+    let view_direction = normalize(view.world_position - position);
+    position = parallax_position(in.block_type, extra, position, world_normal, tangent, bitangent, view_direction, in.scale);
+#endif
+#ifdef VOXEL_NORMAL_MAP
+    N = mapped_normal(in.block_type, extra, position, world_normal, tangent, bitangent, in.scale);
+#endif
+    var ts = sample_block(in.block_type, extra, position, world_normal, in.scale);
+    ts *= block_tint(in.block_type, extra);
+    let a = ts.a;
+    let emission = block_emission(in.block_type, extra, ts, position, world_normal, in.scale);
+#ifdef VOXEL_PBR
+    if a < 0.2 {
+        discard;
     }
-    uvy -= (axis * texture_step.y);
-    
-    if abs(world_normal.x) < 0.5 {
-        axis = (in.world_position.x * in.scale.x) % 1;
+    ts = pbr_lighting(in, world_normal, N, ts, emission);
+#else
+    if fully_emissive(in.block_type) {
+        // glowing blocks are as bright from every side
+        ts = vec4(emission, 1.);
     } else {
-        axis = (in.world_position.z * in.scale.z) % 1;
-    };
-    if axis < 0 {
-        axis += 1;
+        ts *= dp * COLOR_MULTIPLIER;
+        ts = vec4(ts.rgb + emission, ts.a);
     }
-    
-    // if in.block_type == 77 && world_normal.y > 0.5 { // grass
-    //     uvx += 1. / f32(atlas_size.x);
-    // }
-    
-    
-    uvx += axis * texture_step.x;
-    var ts = textureSample(material_color_texture, material_color_sampler, vec2(uvx, uvy));
-    let a = ts.a;
-    ts *= dp * COLOR_MULTIPLIER;
     if a < 0.2 {
         discard;
     } else {
         ts.a = a;
     }
-    
+#endif
+
     // return vec4(color, 1.);
     return ts;
 }
@@ -166,29 +166,27 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     // Use vertex_no_morph.instance_index instead of vertex.instance_index to work around a wgpu dx12 bug.
     // See https://github.com/gfx-rs/naga/issues/2416 .
     var world_from_local = in_world_from_local;
-    let x = vertex.position & 31;
-    let y = (vertex.position >> 5) & 31;
-    let z = (vertex.position >> 10) & 31;
-    out.block_type = (vertex.position >> 18) & 255;
-    let pos = vec3(f32(x), f32(y), f32(z));
-
-
-    // calculate scale
-    out.scale = vec3<f32>(1.);
-    // 1. calculate the determinant of the affine matrix
-    // determinant = dot(z, cross(x, y))
-    let determinant = determinant(in_world_from_local);
-    // 2. x = length of the first column of the affine matrix
-    out.scale.x = 1. / length(in_world_from_local[0]);
-    // 3. is the determinant negative? if so, negate the x of the scale
-    if determinant < 0. {
-        out.scale.x = -out.scale.x;
+    out.block_type = vertex_block_type(vertex);
+#ifdef BLOCK_EXTRA
+    out.extra = vertex_extra(vertex);
+#endif
+#ifdef VOXEL_PBR
+    out.instance_index = vertex.instance_index;
+#endif
+    let pos = local_position(vertex);
+
+    out.scale = inverse_scale(in_world_from_local);
+
+#ifdef VOXEL_TANGENTS
+    // only the first vertex of each triangle is used, it carries the face of the triangle
+    let face = vertex_face(vertex);
+    out.world_tangent = vec3(0.);
+    out.world_bitangent = vec3(0.);
+    if face != 0u {
+        out.world_tangent = normalize((world_from_local * vec4(face_tangent(face), 0.)).xyz);
+        out.world_bitangent = normalize((world_from_local * vec4(face_bitangent(face), 0.)).xyz);
     }
-    // 4. y = length of the second column of the affine matrix
-    out.scale.y = 1. / length(in_world_from_local[1]);
-    // 5. z = length of the third column of the affine matrix
-    out.scale.z = 1. / length(in_world_from_local[2]);
-    // let scale = vec3<f32>(det, det, det);
+#endif
 
     /// set pos
     out.world_position = mesh_functions::mesh_position_local_to_world(world_from_local, vec4<f32>(pos, 1.0));
@@ -0,0 +1,101 @@
+// culls the indirect draws of a view against its frustum and the depth of the frame before,
+// a draw that is hidden is left in place with no instances
+
+struct DrawArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    // the slot of the chunk
+    first_instance: u32,
+}
+
+struct ChunkBounds {
+    center: vec4<f32>,
+    half_extents: vec4<f32>,
+}
+
+struct CullView {
+    clip_from_world: mat4x4<f32>,
+    previous_clip_from_world: mat4x4<f32>,
+    pyramid_size: vec2<u32>,
+    mip_count: u32,
+    draw_count: u32,
+    occlusion: u32,
+}
+
+@group(0) @binding(0) var<uniform> cull_view: CullView;
+@group(0) @binding(1) var<storage, read_write> draws: array<DrawArgs>;
+@group(0) @binding(2) var<storage, read> chunks: array<mat4x4<f32>>;
+@group(0) @binding(3) var<storage, read> bounds: array<ChunkBounds>;
+// the farthest depth under each texel, reverse z so the smallest value
+@group(0) @binding(4) var depth_pyramid: texture_2d<f32>;
+
+// corner `i` of the bounds in world space
+fn corner(world_from_local: mat4x4<f32>, chunk: ChunkBounds, i: u32) -> vec4<f32> {
+    let side = vec3(f32(i & 1u), f32((i >> 1u) & 1u), f32((i >> 2u) & 1u)) * 2. - 1.;
+    return world_from_local * vec4(chunk.center.xyz + chunk.half_extents.xyz * side, 1.);
+}
+
+fn in_frustum(world_from_local: mat4x4<f32>, chunk: ChunkBounds) -> bool {
+    // how many corners are outside each plane, the far plane is at infinity
+    var outside = vec4(0u);
+    var outside_near = 0u;
+    for (var i = 0u; i < 8u; i++) {
+        let clip = cull_view.clip_from_world * corner(world_from_local, chunk, i);
+        outside += vec4<u32>(vec4(clip.x < -clip.w, clip.x > clip.w, clip.y < -clip.w, clip.y > clip.w));
+        outside_near += u32(clip.z > clip.w);
+    }
+    return all(outside < vec4(8u)) && outside_near < 8u;
+}
+
+fn occluded(world_from_local: mat4x4<f32>, chunk: ChunkBounds) -> bool {
+    var uv_min = vec2(1.);
+    var uv_max = vec2(0.);
+    var nearest = 0.;
+    for (var i = 0u; i < 8u; i++) {
+        let clip = cull_view.previous_clip_from_world * corner(world_from_local, chunk, i);
+        if clip.w <= 0. {
+            // the chunk was around the camera
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        nearest = max(nearest, ndc.z);
+    }
+    uv_min = saturate(uv_min);
+    uv_max = saturate(uv_max);
+
+    // the mip where the chunk covers no more than 2x2 texels
+    let extent = (uv_max - uv_min) * vec2<f32>(cull_view.pyramid_size);
+    let mip = min(u32(ceil(log2(max(max(extent.x, extent.y), 1.)))), cull_view.mip_count - 1u);
+    let mip_size = max(cull_view.pyramid_size >> vec2(mip), vec2(1u));
+    let low = min(vec2<u32>(uv_min * vec2<f32>(mip_size)), mip_size - 1u);
+    let high = min(vec2<u32>(uv_max * vec2<f32>(mip_size)), mip_size - 1u);
+
+    var farthest = 1.;
+    for (var y = low.y; y <= high.y; y++) {
+        for (var x = low.x; x <= high.x; x++) {
+            farthest = min(farthest, textureLoad(depth_pyramid, vec2(x, y), i32(mip)).r);
+        }
+    }
+    // everything under the chunk was nearer than the nearest point of it
+    return nearest < farthest;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= cull_view.draw_count {
+        return;
+    }
+    let slot = draws[id.x].first_instance;
+    let world_from_local = chunks[slot];
+    let chunk = bounds[slot];
+    var visible = in_frustum(world_from_local, chunk);
+    if visible && cull_view.occlusion != 0u {
+        visible = !occluded(world_from_local, chunk);
+    }
+    draws[id.x].instance_count = select(0u, 1u, visible);
+}
@@ -0,0 +1,58 @@
+// builds the depth pyramid the chunks are culled against, each texel is the farthest
+// of the texels under it; reverse z so the smallest
+
+#ifdef DEPTH_INPUT
+#ifdef MULTISAMPLED
+@group(0) @binding(0) var input: texture_depth_multisampled_2d;
+#else
+@group(0) @binding(0) var input: texture_depth_2d;
+#endif
+#else
+@group(0) @binding(0) var input: texture_2d<f32>;
+#endif
+@group(0) @binding(1) var output: texture_storage_2d<r32float, write>;
+#ifdef DEPTH_INPUT
+// the part of the depth texture the view draws to, the origin in xy and the size in zw
+@group(0) @binding(2) var<uniform> viewport: vec4<u32>;
+#endif
+
+// the size of the part of the input the output is built from
+fn input_size() -> vec2<u32> {
+#ifdef DEPTH_INPUT
+    return viewport.zw;
+#else
+    return textureDimensions(input);
+#endif
+}
+
+// texels are relative to the viewport of the view for the depth texture
+fn load(texel: vec2<u32>) -> f32 {
+#ifdef DEPTH_INPUT
+    // the first sample of a multisampled depth texture is good enough to cull with
+    return textureLoad(input, viewport.xy + texel, 0);
+#else
+    return textureLoad(input, texel, 0).r;
+#endif
+}
+
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let output_size = textureDimensions(output);
+    if any(id.xy >= output_size) {
+        return;
+    }
+    let input_size = input_size();
+    // the last texel of an odd sized input also takes the row or column left over
+    let odd = (input_size & vec2(1u)) == vec2(1u);
+    let last = id.xy == output_size - 1u;
+    let extra = select(vec2(0u), vec2(1u), odd & last);
+
+    var farthest = 1.;
+    for (var y = 0u; y <= 1u + extra.y; y++) {
+        for (var x = 0u; x <= 1u + extra.x; x++) {
+            let texel = min(id.xy * 2u + vec2(x, y), input_size - 1u);
+            farthest = min(farthest, load(texel));
+        }
+    }
+    textureStore(output, id.xy, vec4(farthest));
+}
@@ -0,0 +1,398 @@
+#define_import_path phoxels::voxel_functions
+
+// decoding of the packed voxel vertex and the atlas lookup,
+// shared by the main pass and the prepass
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: u32,
+#ifdef BLOCK_EXTRA
+    @location(1) extra: u32,
+#endif
+};
+
+// the prepass view layout has globals at binding 1 and nothing at the
+// main pass binding, so it is declared here rather than imported
+#ifdef PREPASS_PIPELINE
+#import bevy_render::globals::Globals
+@group(0) @binding(1) var<uniform> globals: Globals;
+#else
+#import bevy_pbr::mesh_view_bindings::globals
+#endif
+
+struct FaceOverride {
+    block_a: u32,
+    block_b: u32,
+    block_c: u32,
+    block_d: u32,
+}
+
+@group(2) @binding(0) var<uniform> atlas_size: vec4<u32>;
+@group(2) @binding(1) var material_color_texture: texture_2d<f32>;
+@group(2) @binding(2) var material_color_sampler: sampler;
+@group(2) @binding(3) var<uniform> face_overrides: array<FaceOverride, 256 / 4>;
+
+// everything about a block other then its face overrides, see `BlockProperties`
+struct BlockProperties {
+    @align(16) state_stride: u32,
+    // roughness in bits 0..8, metallic in 8..16, bit 16 set when the block has a surface
+    surface: u32,
+    // a linear rgb colour in bits 0..24, the mode in 24..32; 0 none, 1 constant, 2 biome, 3 state
+    tint: u32,
+    animation_frames: u32,
+    frame_time: f32,
+    animation_stride: u32,
+    emissive_intensity: f32,
+    emissive_stride: u32,
+}
+
+@group(2) @binding(4) var<uniform> blocks: array<BlockProperties, 256>;
+// one layer per texture, used instead of the atlas with VOXEL_TEXTURE_ARRAY
+@group(2) @binding(6) var material_array_texture: texture_2d_array<f32>;
+@group(2) @binding(7) var material_array_sampler: sampler;
+
+@group(2) @binding(10) var tint_colormap: texture_2d<f32>;
+@group(2) @binding(11) var tint_colormap_sampler: sampler;
+
+// both laid out like the atlas
+@group(2) @binding(13) var normal_map_texture: texture_2d<f32>;
+@group(2) @binding(14) var normal_map_sampler: sampler;
+@group(2) @binding(15) var height_map_texture: texture_2d<f32>;
+@group(2) @binding(16) var height_map_sampler: sampler;
+// (depth scale, max layer count, 0, 0)
+@group(2) @binding(17) var<uniform> parallax: vec4<f32>;
+
+// get the entry for a block from a table that packs 4 blocks into each element
+fn block_entry(entries: FaceOverride, block: u32) -> u32 {
+    let index = block % 4;
+    if index == 0 {
+        return entries.block_a;
+    } else if index == 1 {
+        return entries.block_b;
+    } else if index == 2 {
+        return entries.block_c;
+    }
+    return entries.block_d;
+}
+
+fn vertex_block_type(vertex: Vertex) -> u32 {
+    return (vertex.position >> 18) & 255;
+}
+
+// the face the triangles starting at this vertex are on, `BlockFace` + 1 or 0 if they are not on the block grid
+fn vertex_face(vertex: Vertex) -> u32 {
+    return vertex.position >> 29;
+}
+
+// the extra data of the vertex, 0 for chunks without any
+fn vertex_extra(vertex: Vertex) -> u32 {
+#ifdef BLOCK_EXTRA
+    return vertex.extra;
+#else
+    return 0u;
+#endif
+}
+
+// the position of the vertex inside its chunk
+fn local_position(vertex: Vertex) -> vec3<f32> {
+    let x = vertex.position & 31;
+    let y = (vertex.position >> 5) & 31;
+    let z = (vertex.position >> 10) & 31;
+    var pos = vec3(f32(x), f32(y), f32(z));
+    // shaped blocks are not on the block grid
+    let extra = vertex_extra(vertex);
+    let sub_block = vec3(extra & 15, (extra >> 4) & 15, (extra >> 8) & 15);
+    pos += vec3<f32>(sub_block) / 16.;
+    return pos;
+}
+
+// the inverse scale of the mesh, so textures stay one block wide when chunks are scaled
+fn inverse_scale(world_from_local: mat4x4<f32>) -> vec3<f32> {
+    var scale = vec3<f32>(1.);
+    // 1. calculate the determinant of the affine matrix
+    // determinant = dot(z, cross(x, y))
+    let determinant = determinant(world_from_local);
+    // 2. x = length of the first column of the affine matrix
+    scale.x = 1. / length(world_from_local[0]);
+    // 3. is the determinant negative? if so, negate the x of the scale
+    if determinant < 0. {
+        scale.x = -scale.x;
+    }
+    // 4. y = length of the second column of the affine matrix
+    scale.y = 1. / length(world_from_local[1]);
+    // 5. z = length of the third column of the affine matrix
+    scale.z = 1. / length(world_from_local[2]);
+    return scale;
+}
+
+// the normal of a flat face from the screen space derivatives of its position
+fn face_normal(world_position: vec3<f32>) -> vec3<f32> {
+    return normalize(cross(dpdy(world_position), dpdx(world_position)));
+}
+
+// the flat shading of the simple lighting, so faces of a block can be told apart
+fn face_shade(world_normal: vec3<f32>) -> f32 {
+    var dp = 0.7;
+
+    if world_normal.y > 0.2 {
+        dp = 1.;
+    } else if world_normal.y < -0.2 {
+        dp = 0.5;
+    } else {
+        if world_normal.x > 0.2 {
+            dp += 0.05;
+        } else if world_normal.x < -0.2 {
+            dp -= 0.1;
+        }
+        if world_normal.z > 0.2 {
+            dp += 0.1;
+        } else if world_normal.z < -0.2 {
+            dp -= 0.05;
+        }
+    }
+    return dp;
+}
+
+// the offset of a face in the override table
+fn block_face(world_normal: vec3<f32>, extra: u32) -> u32 {
+    // back, left, right, top, bottom
+    var face: u32 = 10;
+    if world_normal.x > 0.5 {
+        face = 15;
+    } else if world_normal.x < -0.5 {
+        face = 10;
+    } else if world_normal.y > 0.5 {
+        face = 5;
+    } else if world_normal.y < -0.5 {
+        face = 0;
+    } else if world_normal.z > 0.5 {
+        face = 20;
+    } else if world_normal.z < -0.5 {
+        face = 25;
+    };
+    // rotated blocks tell us which face of the unrotated block this is
+    switch (extra >> 12) & 7 {
+        case 1u: { face = 5; } // up
+        case 2u: { face = 0; } // down
+        case 3u: { face = 25; } // north
+        case 4u: { face = 20; } // south
+        case 5u: { face = 15; } // east
+        case 6u: { face = 10; } // west
+        default: {}
+    }
+    return face;
+}
+
+// the index in the atlas of the texture for a face of a block
+fn block_texture(block_type: u32, face: u32, extra: u32) -> u32 {
+    let faceover = block_entry(face_overrides[block_type / 4], block_type);
+    let stride = (faceover >> face) & 31;
+    let state = (extra >> 16) & 255;
+    return block_type + stride + state * blocks[block_type].state_stride
+        + animation_frame(block_type) * blocks[block_type].animation_stride;
+}
+
+// the frame an animated block is on, 0 for blocks that are not animated
+fn animation_frame(block_type: u32) -> u32 {
+    let properties = blocks[block_type];
+    if properties.animation_frames < 2u {
+        return 0u;
+    }
+    return u32(globals.time / properties.frame_time) % properties.animation_frames;
+}
+
+// the uv in the atlas of a point on a face
+fn atlas_uv(texture: u32, world_position: vec3<f32>, world_normal: vec3<f32>, scale: vec3<f32>) -> vec2<f32> {
+    let x = texture % atlas_size.x;
+    let y = texture / atlas_size.y;
+    var uvx = f32(x) /  f32(atlas_size.x);
+    var uvy = (1.+f32(y)) /  f32(atlas_size.x);
+
+    let texture_step = 1. / vec4<f32>(atlas_size);
+
+    var axis: f32;
+    if abs(world_normal.y) < 0.5 {
+        axis = (world_position.y * scale.y) % 1;
+    } else {
+        axis = (world_position.z * scale.z) % 1;
+    };
+    if axis < 0 {
+        axis += 1;
+    }
+    uvy -= (axis * texture_step.y);
+
+    if abs(world_normal.x) < 0.5 {
+        axis = (world_position.x * scale.x) % 1;
+    } else {
+        axis = (world_position.z * scale.z) % 1;
+    };
+    if axis < 0 {
+        axis += 1;
+    }
+    uvx += axis * texture_step.x;
+    return vec2(uvx, uvy);
+}
+
+// the uv of a point on a face for a repeating texture, not wrapped to 0..1
+// so the derivatives stay smooth across block edges and mips are picked correctly
+fn layer_uv(world_position: vec3<f32>, world_normal: vec3<f32>, scale: vec3<f32>) -> vec2<f32> {
+    var v: f32;
+    if abs(world_normal.y) < 0.5 {
+        v = world_position.y * scale.y;
+    } else {
+        v = world_position.z * scale.z;
+    };
+    var u: f32;
+    if abs(world_normal.x) < 0.5 {
+        u = world_position.x * scale.x;
+    } else {
+        u = world_position.z * scale.z;
+    };
+    // the atlas has v going down the texture
+    return vec2(u, -v);
+}
+
+// the color of a point on a texture of the atlas or texture array
+fn sample_texture(texture: u32, world_position: vec3<f32>, world_normal: vec3<f32>, scale: vec3<f32>) -> vec4<f32> {
+#ifdef VOXEL_TEXTURE_ARRAY
+    let uv = layer_uv(world_position, world_normal, scale);
+    return textureSample(material_array_texture, material_array_sampler, uv, texture);
+#else
+    let uv = atlas_uv(texture, world_position, world_normal, scale);
+    return textureSample(material_color_texture, material_color_sampler, uv);
+#endif
+}
+
+// the unlit color of a point on a block
+fn sample_block(block_type: u32, extra: u32, world_position: vec3<f32>, world_normal: vec3<f32>, scale: vec3<f32>) -> vec4<f32> {
+    let face = block_face(world_normal, extra);
+    let texture = block_texture(block_type, face, extra);
+    return sample_texture(texture, world_position, world_normal, scale);
+}
+
+// does the whole block glow, so it should not be shaded
+fn fully_emissive(block_type: u32) -> bool {
+    let properties = blocks[block_type];
+    return properties.emissive_intensity > 0. && properties.emissive_stride == 0u;
+}
+
+// the light given off by a point on a block, black for blocks that are not emissive
+fn block_emission(block_type: u32, extra: u32, color: vec4<f32>, world_position: vec3<f32>, world_normal: vec3<f32>, scale: vec3<f32>) -> vec3<f32> {
+    let properties = blocks[block_type];
+    if properties.emissive_intensity <= 0. {
+        return vec3(0.);
+    }
+    if properties.emissive_stride == 0u {
+        return color.rgb * properties.emissive_intensity;
+    }
+    let face = block_face(world_normal, extra);
+    let texture = block_texture(block_type, face, extra) + properties.emissive_stride;
+    let glow = sample_texture(texture, world_position, world_normal, scale);
+    return glow.rgb * glow.a * properties.emissive_intensity;
+}
+
+// the colour a block is multiplied by
+fn block_tint(block_type: u32, extra: u32) -> vec4<f32> {
+    let tint = blocks[block_type].tint;
+    switch tint >> 24 {
+        case 1u: {
+            return vec4(vec3(f32(tint & 255), f32((tint >> 8) & 255), f32((tint >> 16) & 255)) / 255., 1.);
+        }
+        case 2u, 3u: {
+            let uv = vec2((f32(extra >> 24) + 0.5) / 256., 0.);
+            return vec4(textureSampleLevel(tint_colormap, tint_colormap_sampler, uv, 0.).rgb, 1.);
+        }
+        default: {
+            return vec4(1.);
+        }
+    }
+}
+
+// the direction along the texture of a face, in the space of the chunk, zero for faces off the block grid.
+// faces are in `BlockFace` order + 1; up, down, north, south, east, west
+fn face_tangent(face: u32) -> vec3<f32> {
+    switch face {
+        case 1u, 2u, 3u, 4u: { return vec3(1., 0., 0.); }
+        case 5u, 6u: { return vec3(0., 0., 1.); }
+        default: { return vec3(0.); }
+    }
+}
+
+// the direction up the texture of a face, in the space of the chunk, zero for faces off the block grid
+fn face_bitangent(face: u32) -> vec3<f32> {
+    switch face {
+        case 1u, 2u: { return vec3(0., 0., 1.); }
+        case 3u, 4u, 5u, 6u: { return vec3(0., 1., 0.); }
+        default: { return vec3(0.); }
+    }
+}
+
+// the direction along the texture of a face from its normal, matches `atlas_uv`
+fn normal_tangent(world_normal: vec3<f32>) -> vec3<f32> {
+    if abs(world_normal.x) < 0.5 {
+        return vec3(1., 0., 0.);
+    }
+    return vec3(0., 0., 1.);
+}
+
+// the direction up the texture of a face from its normal, matches `atlas_uv`
+fn normal_bitangent(world_normal: vec3<f32>) -> vec3<f32> {
+    if abs(world_normal.y) < 0.5 {
+        return vec3(0., 1., 0.);
+    }
+    return vec3(0., 0., 1.);
+}
+
+// the height of a point on a face from the height map, 1 is the surface.
+// The height and normal maps are atlases even with VOXEL_TEXTURE_ARRAY
+fn sample_height(texture: u32, world_position: vec3<f32>, world_normal: vec3<f32>, scale: vec3<f32>) -> f32 {
+    let uv = atlas_uv(texture, world_position, world_normal, scale);
+    // sampled in a loop so there are no derivatives to pick a mip with
+    return textureSampleLevel(height_map_texture, height_map_sampler, uv, 0.).r;
+}
+
+// the point on a face that is seen through the height map, with steep parallax mapping
+fn parallax_position(
+    block_type: u32,
+    extra: u32,
+    world_position: vec3<f32>,
+    world_normal: vec3<f32>,
+    tangent: vec3<f32>,
+    bitangent: vec3<f32>,
+    view_direction: vec3<f32>,
+    scale: vec3<f32>,
+) -> vec3<f32> {
+    let texture = block_texture(block_type, block_face(world_normal, extra), extra);
+    let v = vec3(dot(view_direction, tangent), dot(view_direction, bitangent), dot(view_direction, world_normal));
+    // fewer layers are needed when looking straight at the face
+    let layers = max(mix(parallax.y, parallax.y / 4., abs(v.z)), 1.);
+    let layer_depth = 1. / layers;
+    let step = -v.xy / max(v.z, 0.05) * parallax.x * layer_depth;
+
+    var offset = vec2(0.);
+    var depth = 0.;
+    var surface = 1. - sample_height(texture, world_position, world_normal, scale);
+    for (var i = 0u; i < u32(layers) && depth < surface; i++) {
+        offset += step;
+        depth += layer_depth;
+        let point = world_position + tangent * offset.x + bitangent * offset.y;
+        surface = 1. - sample_height(texture, point, world_normal, scale);
+    }
+    return world_position + tangent * offset.x + bitangent * offset.y;
+}
+
+// the normal of a point on a face bent by the normal map
+fn mapped_normal(
+    block_type: u32,
+    extra: u32,
+    world_position: vec3<f32>,
+    world_normal: vec3<f32>,
+    tangent: vec3<f32>,
+    bitangent: vec3<f32>,
+    scale: vec3<f32>,
+) -> vec3<f32> {
+    let texture = block_texture(block_type, block_face(world_normal, extra), extra);
+    let uv = atlas_uv(texture, world_position, world_normal, scale);
+    let n = textureSample(normal_map_texture, normal_map_sampler, uv).rgb * 2. - 1.;
+    return normalize(tangent * n.x + bitangent * n.y + world_normal * n.z);
+}
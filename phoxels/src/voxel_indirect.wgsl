@@ -0,0 +1,58 @@
+#import phoxels::voxel_functions::{
+    Vertex,
+    block_emission,
+    block_tint,
+    face_normal,
+    face_shade,
+    fully_emissive,
+    inverse_scale,
+    local_position,
+    sample_block,
+    vertex_block_type,
+    vertex_extra,
+}
+#import bevy_pbr::view_transformations::position_world_to_clip
+
+// the transform of every chunk, the instance index of a draw is the slot of its chunk
+@group(1) @binding(0) var<storage, read> chunks: array<mat4x4<f32>>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(2) @interpolate(flat) block_type: u32,
+    @location(3) @interpolate(flat) scale: vec3<f32>,
+    @location(4) @interpolate(flat) extra: u32,
+}
+
+const COLOR_MULTIPLIER: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 0.5);
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let world_from_local = chunks[vertex.instance_index];
+    out.block_type = vertex_block_type(vertex);
+    out.extra = vertex_extra(vertex);
+    out.scale = inverse_scale(world_from_local);
+    out.world_position = world_from_local * vec4(local_position(vertex), 1.);
+    out.position = position_world_to_clip(out.world_position.xyz);
+    return out;
+}
+
+// the simple lighting of voxel.wgsl
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let world_normal = face_normal(in.world_position.xyz);
+    let position = in.world_position.xyz;
+    var ts = sample_block(in.block_type, in.extra, position, world_normal, in.scale);
+    ts *= block_tint(in.block_type, in.extra);
+    let a = ts.a;
+    if a < 0.2 {
+        discard;
+    }
+    let emission = block_emission(in.block_type, in.extra, ts, position, world_normal, in.scale);
+    if fully_emissive(in.block_type) {
+        return vec4(emission, a);
+    }
+    ts *= face_shade(world_normal) * COLOR_MULTIPLIER;
+    return vec4(ts.rgb + emission, a);
+}
@@ -0,0 +1,98 @@
+// the depth, normal and motion vector prepass and shadow pass of voxel chunks
+
+#import phoxels::voxel_functions::{
+    Vertex,
+    face_normal,
+    inverse_scale,
+    local_position,
+    sample_block,
+    vertex_block_type,
+    vertex_extra,
+}
+#import bevy_pbr::{
+    mesh_functions,
+    mesh_view_bindings::view,
+    prepass_bindings,
+    view_transformations::position_world_to_clip,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(2) @interpolate(flat) block_type: u32,
+    @location(3) scale: vec3<f32>,
+    @location(4) @interpolate(flat) extra: u32,
+#ifdef MOTION_VECTOR_PREPASS
+    @location(5) previous_world_position: vec4<f32>,
+#endif
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    @location(6) unclipped_depth: f32,
+#endif
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    let pos = vec4<f32>(local_position(vertex), 1.0);
+    out.block_type = vertex_block_type(vertex);
+    out.extra = vertex_extra(vertex);
+    out.scale = inverse_scale(world_from_local);
+
+    out.world_position = mesh_functions::mesh_position_local_to_world(world_from_local, pos);
+    out.position = position_world_to_clip(out.world_position.xyz);
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    out.unclipped_depth = out.position.z;
+    out.position.z = min(out.position.z, 1.0); // Clamp depth to avoid clipping
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    let previous_world_from_local = mesh_functions::get_previous_world_from_local(vertex.instance_index);
+    out.previous_world_position = mesh_functions::mesh_position_local_to_world(previous_world_from_local, pos);
+#endif
+    return out;
+}
+
+#ifdef PREPASS_FRAGMENT
+struct FragmentOutput {
+#ifdef NORMAL_PREPASS
+    @location(0) normal: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(1) motion_vector: vec2<f32>,
+#endif
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    @builtin(frag_depth) frag_depth: f32,
+#endif
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    let world_normal = face_normal(in.world_position.xyz);
+
+    // cut out the same pixels as the main pass, like the gaps in leaves
+    let color = sample_block(in.block_type, in.extra, in.world_position.xyz, world_normal, in.scale);
+    if color.a < 0.2 {
+        discard;
+    }
+
+#ifdef NORMAL_PREPASS
+    out.normal = vec4(world_normal * 0.5 + vec3(0.5), 1.0);
+#endif
+
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    out.frag_depth = in.unclipped_depth;
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    let clip_position_t = view.unjittered_clip_from_world * in.world_position;
+    let clip_position = clip_position_t.xy / clip_position_t.w;
+    let previous_clip_position_t = prepass_bindings::previous_view_uniforms.clip_from_world * in.previous_world_position;
+    let previous_clip_position = previous_clip_position_t.xy / previous_clip_position_t.w;
+    out.motion_vector = (clip_position - previous_clip_position) * vec2(0.5, -0.5);
+#endif
+
+    return out;
+}
+#endif
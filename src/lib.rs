@@ -22,6 +22,11 @@ impl Plugin for GamePlugin {
         app.insert_resource(phoxels::prelude::GeneratorLimits {
             max_generating_chunks: 100,
             max_meshing_chunks: 100,
+            // spread mesh uploads over frames so loading the map doesn't hitch
+            extract: phoxels::prelude::ExtractLimit::Adaptive {
+                target_frame_time: std::time::Duration::from_millis(16),
+                bytes: Some(8 * 1024 * 1024),
+            },
         });
         app.add_plugins(phoxels::PhoxelsPlugin::<map::GeneratorDataType>::default());
     }
@@ -1,4 +1,4 @@
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
 use bevy::{
     asset::RenderAssetUsages,
@@ -9,10 +9,12 @@ use bevy::{
     text::cosmic_text::fontdb::Query,
 };
 use indexmap::IndexMap;
-use noise::{Fbm, MultiFractal, NoiseFn, SuperSimplex};
 use phoxels::core::{
-    BlockMeta, BlockOverride, BlockOverrides, PhoxelGenerator, PhoxelGeneratorData,
+    Biome, BiomeMap, BlockFace, BlockMeta, BlockOverride, BlockOverrides, BlockRotation, ChunkRng,
+    GenerationPipeline, HeightCurve, PhoxelGenerator, PhoxelGeneratorData, Structure,
+    VoxelLighting, WorldSeed,
 };
+pub use phoxels::prelude::ChunkId;
 
 pub type GeneratorDataType = ChunkId;
 
@@ -31,48 +33,82 @@ const GROUND_HIGHT: i32 = 8;
 
 const MAP_SIZE: i32 = 50;
 
+const WORLD_SEED: WorldSeed = WorldSeed(0x5eed);
+
 pub fn plugin(app: &mut App) {
     app.init_resource::<BlockDescriptor>();
     // .init_resource::<MapDescriptor>()
     // .init_resource::<MapData>();
     app.add_systems(Startup, spawn_world);
-    let map_descriptor = MapDescriptor::from_world(app.world_mut());
-    app.insert_resource(map_descriptor.clone());
-    app.insert_resource(phoxels::prelude::PhoxelGenerator::new(
-        move |id: GeneratorDataType| {
-            let mut chunk = phoxels::prelude::ChunkData::empty();
-            let map_descriptor = map_descriptor.read().unwrap();
-            // let (id, _) = id;
-            for x in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    let h = map_descriptor
-                        .get_height(x as i32 + id.x * CHUNK_SIZE, z as i32 + id.z * CHUNK_SIZE);
-                    for y in (id.y * CHUNK_SIZE)..(id.y + 1) * CHUNK_SIZE {
-                        if y > h {
-                            if x == 1 && z == 1 {
-                                chunk.set_block(
-                                    x as u32,
-                                    (y - id.y * CHUNK_SIZE) as u32,
-                                    z as u32,
-                                    BlockType::Furnuse,
-                                );
-                            }
-                            break;
-                        }
-                        let block = if y == h {
-                            BlockType::Grass
-                        } else if y + 3 > h {
-                            BlockType::Dirt
-                        } else {
-                            BlockType::Stone
-                        };
-                        chunk.set_block(x as u32, (y - id.y * CHUNK_SIZE) as u32, z as u32, block);
-                    }
-                }
+    app.insert_resource(WORLD_SEED);
+    let biomes = Arc::new(biomes(WORLD_SEED));
+    let terrain = biomes.clone();
+    app.insert_resource(phoxels::prelude::PhoxelGenerator::seeded(
+        move |id: GeneratorDataType, rng: &mut ChunkRng| {
+            let mut chunk = terrain.generate(id);
+            let origin = id.origin();
+            let h = terrain.column(origin.x + 1, origin.z + 1).height - origin.y;
+            if (-1..CHUNK_SIZE - 1).contains(&h) && rng.chance(0.25) {
+                // face the furnace towards the camera
+                chunk.set_block_rotated(
+                    1,
+                    (h + 1) as u32,
+                    1,
+                    BlockType::Furnuse,
+                    BlockRotation::facing(BlockFace::South),
+                );
             }
             chunk
         },
     ));
+    // trees are planted after the terrain of the chunks around them is made
+    // so they can grow over chunk borders, the chunks past the map are the edge of the world
+    let (min, max) = if MAP_SIZE == 0 {
+        (ChunkId::new(-1, 0, -1), ChunkId::new(-1, 0, -1))
+    } else {
+        (
+            ChunkId::new(-MAP_SIZE, 0, -MAP_SIZE),
+            ChunkId::new(MAP_SIZE, 0, MAP_SIZE),
+        )
+    };
+    app.insert_resource(
+        GenerationPipeline::new()
+            .with_bounds(min, max)
+            .with_pass(move |neighbourhood| biomes.decorate(neighbourhood)),
+    );
+}
+
+fn biomes(seed: WorldSeed) -> BiomeMap<BlockType> {
+    let mut tree = Structure::new();
+    for y in 3..=6 {
+        let r = if y > 4 { 1 } else { 2 };
+        for x in -r..=r {
+            for z in -r..=r {
+                tree.add_block(IVec3::new(x, y, z), BlockType::Leaves, default());
+            }
+        }
+    }
+    for y in 0..4 {
+        tree.add_block(IVec3::new(0, y, 0), BlockType::Log, default());
+    }
+
+    let plains = Biome::new("plains", BlockType::Stone)
+        .with_climate(0., 0.3)
+        .with_layers(BlockType::Grass, BlockType::Dirt, 2)
+        .with_height(HeightCurve::linear(
+            GROUND_HIGHT as f32,
+            GROUND_HIGHT as f32,
+        ))
+        .with_decoration(1. / 97., tree);
+    let desert = Biome::new("desert", BlockType::Stone)
+        .with_climate(0.6, -0.5)
+        .with_layers(BlockType::Sand, BlockType::Sand, 3)
+        .with_height(HeightCurve::linear(GROUND_HIGHT as f32 - 2., 3.));
+    let hills = Biome::new("hills", BlockType::Stone)
+        .with_climate(-0.5, -0.2)
+        .with_layers(BlockType::Cobblestone, BlockType::Stone, 0)
+        .with_height(HeightCurve::from_points([(-1., 6.), (0., 10.), (1., 15.)]));
+    BiomeMap::new(seed.noise_seed(), vec![plains, desert, hills])
 }
 
 // fn sort_gen_order(mut chunks: ResMut<phoxels::ChunkMesher>) {
@@ -99,34 +135,6 @@ pub fn plugin(app: &mut App) {
 //     })
 // }
 
-#[derive(Debug, Component, PartialEq, Eq, Hash, Clone, Copy, Deref, Default, Reflect)]
-#[component(immutable, on_insert = ChunkId::on_add)]
-#[require(Transform, Visibility)]
-pub struct ChunkId(IVec3);
-
-//Send + Sync + Clone + QueryData + FromReflect + GetTypeRegistration + Bundle + Reflect
-impl ChunkId {
-    pub fn new(x: i32, y: i32, z: i32) -> Self {
-        ChunkId(IVec3::new(x, y, z))
-    }
-
-    fn on_add(mut world: bevy::ecs::world::DeferredWorld, ctx: bevy::ecs::component::HookContext) {
-        let id = *world
-            .entity(ctx.entity)
-            .get::<ChunkId>()
-            .expect("onadd of ChunkId");
-        world
-            .entity_mut(ctx.entity)
-            .get_mut::<Transform>()
-            .expect("ChunkId Requires Transform")
-            .translation = id.to_vec3();
-    }
-
-    fn to_vec3(self) -> Vec3 {
-        self.0.as_vec3() * CHUNK_SIZE as f32
-    }
-}
-
 struct ChunkBlockIter {
     x: i32,
     y: i32,
@@ -179,7 +187,10 @@ fn spawn_world(
 ) {
     // map_descriptor.min_max_y();
     commands.spawn((
-        DirectionalLight::default(),
+        DirectionalLight {
+            shadows_enabled: true,
+            ..Default::default()
+        },
         Transform::from_translation(Vec3::ONE * 100.).looking_at(Vec3::NEG_Y * 100., Vec3::Y),
     ));
     commands.spawn((Mesh3d(
@@ -207,8 +218,6 @@ fn spawn_world(
     }
 }
 
-type GeneratorData = std::sync::Arc<RwLock<MapDescriptorInernal>>;
-
 #[derive(Resource)]
 pub struct BlockDescriptor {
     mesh: Handle<Mesh>,
@@ -226,6 +235,7 @@ impl FromWorld for BlockDescriptor {
         let mut material_with_override = CustomMaterial {
             base_color_texture: Some(texture.clone()),
             atlas_shape: UVec4::new(16, 16, 0, 0),
+            lighting: VoxelLighting::Pbr,
             ..Default::default()
         };
 
@@ -240,6 +250,9 @@ impl FromWorld for BlockDescriptor {
             .top(18);
         material_with_override.set_override(BlockType::Furnuse, override_data);
 
+        let override_data = BlockOverride::default().top(1).bottom(1);
+        material_with_override.set_override(BlockType::Log, override_data);
+
         let material = world
             .resource_mut::<Assets<CustomMaterial>>()
             .add(material_with_override);
@@ -261,66 +274,17 @@ impl BlockDescriptor {
     }
 }
 
-#[derive(Clone, Resource, Deref, DerefMut)]
-struct MapDescriptor(GeneratorData);
-
-struct MapDescriptorInernal {
-    noise: Fbm<SuperSimplex>,
-}
-
-impl MapDescriptorInernal {
-    fn get_height(&self, x: i32, z: i32) -> i32 {
-        let h = self.noise.get([x as f64, z as f64]) * GROUND_HIGHT as f64;
-        GROUND_HIGHT + h as i32
-    }
-
-    #[allow(dead_code)]
-    fn min_max_y(&self) {
-        let mut min = f64::MAX;
-        let mut max = f64::MIN;
-        for x in -1000..1000 {
-            for z in -1000..1000 {
-                let n = self.noise.get([x as f64, z as f64]);
-                min = n.min(min);
-                max = n.max(max);
-            }
-        }
-        for x in -1000..1000 {
-            for z in -1000..1000 {
-                let n = self.noise.get([x as f64 + 0.5, z as f64 - 0.5]);
-                min = n.min(min);
-                max = n.max(max);
-            }
-        }
-        println!("min: {min}\nmax: {max}");
-    }
-}
-
-impl FromWorld for MapDescriptor {
-    fn from_world(_: &mut World) -> Self {
-        let mut noise = Fbm::new(0);
-        noise = noise.set_frequency(0.005);
-        noise = noise.set_persistence(0.7);
-        MapDescriptor::new(noise)
-    }
-}
-
-impl MapDescriptor {
-    fn new(noise: Fbm<SuperSimplex>) -> MapDescriptor {
-        let mdi = MapDescriptorInernal { noise };
-        mdi.min_max_y();
-        MapDescriptor(GeneratorData::new(RwLock::new(mdi)))
-    }
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum BlockType {
     Air,
     Stone,
     Dirt,
     Cobblestone = 16,
+    Sand = 18,
+    Log = 20,
 
     Furnuse = 44,
+    Leaves = 52,
     Grass = 77,
 }
 
@@ -331,15 +295,24 @@ impl phoxels::core::Block for BlockType {
     fn is_solid(&self) -> bool {
         match self {
             BlockType::Air => false,
-            BlockType::Stone | BlockType::Cobblestone | BlockType::Dirt | BlockType::Grass => true,
-            BlockType::Furnuse => true,
+            BlockType::Stone
+            | BlockType::Cobblestone
+            | BlockType::Dirt
+            | BlockType::Grass
+            | BlockType::Sand => true,
+            BlockType::Furnuse | BlockType::Log | BlockType::Leaves => true,
         }
     }
     fn is_transparent(&self) -> bool {
         match self {
             BlockType::Air => true,
-            BlockType::Stone | BlockType::Cobblestone | BlockType::Dirt | BlockType::Grass => false,
-            BlockType::Furnuse => false,
+            BlockType::Stone
+            | BlockType::Cobblestone
+            | BlockType::Dirt
+            | BlockType::Grass
+            | BlockType::Sand => false,
+            BlockType::Furnuse | BlockType::Log => false,
+            BlockType::Leaves => true,
         }
     }
 }